@@ -0,0 +1,162 @@
+// Screen space ambient occlusion from a single sampled depth pre-pass. Positions and normals
+// are rebuilt in world space from depth, a cosine weighted hemisphere of samples is projected
+// back onto the depth buffer, and the noisy result is smoothed with a depth aware blur.
+struct Camera{
+    view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    inv_view_proj: mat4x4<f32>,
+}
+
+struct Settings{
+    // world space radius of the sampled hemisphere
+    radius: f32,
+    // exponent applied to the visibility, higher is darker
+    intensity: f32,
+    // depth difference below which a sample doesn't count as occluded
+    bias: f32,
+    sample_count: u32,
+}
+
+struct VertexOutput{
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var<uniform> settings: Settings;
+@group(1) @binding(1)
+var t_depth: texture_depth_2d;
+@group(1) @binding(2)
+var t_occlusion: texture_2d<f32>;
+
+let PI: f32 = 3.14159265359;
+let GOLDEN_ANGLE: f32 = 2.39996323;
+let BLUR_RADIUS: i32 = 4;
+// how quickly blur weights fall off with relative depth difference
+let BLUR_SHARPNESS: f32 = 40.0;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput{
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+fn load_depth(coords: vec2<i32>) -> f32 {
+    let size = textureDimensions(t_depth);
+    return textureLoad(t_depth, clamp(coords, vec2<i32>(0), size - 1), 0);
+}
+
+fn world_position(coords: vec2<i32>, depth: f32) -> vec3<f32> {
+    let uv = (vec2<f32>(coords) + 0.5) / vec2<f32>(textureDimensions(t_depth));
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = camera.inv_view_proj * ndc;
+    return world.xyz / world.w;
+}
+
+fn load_position(coords: vec2<i32>) -> vec3<f32> {
+    return world_position(coords, load_depth(coords));
+}
+
+// Distance from the eye, what the blur compares
+fn linear_depth(coords: vec2<i32>) -> f32 {
+    return distance(load_position(coords), camera.view_position.xyz);
+}
+
+// Interleaved gradient noise, rotates the sample pattern per pixel so the blur can hide it
+fn noise(coords: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(coords, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+@fragment
+fn fs_occlusion(in: VertexOutput) -> @location(0) vec4<f32>{
+    let coords = vec2<i32>(in.clip_position.xy);
+    let depth = load_depth(coords);
+    // nothing was drawn here
+    if (depth >= 1.0) {
+        return vec4<f32>(1.0);
+    }
+    let p = world_position(coords, depth);
+
+    // take the neighbour on the flatter side so normals don't bend across silhouettes
+    let right = load_position(coords + vec2<i32>(1, 0)) - p;
+    let left = p - load_position(coords - vec2<i32>(1, 0));
+    let down = load_position(coords + vec2<i32>(0, 1)) - p;
+    let up = p - load_position(coords - vec2<i32>(0, 1));
+    let dx = select(left, right, dot(right, right) < dot(left, left));
+    let dy = select(up, down, dot(down, down) < dot(up, up));
+    let to_eye = camera.view_position.xyz - p;
+    var n = normalize(cross(dx, dy));
+    if (dot(n, to_eye) < 0.0) {
+        n = -n;
+    }
+
+    let helper = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), abs(n.x) > 0.9);
+    let t = normalize(cross(helper, n));
+    let b = cross(n, t);
+    let rotation = noise(in.clip_position.xy) * 2.0 * PI;
+    let p_distance = length(to_eye);
+
+    var occlusion = 0.0;
+    let count = max(settings.sample_count, 1u);
+    for (var i = 0u; i < count; i = i + 1u) {
+        // golden angle spiral over a cosine weighted hemisphere, denser towards the point
+        let fraction = (f32(i) + 0.5) / f32(count);
+        let phi = f32(i) * GOLDEN_ANGLE + rotation;
+        let r = sqrt(fraction);
+        let direction = t * (r * cos(phi)) + b * (r * sin(phi)) + n * sqrt(1.0 - fraction);
+        let scale = mix(0.1, 1.0, fraction * fraction) * settings.radius;
+        let sample_position = p + direction * scale;
+
+        let clip = camera.view_proj * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xyz / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+            continue;
+        }
+        let sample_coords = vec2<i32>(uv * vec2<f32>(textureDimensions(t_depth)));
+        let scene_distance = distance(load_position(sample_coords), camera.view_position.xyz);
+        let sample_distance = distance(sample_position, camera.view_position.xyz);
+        // geometry far in front of the point doesn't occlude it
+        let range = smoothstep(0.0, 1.0, settings.radius / abs(p_distance - scene_distance));
+        if (scene_distance < sample_distance - settings.bias) {
+            occlusion = occlusion + range;
+        }
+    }
+
+    let visibility = pow(1.0 - occlusion / f32(count), settings.intensity);
+    return vec4<f32>(visibility, visibility, visibility, 1.0);
+}
+
+// Separable gaussian that ignores taps at a different depth, so edges stay sharp
+fn blur(coords: vec2<i32>, direction: vec2<i32>) -> vec4<f32> {
+    let center_depth = linear_depth(coords);
+    var total = 0.0;
+    var weight_sum = 0.0;
+    for (var i = -BLUR_RADIUS; i <= BLUR_RADIUS; i = i + 1) {
+        let tap = coords + direction * i;
+        let size = textureDimensions(t_occlusion);
+        let clamped = clamp(tap, vec2<i32>(0), size - 1);
+        let sigma = f32(BLUR_RADIUS) * 0.5;
+        let spatial = exp(-f32(i * i) / (2.0 * sigma * sigma));
+        let difference = abs(linear_depth(clamped) - center_depth) / max(center_depth, 0.0001);
+        let weight = spatial * exp(-difference * BLUR_SHARPNESS);
+        total = total + textureLoad(t_occlusion, clamped, 0).r * weight;
+        weight_sum = weight_sum + weight;
+    }
+    let visibility = total / weight_sum;
+    return vec4<f32>(visibility, visibility, visibility, 1.0);
+}
+
+@fragment
+fn fs_blur_horizontal(in: VertexOutput) -> @location(0) vec4<f32>{
+    return blur(vec2<i32>(in.clip_position.xy), vec2<i32>(1, 0));
+}
+
+@fragment
+fn fs_blur_vertical(in: VertexOutput) -> @location(0) vec4<f32>{
+    return blur(vec2<i32>(in.clip_position.xy), vec2<i32>(0, 1));
+}
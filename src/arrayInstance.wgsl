@@ -0,0 +1,63 @@
+// Animates the instance grid the way ArrayInstance::update does and writes each InstanceRaw
+// straight into the instance buffer. Keep it in step with ArrayInstance.
+struct Params{
+    time: f32,
+    width: u32,
+    depth: u32,
+    // size of an InstanceRaw in floats
+    stride: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+// InstanceRaw is packed without the padding a WGSL struct would get, so it's written float by float
+@group(0) @binding(1)
+var<storage, read_write> instances: array<f32>;
+
+fn write_vec3(offset: u32, value: vec3<f32>) {
+    instances[offset] = value.x;
+    instances[offset + 1u] = value.y;
+    instances[offset + 2u] = value.z;
+}
+
+fn write_vec4(offset: u32, value: vec4<f32>) {
+    write_vec3(offset, value.xyz);
+    instances[offset + 3u] = value.w;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.width * params.depth) {
+        return;
+    }
+    let x = f32(id.x % params.width);
+    let z = f32(id.x / params.width);
+
+    // rotation about y by z * time degrees, bobbing more towards +x
+    let angle = radians(z * params.time);
+    let c = cos(angle);
+    let s = sin(angle);
+    let position = vec3<f32>(
+        3.0 * x - 14.5,
+        sin(0.2 * params.time + x) * (1.0 + 2.0 * x / 9.0),
+        -2.5 * z + 14.5,
+    );
+
+    let base = id.x * params.stride;
+    // model
+    write_vec4(base, vec4<f32>(c, 0.0, -s, 0.0));
+    write_vec4(base + 4u, vec4<f32>(0.0, 1.0, 0.0, 0.0));
+    write_vec4(base + 8u, vec4<f32>(s, 0.0, c, 0.0));
+    write_vec4(base + 12u, vec4<f32>(position, 1.0));
+    // normal, the scale is 1 so the inverse transpose is the rotation itself
+    write_vec3(base + 16u, vec3<f32>(c, 0.0, -s));
+    write_vec3(base + 19u, vec3<f32>(0.0, 1.0, 0.0));
+    write_vec3(base + 22u, vec3<f32>(s, 0.0, c));
+    // tint
+    write_vec4(base + 25u, vec4<f32>(0.8 + 0.2 * x / 9.0, 0.9, 0.8 + 0.2 * z / 9.0, 1.0));
+    // material index and joint offset
+    instances[base + 29u] = bitcast<f32>(0u);
+    instances[base + 30u] = bitcast<f32>(0u);
+    // custom
+    write_vec4(base + 31u, vec4<f32>(0.0));
+}
@@ -0,0 +1,59 @@
+// Reduces the luminance histogram to its average, eases the previous average towards it
+// and derives the exposure the tonemapping pass applies. Clears the histogram for the next frame.
+struct Params{
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    pixel_count: f32,
+    // per second
+    adaptation_rate: f32,
+    key_value: f32,
+    compensation: f32,
+    // seconds since the last frame
+    delta: f32,
+}
+
+struct Exposure{
+    average_luminance: f32,
+    exposure: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(2)
+var<storage, read_write> exposure: Exposure;
+
+var<workgroup> weighted: array<f32, 256>;
+
+@compute @workgroup_size(256)
+fn cs_main(@builtin(local_invocation_index) index: u32) {
+    let count = atomicLoad(&histogram[index]);
+    weighted[index] = f32(count) * f32(index);
+    atomicStore(&histogram[index], 0u);
+    workgroupBarrier();
+
+    for (var stride = 128u; stride > 0u; stride = stride >> 1u) {
+        if (index < stride) {
+            weighted[index] = weighted[index] + weighted[index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if (index == 0u) {
+        // count is bin 0 here, the black pixels are left out of the average
+        let lit_pixels = max(params.pixel_count - f32(count), 1.0);
+        let average_bin = weighted[0] / lit_pixels - 1.0;
+        let luminance = exp2(average_bin / 254.0 * params.log_luminance_range + params.min_log_luminance);
+
+        // the first frame starts at the measured value instead of fading in. The fraction covered
+        // follows the time passed, so the speed doesn't depend on the frame rate.
+        var adapted = luminance;
+        if (exposure.average_luminance > 0.0) {
+            let fraction = 1.0 - exp(-params.delta * params.adaptation_rate);
+            adapted = exposure.average_luminance + (luminance - exposure.average_luminance) * fraction;
+        }
+        exposure.average_luminance = adapted;
+        exposure.exposure = params.key_value / adapted * exp2(params.compensation);
+    }
+}
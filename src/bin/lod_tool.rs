@@ -0,0 +1,311 @@
+// Builds LOD chains offline. Reads an OBJ mesh, simplifies it to each ratio with quadric edge
+// collapse and writes a .lod file for LodChain::from_file, on the CPU only.
+//
+//   lod_tool input.obj output.lod [ratio@min_screen_size ...]
+//
+// A ratio is the fraction of the input's triangles the level keeps, finest level first, screen
+// sizes are as in LodChain::make. Without levels it writes 1@0.5 0.5@0.25 0.25@0.1 0.1@0.
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::io::Write;
+
+#[path = "../graphics/lod_file.rs"]
+mod lod_file;
+#[path = "../graphics/simplify.rs"]
+mod simplify;
+
+use lod_file::{LodFile, LodFileLevel, LodVertex, MAGIC, VERSION};
+use simplify::simplify;
+
+const DEFAULT_LEVELS: [(f32, f32); 4] = [(1.0, 0.5), (0.5, 0.25), (0.25, 0.1), (0.1, 0.0)];
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (input, output, levels) = match args.as_slice() {
+        [input, output, levels @ ..] => (input, output, levels),
+        _ => bail!("usage: lod_tool input.obj output.lod [ratio@min_screen_size ...]"),
+    };
+    let levels = if levels.is_empty() {
+        DEFAULT_LEVELS.to_vec()
+    } else {
+        levels
+            .iter()
+            .map(|level| parse_level(level).with_context(|| format!("invalid level `{}`", level)))
+            .collect::<Result<_>>()?
+    };
+    if levels.windows(2).any(|pair| pair[1].0 > pair[0].0) {
+        bail!("ratios have to get smaller with each level");
+    }
+    // what LodChain::make checks when the file is loaded, the last level's size is ignored
+    if levels[..levels.len() - 1]
+        .windows(2)
+        .any(|pair| pair[1].1 >= pair[0].1)
+    {
+        bail!("LOD screen sizes have to get smaller with each level");
+    }
+
+    let (vertices, indices) = load_obj(input)?;
+    // the renderer's meshes take 16 bit indices
+    if vertices.len() > u16::MAX as usize + 1 {
+        bail!(
+            "{} has {} vertices, at most 65536 fit",
+            input,
+            vertices.len()
+        );
+    }
+    let positions: Vec<[f32; 3]> = vertices.iter().map(|vertex| vertex.position).collect();
+    let triangle_count = indices.len() / 3;
+    println!(
+        "{}: {} vertices, {} triangles",
+        input,
+        vertices.len(),
+        triangle_count
+    );
+
+    let mut file = LodFile {
+        vertices,
+        levels: Vec::with_capacity(levels.len()),
+    };
+    let mut current = indices;
+    for (level, &(ratio, min_screen_size)) in levels.iter().enumerate() {
+        // simplifying the level before instead of the full mesh nests the levels, and gets
+        // quicker as they get smaller
+        let target = (triangle_count as f32 * ratio) as usize * 3;
+        if target < current.len() {
+            current = simplify(&positions, &current, target);
+        }
+        println!(
+            "level {}: {} triangles, min screen size {}",
+            level,
+            current.len() / 3,
+            min_screen_size
+        );
+        file.levels.push(LodFileLevel {
+            min_screen_size,
+            indices: current.clone(),
+        });
+    }
+
+    write_lod_file(output, &file)?;
+    // reading it back checks the file the way the renderer will
+    if LodFile::load(output)? != file {
+        bail!("{} doesn't read back the same", output);
+    }
+    println!("wrote {}", output);
+    Ok(())
+}
+
+// ratio@min_screen_size
+fn parse_level(level: &str) -> Result<(f32, f32)> {
+    let (ratio, min_screen_size) = level
+        .split_once('@')
+        .context("expected ratio@min_screen_size")?;
+    let ratio: f32 = ratio.parse()?;
+    if !(ratio > 0.0 && ratio <= 1.0) {
+        bail!("the ratio has to be above 0 and at most 1");
+    }
+    Ok((ratio, min_screen_size.parse()?))
+}
+
+// The layout described in lod_file.rs
+fn write_lod_file(path: &str, file: &LodFile) -> Result<()> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    for value in [
+        VERSION,
+        file.vertices.len() as u32,
+        file.levels.len() as u32,
+    ] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for vertex in &file.vertices {
+        for value in vertex
+            .position
+            .iter()
+            .chain(&vertex.uv)
+            .chain(&vertex.normal)
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    for level in &file.levels {
+        bytes.extend_from_slice(&level.min_screen_size.to_le_bytes());
+        bytes.extend_from_slice(&(level.indices.len() as u32).to_le_bytes());
+        for index in &level.indices {
+            bytes.extend_from_slice(&index.to_le_bytes());
+        }
+    }
+    std::fs::File::create(path)
+        .and_then(|mut output| output.write_all(&bytes))
+        .with_context(|| format!("failed to write {}", path))
+}
+
+// Position, uv and normal indices of a face corner, from 0
+type Corner = (usize, Option<usize>, Option<usize>);
+
+// Faces are split into triangle fans, and a vertex is made for every distinct corner. Faces
+// without normals get smooth ones, averaged over the faces around each position.
+fn load_obj(path: &str) -> Result<(Vec<LodVertex>, Vec<u32>)> {
+    let source =
+        std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path))?;
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut normals = Vec::new();
+    let mut vertex_of: HashMap<Corner, u32> = HashMap::new();
+    let mut corners = Vec::new();
+    let mut indices = Vec::new();
+    for (number, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let mut words = line.split_whitespace();
+        let context = || format!("{} line {}", path, number + 1);
+        match words.next() {
+            Some("v") => positions.push(parse_floats::<3>(words).with_context(context)?),
+            Some("vt") => {
+                let [u, v] = parse_floats::<2>(words).with_context(context)?;
+                // OBJ puts v = 0 at the bottom of the image, wgpu at the top
+                uvs.push([u, 1.0 - v]);
+            }
+            Some("vn") => normals.push(parse_floats::<3>(words).with_context(context)?),
+            Some("f") => {
+                let face = words
+                    .map(|corner| {
+                        parse_corner(corner, positions.len(), uvs.len(), normals.len())
+                            .with_context(context)
+                    })
+                    .map(|corner| {
+                        corner.map(|corner| {
+                            *vertex_of.entry(corner).or_insert_with(|| {
+                                corners.push(corner);
+                                (corners.len() - 1) as u32
+                            })
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                if face.len() < 3 {
+                    bail!("{}: a face needs three corners", context());
+                }
+                for i in 1..face.len() - 1 {
+                    indices.extend_from_slice(&[face[0], face[i], face[i + 1]]);
+                }
+            }
+            // groups, materials and smoothing don't change the geometry
+            _ => {}
+        }
+    }
+    if indices.is_empty() {
+        bail!("{} has no faces", path);
+    }
+
+    let mut smooth_normals = vec![[0.0; 3]; positions.len()];
+    if corners.iter().any(|&(_, _, normal)| normal.is_none()) {
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| positions[corners[triangle[i] as usize].0]);
+            // area weighted
+            let normal = cross(sub(b, a), sub(c, a));
+            for &vertex in triangle {
+                let sum = &mut smooth_normals[corners[vertex as usize].0];
+                *sum = [sum[0] + normal[0], sum[1] + normal[1], sum[2] + normal[2]];
+            }
+        }
+    }
+    let vertices = corners
+        .iter()
+        .map(|&(position, uv, normal)| LodVertex {
+            position: positions[position],
+            uv: uv.map_or([0.0, 0.0], |uv| uvs[uv]),
+            normal: normalize(normal.map_or(smooth_normals[position], |normal| normals[normal])),
+        })
+        .collect();
+    Ok((vertices, indices))
+}
+
+fn parse_floats<'a, const N: usize>(mut words: impl Iterator<Item = &'a str>) -> Result<[f32; N]> {
+    let mut floats = [0.0; N];
+    for float in floats.iter_mut() {
+        *float = words.next().context("too few numbers")?.parse()?;
+    }
+    Ok(floats)
+}
+
+// position, position/uv, position//normal or position/uv/normal, from 1 or negative to count
+// back from the last one
+fn parse_corner(corner: &str, positions: usize, uvs: usize, normals: usize) -> Result<Corner> {
+    let mut parts = corner.split('/');
+    let mut index = |count: usize| -> Result<Option<usize>> {
+        let part = match parts.next() {
+            Some(part) if !part.is_empty() => part,
+            _ => return Ok(None),
+        };
+        let index: i64 = part.parse()?;
+        let resolved = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
+        };
+        if resolved < 0 || resolved >= count as i64 {
+            bail!("index {} out of range", index);
+        }
+        Ok(Some(resolved as usize))
+    };
+    let position = index(positions)?.context("a corner needs a position")?;
+    Ok((position, index(uvs)?, index(normals)?))
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let length = (a[0] * a[0] + a[1] * a[1] + a[2] * a[2]).sqrt();
+    if length > 0.0 {
+        a.map(|x| x / length)
+    } else {
+        [0.0, 1.0, 0.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_file_loads_back_the_same() {
+        let vertex = |position: [f32; 3], uv: [f32; 2]| LodVertex {
+            position,
+            uv,
+            normal: [0.0, 0.0, 1.0],
+        };
+        let file = LodFile {
+            vertices: vec![
+                vertex([0.0, 0.0, 0.0], [0.0, 1.0]),
+                vertex([1.0, 0.0, 0.0], [1.0, 1.0]),
+                vertex([1.0, 1.0, 0.0], [1.0, 0.0]),
+                vertex([0.0, 1.0, 0.0], [0.0, 0.0]),
+            ],
+            levels: vec![
+                LodFileLevel {
+                    min_screen_size: 0.25,
+                    indices: vec![0, 1, 2, 0, 2, 3],
+                },
+                LodFileLevel {
+                    min_screen_size: 0.0,
+                    indices: vec![0, 1, 2],
+                },
+            ],
+        };
+        let path = std::env::temp_dir().join(format!("lod_tool_test_{}.lod", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_lod_file(path, &file).unwrap();
+        let loaded = LodFile::load(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.unwrap(), file);
+    }
+}
@@ -0,0 +1,24 @@
+// Copies a texture onto a full screen triangle with linear filtering, used to build mip chains
+struct VertexOutput{
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coord: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput{
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.tex_coord = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+@group(0) @binding(0)
+var t_src: texture_2d<f32>;
+@group(0) @binding(1)
+var s_src: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>{
+    return textureSample(t_src, s_src, in.tex_coord);
+}
@@ -0,0 +1,126 @@
+// Physically based bloom: the frame is filtered down a mip chain with a 13 tap box, blurred
+// back up with a 3x3 tent, and the result is blended over the scene without a threshold
+struct Settings{
+    // tent radius in uv units of the height
+    filter_radius: f32,
+    // fraction of the composited colour that comes from the bloom chain
+    strength: f32,
+}
+
+struct VertexOutput{
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coord: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> settings: Settings;
+@group(0) @binding(1)
+var t_src: texture_2d<f32>;
+@group(0) @binding(2)
+var s_src: sampler;
+@group(0) @binding(3)
+var t_bloom: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput{
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.tex_coord = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+fn luminance(color: vec3<f32>) -> f32{
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// The 13 taps are grouped into five overlapping 2x2 boxes, the centre box counts for half
+struct Taps{
+    a: vec3<f32>, b: vec3<f32>, c: vec3<f32>,
+    d: vec3<f32>, e: vec3<f32>, f: vec3<f32>,
+    g: vec3<f32>, h: vec3<f32>, i: vec3<f32>,
+    j: vec3<f32>, k: vec3<f32>,
+    l: vec3<f32>, m: vec3<f32>,
+}
+
+fn sample_taps(uv: vec2<f32>) -> Taps{
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_src));
+    let x = texel.x;
+    let y = texel.y;
+    var taps: Taps;
+    taps.a = textureSample(t_src, s_src, uv + vec2<f32>(-2.0 * x, 2.0 * y)).rgb;
+    taps.b = textureSample(t_src, s_src, uv + vec2<f32>(0.0, 2.0 * y)).rgb;
+    taps.c = textureSample(t_src, s_src, uv + vec2<f32>(2.0 * x, 2.0 * y)).rgb;
+    taps.d = textureSample(t_src, s_src, uv + vec2<f32>(-2.0 * x, 0.0)).rgb;
+    taps.e = textureSample(t_src, s_src, uv).rgb;
+    taps.f = textureSample(t_src, s_src, uv + vec2<f32>(2.0 * x, 0.0)).rgb;
+    taps.g = textureSample(t_src, s_src, uv + vec2<f32>(-2.0 * x, -2.0 * y)).rgb;
+    taps.h = textureSample(t_src, s_src, uv + vec2<f32>(0.0, -2.0 * y)).rgb;
+    taps.i = textureSample(t_src, s_src, uv + vec2<f32>(2.0 * x, -2.0 * y)).rgb;
+    taps.j = textureSample(t_src, s_src, uv + vec2<f32>(-x, y)).rgb;
+    taps.k = textureSample(t_src, s_src, uv + vec2<f32>(x, y)).rgb;
+    taps.l = textureSample(t_src, s_src, uv + vec2<f32>(-x, -y)).rgb;
+    taps.m = textureSample(t_src, s_src, uv + vec2<f32>(x, -y)).rgb;
+    return taps;
+}
+
+// Weights each box by the inverse of its luminance so single very bright pixels don't
+// turn into flickering blobs. Only used for the first downsample.
+fn karis_weight(color: vec3<f32>) -> f32{
+    return 1.0 / (1.0 + luminance(color));
+}
+
+@fragment
+fn fs_downsample_first(in: VertexOutput) -> @location(0) vec4<f32>{
+    let t = sample_taps(in.tex_coord);
+    let box0 = (t.a + t.b + t.d + t.e) * 0.25;
+    let box1 = (t.b + t.c + t.e + t.f) * 0.25;
+    let box2 = (t.d + t.e + t.g + t.h) * 0.25;
+    let box3 = (t.e + t.f + t.h + t.i) * 0.25;
+    let box4 = (t.j + t.k + t.l + t.m) * 0.25;
+    let w0 = 0.125 * karis_weight(box0);
+    let w1 = 0.125 * karis_weight(box1);
+    let w2 = 0.125 * karis_weight(box2);
+    let w3 = 0.125 * karis_weight(box3);
+    let w4 = 0.5 * karis_weight(box4);
+    let color = (box0 * w0 + box1 * w1 + box2 * w2 + box3 * w3 + box4 * w4)
+        / (w0 + w1 + w2 + w3 + w4);
+    return vec4<f32>(max(color, vec3<f32>(0.0)), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32>{
+    let t = sample_taps(in.tex_coord);
+    let color = t.e * 0.125
+        + (t.a + t.c + t.g + t.i) * 0.03125
+        + (t.b + t.d + t.f + t.h) * 0.0625
+        + (t.j + t.k + t.l + t.m) * 0.125;
+    return vec4<f32>(max(color, vec3<f32>(0.0)), 1.0);
+}
+
+// Additively blended onto the next larger mip
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32>{
+    let size = vec2<f32>(textureDimensions(t_src));
+    let x = settings.filter_radius * size.y / size.x;
+    let y = settings.filter_radius;
+    let uv = in.tex_coord;
+    let a = textureSample(t_src, s_src, uv + vec2<f32>(-x, y)).rgb;
+    let b = textureSample(t_src, s_src, uv + vec2<f32>(0.0, y)).rgb;
+    let c = textureSample(t_src, s_src, uv + vec2<f32>(x, y)).rgb;
+    let d = textureSample(t_src, s_src, uv + vec2<f32>(-x, 0.0)).rgb;
+    let e = textureSample(t_src, s_src, uv).rgb;
+    let f = textureSample(t_src, s_src, uv + vec2<f32>(x, 0.0)).rgb;
+    let g = textureSample(t_src, s_src, uv + vec2<f32>(-x, -y)).rgb;
+    let h = textureSample(t_src, s_src, uv + vec2<f32>(0.0, -y)).rgb;
+    let i = textureSample(t_src, s_src, uv + vec2<f32>(x, -y)).rgb;
+    let color = (e * 4.0 + (b + d + f + h) * 2.0 + (a + c + g + i)) / 16.0;
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32>{
+    let scene = textureSample(t_src, s_src, in.tex_coord);
+    let bloom = textureSample(t_bloom, s_src, in.tex_coord).rgb;
+    return vec4<f32>(mix(scene.rgb, bloom, settings.strength), scene.a);
+}
@@ -0,0 +1,170 @@
+// Culls the instances of one view. Visible instances are copied into the output buffer from
+// the start of their draw on, and counted in the draw's indirect arguments.
+//
+// With occlusion culling it runs twice a frame. cs_main tests against the previous frame's
+// pyramid and flags what it hides, cs_late tests the flagged instances again against the
+// pyramid of the depth cs_main's instances were drawn into, so nothing that came into view
+// since the last frame is missing.
+struct Params{
+    // the view the pyramid was drawn from, the previous frame's for cs_main
+    occlusion_view_proj: mat4x4<f32>,
+    // pointing inwards: left, right, bottom, top, near, far
+    planes: array<vec4<f32>, 6>,
+    instance_count: u32,
+    // size of an InstanceRaw in floats
+    stride: u32,
+    // 1 to also test against the Hi-Z pyramid
+    occlusion: u32,
+    hi_z_levels: u32,
+}
+
+// Model space bounds of the draw's mesh
+struct Draw{
+    bounds_min: vec4<f32>,
+    bounds_max: vec4<f32>,
+    first_instance: u32,
+}
+
+// DrawIndexedIndirect
+struct DrawArguments{
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read> instances: array<f32>;
+@group(0) @binding(2)
+var<storage, read> draw_ids: array<u32>;
+@group(0) @binding(3)
+var<storage, read> draws: array<Draw>;
+@group(0) @binding(4)
+var<storage, read_write> visible: array<f32>;
+@group(0) @binding(5)
+var<storage, read_write> arguments: array<DrawArguments>;
+// farthest depth of each texel's footprint
+@group(0) @binding(6)
+var hi_z: texture_2d<f32>;
+// 1 for the instances cs_main found occluded, for cs_late to test again
+@group(0) @binding(7)
+var<storage, read_write> occluded: array<u32>;
+
+struct Bounds{
+    min: vec3<f32>,
+    max: vec3<f32>,
+}
+
+fn read_vec4(offset: u32) -> vec4<f32> {
+    return vec4<f32>(instances[offset], instances[offset + 1u], instances[offset + 2u], instances[offset + 3u]);
+}
+
+fn in_frustum(bounds_min: vec3<f32>, bounds_max: vec3<f32>) -> bool {
+    for (var i = 0; i < 6; i = i + 1) {
+        let plane = params.planes[i];
+        // the corner furthest along the normal
+        let corner = select(bounds_min, bounds_max, plane.xyz >= vec3<f32>(0.0));
+        if (dot(plane.xyz, corner) + plane.w < 0.0) {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn hi_z_depth(texel: vec2<i32>, level: i32) -> f32 {
+    let size = textureDimensions(hi_z, level);
+    return textureLoad(hi_z, clamp(texel, vec2<i32>(0), size - vec2<i32>(1)), level).r;
+}
+
+// Behind everything in the pyramid over the box's screen rectangle
+fn is_occluded(bounds_min: vec3<f32>, bounds_max: vec3<f32>) -> bool {
+    var rect_min = vec2<f32>(1.0);
+    var rect_max = vec2<f32>(0.0);
+    var nearest = 1.0;
+    for (var i = 0u; i < 8u; i = i + 1u) {
+        let corner = select(bounds_min, bounds_max, vec3<bool>((i & 1u) != 0u, (i & 2u) != 0u, (i & 4u) != 0u));
+        let clip = params.occlusion_view_proj * vec4<f32>(corner, 1.0);
+        // crosses the near plane, no rectangle to test
+        if (clip.w <= 0.0) {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        rect_min = min(rect_min, uv);
+        rect_max = max(rect_max, uv);
+        nearest = min(nearest, ndc.z);
+    }
+    rect_min = clamp(rect_min, vec2<f32>(0.0), vec2<f32>(1.0));
+    rect_max = clamp(rect_max, vec2<f32>(0.0), vec2<f32>(1.0));
+
+    // the level where the rectangle covers at most two texels each way
+    let extent = (rect_max - rect_min) * vec2<f32>(textureDimensions(hi_z, 0));
+    let level = min(i32(ceil(log2(max(max(extent.x, extent.y), 1.0)))), i32(params.hi_z_levels) - 1);
+    let size = vec2<f32>(textureDimensions(hi_z, level));
+    let low = vec2<i32>(rect_min * size);
+    let high = vec2<i32>(rect_max * size);
+    let farthest = max(
+        max(hi_z_depth(low, level), hi_z_depth(vec2<i32>(high.x, low.y), level)),
+        max(hi_z_depth(vec2<i32>(low.x, high.y), level), hi_z_depth(high, level)),
+    );
+    return nearest > farthest;
+}
+
+// World space box around the transformed mesh bounds
+fn world_bounds(base: u32, draw: Draw) -> Bounds {
+    let model = mat4x4<f32>(read_vec4(base), read_vec4(base + 4u), read_vec4(base + 8u), read_vec4(base + 12u));
+    let center = (model * vec4<f32>((draw.bounds_min.xyz + draw.bounds_max.xyz) * 0.5, 1.0)).xyz;
+    let half = (draw.bounds_max.xyz - draw.bounds_min.xyz) * 0.5;
+    let extent = abs(model[0].xyz) * half.x + abs(model[1].xyz) * half.y + abs(model[2].xyz) * half.z;
+    return Bounds(center - extent, center + extent);
+}
+
+fn append(base: u32, draw_id: u32, draw: Draw) {
+    let slot = atomicAdd(&arguments[draw_id].instance_count, 1u);
+    let destination = (draw.first_instance + slot) * params.stride;
+    for (var i = 0u; i < params.stride; i = i + 1u) {
+        visible[destination + i] = instances[base + i];
+    }
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.instance_count) {
+        return;
+    }
+    occluded[id.x] = 0u;
+    let base = id.x * params.stride;
+    let draw_id = draw_ids[id.x];
+    let draw = draws[draw_id];
+    let bounds = world_bounds(base, draw);
+
+    if (!in_frustum(bounds.min, bounds.max)) {
+        return;
+    }
+    if (params.occlusion != 0u && is_occluded(bounds.min, bounds.max)) {
+        occluded[id.x] = 1u;
+        return;
+    }
+    append(base, draw_id, draw);
+}
+
+@compute @workgroup_size(64)
+fn cs_late(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.instance_count) {
+        return;
+    }
+    if (occluded[id.x] == 0u) {
+        return;
+    }
+    let base = id.x * params.stride;
+    let draw_id = draw_ids[id.x];
+    let draw = draws[draw_id];
+    let bounds = world_bounds(base, draw);
+    if (is_occluded(bounds.min, bounds.max)) {
+        return;
+    }
+    append(base, draw_id, draw);
+}
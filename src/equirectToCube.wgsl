@@ -0,0 +1,51 @@
+// Projects an equirectangular HDR image onto the six faces of a cube map.
+// The source is Rgba32Float which isn't filterable, so bilinear filtering is done by hand.
+@group(0) @binding(0)
+var src: texture_2d<f32>;
+@group(0) @binding(1)
+var dst: texture_storage_2d_array<rgba16float, write>;
+
+let PI: f32 = 3.14159265359;
+
+// face order is +X, -X, +Y, -Y, +Z, -Z
+fn cube_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let u = uv.x * 2.0 - 1.0;
+    let v = uv.y * 2.0 - 1.0;
+    var dir: vec3<f32>;
+    switch face {
+        case 0u: { dir = vec3<f32>(1.0, -v, -u); }
+        case 1u: { dir = vec3<f32>(-1.0, -v, u); }
+        case 2u: { dir = vec3<f32>(u, 1.0, v); }
+        case 3u: { dir = vec3<f32>(u, -1.0, -v); }
+        case 4u: { dir = vec3<f32>(u, -v, 1.0); }
+        default: { dir = vec3<f32>(-u, -v, -1.0); }
+    }
+    return normalize(dir);
+}
+
+fn load_wrapped(texel: vec2<i32>, size: vec2<i32>) -> vec4<f32> {
+    let x = (texel.x % size.x + size.x) % size.x;
+    let y = clamp(texel.y, 0, size.y - 1);
+    return textureLoad(src, vec2<i32>(x, y), 0);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_main(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let dst_size = textureDimensions(dst);
+    if (i32(gid.x) >= dst_size.x || i32(gid.y) >= dst_size.y) {
+        return;
+    }
+
+    let uv = (vec2<f32>(gid.xy) + 0.5) / vec2<f32>(dst_size);
+    let dir = cube_direction(gid.z, uv);
+    let equirect_uv = vec2<f32>(atan2(dir.z, dir.x) / (2.0 * PI) + 0.5, acos(dir.y) / PI);
+
+    let src_size = textureDimensions(src, 0);
+    let coord = equirect_uv * vec2<f32>(src_size) - 0.5;
+    let base = vec2<i32>(floor(coord));
+    let t = fract(coord);
+    let top = mix(load_wrapped(base, src_size), load_wrapped(base + vec2<i32>(1, 0), src_size), t.x);
+    let bottom = mix(load_wrapped(base + vec2<i32>(0, 1), src_size), load_wrapped(base + vec2<i32>(1, 1), src_size), t.x);
+
+    textureStore(dst, vec2<i32>(gid.xy), i32(gid.z), vec4<f32>(mix(top, bottom, t.y).rgb, 1.0));
+}
@@ -0,0 +1,150 @@
+// Fast approximate anti-aliasing, after Lottes' FXAA 3.11 quality preset. Finds the local edge
+// direction from luma, walks along the edge to both of its ends and resamples the pixel
+// across the edge in proportion to how close it is to the nearer end.
+struct VertexOutput{
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coord: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var t_src: texture_2d<f32>;
+@group(0) @binding(1)
+var s_src: sampler;
+
+// contrast needed before a pixel counts as an edge, relative and absolute
+let EDGE_THRESHOLD: f32 = 0.125;
+let EDGE_THRESHOLD_MIN: f32 = 0.0312;
+let SUBPIXEL_QUALITY: f32 = 0.75;
+let SEARCH_STEPS: i32 = 12;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput{
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.tex_coord = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+// The frame is still HDR, so luma is compressed into [0, 1) before comparing
+fn luma(color: vec3<f32>) -> f32 {
+    let l = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    return sqrt(l / (1.0 + l));
+}
+
+fn luma_at(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(t_src, s_src, uv, 0.0).rgb);
+}
+
+// the walk takes larger strides the further it gets from the pixel
+fn step_scale(i: i32) -> f32 {
+    if (i < 5) {
+        return 1.0;
+    } else if (i == 5) {
+        return 1.5;
+    } else if (i < 10) {
+        return 2.0;
+    } else if (i == 10) {
+        return 4.0;
+    }
+    return 8.0;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>{
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_src));
+    let uv = in.tex_coord;
+    let center = textureSampleLevel(t_src, s_src, uv, 0.0);
+
+    let m = luma(center.rgb);
+    let n = luma_at(uv + vec2<f32>(0.0, -texel.y));
+    let s = luma_at(uv + vec2<f32>(0.0, texel.y));
+    let w = luma_at(uv + vec2<f32>(-texel.x, 0.0));
+    let e = luma_at(uv + vec2<f32>(texel.x, 0.0));
+    let luma_min = min(m, min(min(n, s), min(w, e)));
+    let luma_max = max(m, max(max(n, s), max(w, e)));
+    let range = luma_max - luma_min;
+    if (range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD)) {
+        return center;
+    }
+
+    let nw = luma_at(uv + vec2<f32>(-texel.x, -texel.y));
+    let ne = luma_at(uv + vec2<f32>(texel.x, -texel.y));
+    let sw = luma_at(uv + vec2<f32>(-texel.x, texel.y));
+    let se = luma_at(uv + vec2<f32>(texel.x, texel.y));
+
+    let edge_horizontal = abs(nw + ne - 2.0 * n) + 2.0 * abs(w + e - 2.0 * m) + abs(sw + se - 2.0 * s);
+    let edge_vertical = abs(nw + sw - 2.0 * w) + 2.0 * abs(n + s - 2.0 * m) + abs(ne + se - 2.0 * e);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // which side of the pixel the edge is on
+    let luma_1 = select(w, n, is_horizontal);
+    let luma_2 = select(e, s, is_horizontal);
+    let gradient_1 = luma_1 - m;
+    let gradient_2 = luma_2 - m;
+    let is_1_steepest = abs(gradient_1) >= abs(gradient_2);
+    let gradient_scaled = 0.25 * max(abs(gradient_1), abs(gradient_2));
+
+    var step_length = select(texel.x, texel.y, is_horizontal);
+    var luma_local_average = 0.5 * (luma_2 + m);
+    if (is_1_steepest) {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_1 + m);
+    }
+
+    // start half a pixel towards the edge and walk along it in both directions
+    var edge_uv = uv;
+    if (is_horizontal) {
+        edge_uv.y = edge_uv.y + step_length * 0.5;
+    } else {
+        edge_uv.x = edge_uv.x + step_length * 0.5;
+    }
+    let offset = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), is_horizontal);
+    var uv_1 = edge_uv - offset;
+    var uv_2 = edge_uv + offset;
+    var luma_end_1 = luma_at(uv_1) - luma_local_average;
+    var luma_end_2 = luma_at(uv_2) - luma_local_average;
+    var reached_1 = abs(luma_end_1) >= gradient_scaled;
+    var reached_2 = abs(luma_end_2) >= gradient_scaled;
+    for (var i = 1; i < SEARCH_STEPS; i = i + 1) {
+        if (reached_1 && reached_2) {
+            break;
+        }
+        if (!reached_1) {
+            uv_1 = uv_1 - offset * step_scale(i);
+            luma_end_1 = luma_at(uv_1) - luma_local_average;
+            reached_1 = abs(luma_end_1) >= gradient_scaled;
+        }
+        if (!reached_2) {
+            uv_2 = uv_2 + offset * step_scale(i);
+            luma_end_2 = luma_at(uv_2) - luma_local_average;
+            reached_2 = abs(luma_end_2) >= gradient_scaled;
+        }
+    }
+
+    let distance_1 = select(uv.y - uv_1.y, uv.x - uv_1.x, is_horizontal);
+    let distance_2 = select(uv_2.y - uv.y, uv_2.x - uv.x, is_horizontal);
+    let is_direction_1 = distance_1 < distance_2;
+    let distance_final = min(distance_1, distance_2);
+    let edge_length = distance_1 + distance_2;
+    let pixel_offset = 0.5 - distance_final / edge_length;
+
+    // only move if the nearer end agrees with which side of the edge the pixel is on
+    let is_center_smaller = m < luma_local_average;
+    let luma_end = select(luma_end_2, luma_end_1, is_direction_1);
+    var final_offset = select(0.0, pixel_offset, (luma_end < 0.0) != is_center_smaller);
+
+    // sub-pixel aliasing, thin lines and single pixels the edge walk misses
+    let luma_average = (2.0 * (n + s + w + e) + nw + ne + sw + se) / 12.0;
+    let subpixel_1 = clamp(abs(luma_average - m) / range, 0.0, 1.0);
+    let subpixel_2 = (-2.0 * subpixel_1 + 3.0) * subpixel_1 * subpixel_1;
+    final_offset = max(final_offset, subpixel_2 * subpixel_2 * SUBPIXEL_QUALITY);
+
+    var final_uv = uv;
+    if (is_horizontal) {
+        final_uv.y = final_uv.y + final_offset * step_length;
+    } else {
+        final_uv.x = final_uv.x + final_offset * step_length;
+    }
+    return textureSampleLevel(t_src, s_src, final_uv, 0.0);
+}
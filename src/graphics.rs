@@ -1,14 +1,67 @@
-use wgpu::util::DeviceExt;
+use std::rc::Rc;
 use winit::event::*;
 use winit::window::Window;
+mod ambient_occlusion;
+mod animation;
+mod array_animation;
+mod bcn;
+mod bloom;
+mod compressed_texture;
+mod culling;
+mod fxaa;
+mod gpu_culling;
+mod id_buffer;
+mod smaa;
+mod taa;
 mod texture;
-use texture::Texture;
+mod texture_cache;
+use texture::{SamplerOptions, Texture};
+use texture_cache::{ColorSpace, TextureCache};
 mod camera;
+mod environment;
 mod instance;
+mod light;
+mod lod;
+mod lod_file;
+mod material;
+mod mesh;
+mod particles;
+mod picking;
+mod post_process;
+mod scene;
+mod scene_graph;
+mod simplify;
+mod skin;
+mod skybox;
+mod tonemapping;
 mod vertex;
+use ambient_occlusion::AmbientOcclusion;
+use animation::{
+    AnimationClip, AnimationTarget, Animator, Interpolation, Keyframe, PlayMode, Track,
+};
+use array_animation::ArrayAnimation;
 use camera::Camera;
 use cgmath::Rotation3;
+use culling::{compute_world_bounds, Aabb, CulledInstances, CullingMode};
+use environment::EnvironmentMap;
+use gpu_culling::{CullMesh, CullPhase, GpuCulledInstances, GpuCulling, HiZPyramid};
+use id_buffer::{IdBuffer, IdHit, IdPick};
 use instance::*;
+use light::Light;
+use lod::{LodChain, LodSelector};
+use lod_file::LodFile;
+use material::{Material, MaterialTextures};
+use mesh::Mesh;
+use particles::{EmitterDescription, EmitterId, ParticleBlend, ParticleSystem};
+use picking::{PickMesh, PickMode, PickShape, Selection};
+use post_process::PostProcessStack;
+use scene::{
+    AntiAliasing, Background, InstanceAnimation, PostEffectKind, SceneDescription, ToneMapping,
+};
+use scene_graph::SceneGraph;
+use skin::{JointChannel, SkeletalClip, Skeleton, SkinId, SkinSet};
+use skybox::Skybox;
+use tonemapping::Tonemapper;
 use vertex::*;
 
 pub struct State {
@@ -16,6 +69,7 @@ pub struct State {
     depth_texture: Texture,
     shadow_texture: Texture,
     msaa_texture: Texture,
+    sample_count: u32,
 
     device: wgpu::Device,
     queue: wgpu::Queue,
@@ -27,25 +81,65 @@ pub struct State {
     shadow_render_pipline: wgpu::RenderPipeline,
     solid_pipeline: wgpu::RenderPipeline,
 
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
+    // the cube, or the scene's lod_mesh
+    instance_lods: LodChain,
+    instance_pick_mesh: PickMesh,
+    bar_lods: LodChain,
+    lod_selector: LodSelector,
     instance_set: InstanceSet,
+    spawned_instances: Vec<InstanceHandle>,
+    selection: Selection,
+    pick_mode: PickMode,
+    id_buffer: IdBuffer,
+    // in pixels from the top left, for picking
+    cursor_position: cgmath::Point2<f32>,
+    modifiers: ModifiersState,
+    scene_graph: SceneGraph,
+    animator: Animator,
+    // played on every spawned cube
+    spawn_clip: Rc<AnimationClip>,
+    last_update: std::time::Instant,
+    // the instance grid when it's animated on the GPU, otherwise it's in the instance set
+    array_animation: Option<ArrayAnimation>,
+    skins: SkinSet,
+    particles: ParticleSystem,
+    // what the camera and the light can see, with culling off every pass draws everything
+    culling: CullingMode,
+    camera_instances: CulledInstances,
+    shadow_instances: CulledInstances,
+    gpu_culling: GpuCulling,
+    gpu_camera_instances: GpuCulledInstances,
+    gpu_shadow_instances: GpuCulledInstances,
+    // built from the depth pre-pass, occlusion culling needs it from the frame before
+    hi_z: HiZPyramid,
+    hi_z_ready: bool,
+    spark_emitter: EmitterId,
+    // its two clips are blended back and forth
+    blended_skin: SkinId,
     camera: Camera,
     light_camera: Camera,
     light_moving_direction: f32,
-    index_len: usize,
+    light: Light,
+    material: Material,
+    environment: EnvironmentMap,
+    skybox: Option<Skybox>,
+    post_process: PostProcessStack,
+    tonemapper: Tonemapper,
+    ambient_occlusion: AmbientOcclusion,
 
-    diffuse_bind_group: wgpu::BindGroup,
+    depth_bind_group_layout: wgpu::BindGroupLayout,
+    light_bind_group_layout: wgpu::BindGroupLayout,
     camera_bind_group: wgpu::BindGroup,
     camera_light_bind_group: wgpu::BindGroup,
     depth_bind_group: wgpu::BindGroup,
     light_bind_group: wgpu::BindGroup,
+    light_uniform_bind_group: wgpu::BindGroup,
 
     time: f32,
 }
 
 impl State {
-    const SAMPLE_COUNT: u32 = 4;
+    const SCENE_PATH: &'static str = "scene.txt";
 
     // Creating some of the wgpu types requires async code
     pub async fn new(window: &Window) -> Self {
@@ -64,10 +158,27 @@ impl State {
             .await
             .unwrap();
 
+        // Scene Description
+        let scene = if std::path::Path::new(Self::SCENE_PATH).exists() {
+            SceneDescription::load(Self::SCENE_PATH).unwrap_or_else(|e| {
+                eprintln!("{:?}, using the default scene", e);
+                SceneDescription::default()
+            })
+        } else {
+            SceneDescription::default()
+        };
+
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    // Compressed textures are uploaded as is where the adapter can sample
+                    // them, otherwise they get decoded on the CPU
+                    features: adapter.features()
+                        & (wgpu::Features::TEXTURE_COMPRESSION_BC
+                            | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+                            | wgpu::Features::TEXTURE_COMPRESSION_ASTC_LDR
+                            // needed for sample counts other than 1 and 4
+                            | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES),
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     limits: if cfg!(target_arch = "wasm32") {
@@ -91,32 +202,59 @@ impl State {
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
         };
         surface.configure(&device, &config);
+        let sample_count = Self::choose_sample_count(&adapter, &device, scene.anti_aliasing);
         let depth_texture = Texture::create_depth_texture(
             &device,
             config.width,
             config.height,
             "depth_texture",
-            Self::SAMPLE_COUNT,
+            sample_count,
         );
         let shadow_texture = Texture::create_depth_texture(&device, 2048, 2048, "depth_texture", 1);
-        let msaa_texture = Texture::create_msaa_texture(&device, &config, "msaa_texture");
+        let msaa_texture = Texture::create_msaa_texture(
+            &device,
+            &config,
+            Tonemapper::HDR_FORMAT,
+            "msaa_texture",
+            sample_count,
+        );
 
         // Vertex / Index / Instance Buffer
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            usage: wgpu::BufferUsages::VERTEX,
-            contents: bytemuck::cast_slice(VERTICES),
-        });
-
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(INDICES),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+        let mut vertices = VERTICES.to_vec();
+        generate_tangents(&mut vertices, INDICES);
+        // the cube has too few triangles to simplify, its one level is drawn at any size
+        let cube_lods = || {
+            let positions: Vec<[f32; 3]> = vertices.iter().map(Vertex::get_position).collect();
+            (
+                LodChain::make(vec![(Mesh::make(&device, &vertices, INDICES, "Cube"), 0.0)])
+                    .unwrap(),
+                PickMesh::make(&positions, INDICES),
+            )
+        };
+        let (instance_lods, instance_pick_mesh) = match &scene.lod_mesh {
+            Some(path) => Self::load_lod_mesh(&device, path).unwrap_or_else(|e| {
+                eprintln!("{:?}, drawing cubes", e);
+                cube_lods()
+            }),
+            None => cube_lods(),
+        };
 
-        let mut cube_instances = (0..10)
-            .flat_map(|z| (0..10).map(move |x| ArrayInstance::make(x, z)))
-            .collect::<Vec<_>>();
+        let grid = scene.instance_grid;
+        let (mut instances, array_animation) = match grid.animation {
+            InstanceAnimation::Cpu => {
+                let instances = (0..grid.depth)
+                    .flat_map(|z| {
+                        (0..grid.width)
+                            .map(move |x| (ArrayInstance::make(x, z), RenderGroup::Textured))
+                    })
+                    .collect::<Vec<_>>();
+                (instances, None)
+            }
+            InstanceAnimation::Gpu => (
+                Vec::new(),
+                Some(ArrayAnimation::make(&device, grid.width, grid.depth)),
+            ),
+        };
 
         let floor_instance = Instance::make(
             (0.0, -10.0, 0.0).into(),
@@ -124,14 +262,260 @@ impl State {
             (30.0, 1.0, 30.0).into(),
         );
 
-        cube_instances.push(floor_instance);
-        let mut instance_set = InstanceSet::make(cube_instances);
+        instances.push((floor_instance, RenderGroup::Solid));
+        let mut instance_set = InstanceSet::make(instances);
+
+        // An arm standing on the floor, each joint carries the segments above it
+        let mut scene_graph = SceneGraph::make();
+        let base = scene_graph.add_node(
+            None,
+            Transform {
+                position: (20.0, -8.0, 0.0).into(),
+                ..Default::default()
+            },
+        );
+        let shoulder = scene_graph.add_node(
+            Some(base),
+            Transform {
+                position: (0.0, 1.0, 0.0).into(),
+                ..Default::default()
+            },
+        );
+        let elbow = scene_graph.add_node(
+            Some(shoulder),
+            Transform {
+                position: (0.0, 3.0, 0.0).into(),
+                ..Default::default()
+            },
+        );
+        let no_rotation =
+            cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(0.0));
+        let base_segment =
+            Instance::make((0.0, 0.0, 0.0).into(), no_rotation, (1.0, 1.0, 1.0).into());
+        let handle = instance_set.insert(base_segment, RenderGroup::Textured);
+        scene_graph.attach(base, handle, &mut instance_set);
+        for joint in [shoulder, elbow] {
+            let segment =
+                Instance::make((0.0, 1.5, 0.0).into(), no_rotation, (0.4, 1.5, 0.4).into());
+            let handle = instance_set.insert(segment, RenderGroup::Textured);
+            scene_graph.attach(joint, handle, &mut instance_set);
+        }
+        scene_graph.update(&mut instance_set);
+
+        // Animations
+        let rotation_key = |time, yaw, roll| Keyframe {
+            time,
+            value: cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(yaw))
+                * cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(roll)),
+        };
+        let shoulder_clip = AnimationClip {
+            translation: None,
+            rotation: Some(Track::make(
+                vec![
+                    rotation_key(0.0, 0.0, 20.0),
+                    rotation_key(2.0, 90.0, -20.0),
+                    rotation_key(4.0, 180.0, 20.0),
+                    rotation_key(6.0, 270.0, -20.0),
+                    rotation_key(8.0, 360.0, 20.0),
+                ],
+                Interpolation::Cubic,
+            )),
+            scale: None,
+            mode: PlayMode::Loop,
+        };
+        let elbow_clip = AnimationClip {
+            translation: None,
+            rotation: Some(Track::make(
+                vec![rotation_key(0.0, 0.0, 10.0), rotation_key(1.5, 0.0, 80.0)],
+                Interpolation::Linear,
+            )),
+            scale: None,
+            mode: PlayMode::PingPong,
+        };
+        // pops in while ticking round a half turn in eighth turns, then holds
+        let spawn_clip = Rc::new(AnimationClip {
+            translation: None,
+            rotation: Some(Track::make(
+                (0..=4)
+                    .map(|i| rotation_key(0.5 * i as f32, 45.0 * i as f32, 0.0))
+                    .collect(),
+                Interpolation::Step,
+            )),
+            scale: Some(Track::make(
+                vec![
+                    Keyframe {
+                        time: 0.0,
+                        value: (0.2, 0.2, 0.2).into(),
+                    },
+                    Keyframe {
+                        time: 1.0,
+                        value: (1.2, 1.2, 1.2).into(),
+                    },
+                    Keyframe {
+                        time: 2.0,
+                        value: (1.0, 1.0, 1.0).into(),
+                    },
+                ],
+                Interpolation::Cubic,
+            )),
+            mode: PlayMode::Once,
+        });
+        let mut animator = Animator::make();
+        for (clip, joint) in [(shoulder_clip, shoulder), (elbow_clip, elbow)] {
+            animator.play(
+                Rc::new(clip),
+                AnimationTarget::Node(joint),
+                1.0,
+                &scene_graph,
+                &instance_set,
+            );
+        }
+
+        // Skinned bars, a chain of three joints that bends and twists
+        let (bar_vertices, bar_indices) = generate_skinned_bar(0.6, 4.5, 12, 3);
+        let bar_lods = LodChain::generate(
+            &device,
+            &bar_vertices,
+            &bar_indices,
+            0.6,
+            &[(0.5, 0.3), (0.2, 0.0)],
+            "Bar",
+        )
+        .unwrap();
+        let joint_rest = |parent, height| {
+            let rest = Transform {
+                position: (0.0, height, 0.0).into(),
+                ..Default::default()
+            };
+            (parent, rest)
+        };
+        let skeleton = Rc::new(
+            Skeleton::make_from_rest_pose(vec![
+                joint_rest(None, 0.0),
+                joint_rest(Some(0), 1.5),
+                joint_rest(Some(1), 1.5),
+            ])
+            .unwrap(),
+        );
+        // one keyframe a second
+        let joint_channel = |joint, axis, angles: &[f32], interpolation| JointChannel {
+            joint,
+            translation: None,
+            rotation: Some(Track::make(
+                angles
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &angle)| Keyframe {
+                        time: i as f32,
+                        value: cgmath::Quaternion::from_axis_angle(axis, cgmath::Deg(angle)),
+                    })
+                    .collect(),
+                interpolation,
+            )),
+            scale: None,
+        };
+        let bend_clip = Rc::new(SkeletalClip {
+            channels: vec![
+                joint_channel(
+                    1,
+                    cgmath::Vector3::unit_z(),
+                    &[-25.0, 25.0, -25.0],
+                    Interpolation::Cubic,
+                ),
+                joint_channel(
+                    2,
+                    cgmath::Vector3::unit_z(),
+                    &[-35.0, 35.0, -35.0],
+                    Interpolation::Cubic,
+                ),
+            ],
+            mode: PlayMode::Loop,
+        });
+        let twist_clip = Rc::new(SkeletalClip {
+            channels: vec![
+                joint_channel(
+                    1,
+                    cgmath::Vector3::unit_y(),
+                    &[0.0, 60.0],
+                    Interpolation::Linear,
+                ),
+                joint_channel(
+                    2,
+                    cgmath::Vector3::unit_y(),
+                    &[0.0, 60.0],
+                    Interpolation::Linear,
+                ),
+            ],
+            mode: PlayMode::PingPong,
+        });
+        // only bending, blended back and forth, only twisting
+        let mut skins = SkinSet::make();
+        let bar_skins: Vec<_> = (0..3)
+            .map(|i| {
+                let skin = skins.add(skeleton.clone());
+                skins.play(skin, bend_clip.clone());
+                skins.play_blended(skin, twist_clip.clone(), i as f32 * 0.5);
+                let bar = Instance::make_skinned(
+                    (-22.0, -9.0, -6.0 + 6.0 * i as f32).into(),
+                    no_rotation,
+                    (1.0, 1.0, 1.0).into(),
+                    skins.get_joint_offset(skin),
+                );
+                instance_set.insert(bar, RenderGroup::Skinned);
+                skin
+            })
+            .collect();
+        let blended_skin = bar_skins[1];
+        skins.create_buffer(&device);
+
         instance_set.create_buffer(&device);
 
         // Texture Buffer
-        let diffuse_bytes = include_bytes!("container.jpg");
-        let diffuse_texture =
-            Texture::from_bytes(&device, &queue, diffuse_bytes, "dogTexture").unwrap();
+        let mut texture_cache = TextureCache::make();
+        let sampler = SamplerOptions {
+            anisotropy: 16,
+            ..Default::default()
+        };
+        let mut load_map = |path: &Option<std::path::PathBuf>, color_space| {
+            path.as_ref().and_then(|path| {
+                texture_cache
+                    .load(&device, &queue, path, color_space, &sampler)
+                    .map_err(|e| eprintln!("{:?}, using the default map", e))
+                    .ok()
+            })
+        };
+        let description = &scene.material;
+        let textures = MaterialTextures {
+            albedo: load_map(&description.albedo_map, ColorSpace::Srgb),
+            metallic_roughness: load_map(&description.metallic_roughness_map, ColorSpace::Linear),
+            occlusion: load_map(&description.occlusion_map, ColorSpace::Linear),
+            emissive: load_map(&description.emissive_map, ColorSpace::Srgb),
+            normal: load_map(&description.normal_map, ColorSpace::Linear),
+        };
+        let albedo = match textures.albedo {
+            Some(albedo) => albedo,
+            None => texture_cache
+                .load_bytes(
+                    &device,
+                    &queue,
+                    "container.jpg",
+                    include_bytes!("container.jpg"),
+                    ColorSpace::Srgb,
+                    &sampler,
+                )
+                .unwrap(),
+        };
+        let mut material = Material::make(
+            &device,
+            &queue,
+            MaterialTextures {
+                albedo: Some(albedo),
+                ..textures
+            },
+            description.metallic,
+            description.roughness,
+        )
+        .unwrap();
 
         // Uniform Buffer
         let mut camera = Camera::make_perspective(
@@ -158,8 +542,20 @@ impl State {
         );
         light_camera.create_buffer(&device);
 
+        let mut light = Light::make((1.0, 1.0, 1.0).into(), 3.0);
+        light.create_buffer(&device, &light_camera);
+
+        // Image Based Lighting
+        let mut environment = EnvironmentMap::from_scene(&device, &queue, &scene.environment);
+
         // Bind Groups
-        let texture_bind_group_layout =
+        let material_bind_group_layout = Material::create_bind_group_layout(&device);
+        material.create_bind_group(&device, &material_bind_group_layout);
+
+        let environment_bind_group_layout = EnvironmentMap::create_bind_group_layout(&device);
+        environment.create_bind_group(&device, &environment_bind_group_layout);
+
+        let depth_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
@@ -168,56 +564,71 @@ impl State {
                         ty: wgpu::BindingType::Texture {
                             multisampled: false,
                             view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            sample_type: wgpu::TextureSampleType::Depth,
                         },
                         count: None,
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
                         visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
                         count: None,
                     },
+                    AmbientOcclusion::create_bind_group_layout_entry(
+                        2,
+                        wgpu::ShaderStages::FRAGMENT,
+                    ),
                 ],
-                label: Some("texture_bind_group_layout"),
+                label: Some("depth_bind_group_layout"),
             });
 
-        let depth_bind_group_layout =
+        let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Depth,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
                         },
                         count: None,
                     },
+                    // joint palette for skinning
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
                         count: None,
                     },
                 ],
-                label: Some("depth_bind_group_layout"),
+                label: Some("camera_bind_group_layout"),
             });
 
-        let camera_bind_group_layout =
+        let light_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
+                    AmbientOcclusion::create_bind_group_layout_entry(
+                        1,
+                        wgpu::ShaderStages::FRAGMENT,
+                    ),
+                ],
+                label: Some("light_bind_group_layout"),
             });
 
         let camera_light_bind_group_layout =
@@ -247,64 +658,64 @@ impl State {
                 label: Some("camera_light_bind_group_layout"),
             });
 
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
+        // Screen Space Ambient Occlusion
+        let ambient_occlusion = AmbientOcclusion::make(
+            &device,
+            &config,
+            &camera_bind_group_layout,
+            &scene.ambient_occlusion,
+        );
+
+        let depth_bind_group = Self::create_depth_bind_group(
+            &device,
+            &depth_bind_group_layout,
+            &shadow_texture,
+            &ambient_occlusion,
+        );
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                    resource: camera
+                        .get_view_projection_buffer()
+                        .unwrap()
+                        .as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(
-                        diffuse_texture.sampler.as_ref().unwrap(),
-                    ),
+                    resource: skins.get_buffer().unwrap().as_entire_binding(),
                 },
             ],
-            label: Some("diffuse_bind_group"),
+            label: Some("camera_bind_group"),
         });
 
-        let depth_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &depth_bind_group_layout,
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&shadow_texture.view),
+                    resource: light_camera
+                        .get_view_projection_buffer()
+                        .unwrap()
+                        .as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(
-                        shadow_texture.sampler.as_ref().unwrap(),
-                    ),
+                    resource: skins.get_buffer().unwrap().as_entire_binding(),
                 },
             ],
-            label: Some("depth_bind_group"),
-        });
-
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera
-                    .get_view_projection_buffer()
-                    .unwrap()
-                    .as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        });
-
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_camera
-                    .get_view_projection_buffer()
-                    .unwrap()
-                    .as_entire_binding(),
-            }],
             label: Some("light_bind_group"),
         });
 
+        let light_uniform_bind_group = Self::create_light_uniform_bind_group(
+            &device,
+            &light_bind_group_layout,
+            &light,
+            &ambient_occlusion,
+        );
+
         let camera_light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &camera_light_bind_group_layout,
             entries: &[
@@ -326,6 +737,78 @@ impl State {
             label: Some("camera_light_bind_group"),
         });
 
+        // Particles, a fountain of sparks circling the arm and a column of smoke
+        let mut particles = ParticleSystem::make(
+            &device,
+            &camera_bind_group_layout,
+            Tonemapper::HDR_FORMAT,
+            sample_count,
+        );
+        let spark_emitter = particles.add_emitter(
+            &device,
+            EmitterDescription {
+                position: (20.0, -9.5, 6.0).into(),
+                max_particles: 4096,
+                rate: 800.0,
+                lifetime: (0.6, 1.4),
+                direction: cgmath::Vector3::unit_y(),
+                cone_angle: 25.0,
+                speed: (6.0, 10.0),
+                gravity: (0.0, -9.8, 0.0).into(),
+                start_color: [8.0, 4.0, 1.2, 1.0],
+                end_color: [2.0, 0.3, 0.05, 0.0],
+                start_size: 0.12,
+                end_size: 0.04,
+                blend: ParticleBlend::Additive,
+            },
+        );
+        particles.add_emitter(
+            &device,
+            EmitterDescription {
+                position: (0.0, -9.5, -14.0).into(),
+                max_particles: 1024,
+                rate: 60.0,
+                lifetime: (4.0, 6.0),
+                direction: cgmath::Vector3::unit_y(),
+                cone_angle: 15.0,
+                speed: (1.0, 1.8),
+                gravity: (0.3, 0.2, 0.0).into(),
+                start_color: [0.35, 0.35, 0.35, 0.6],
+                end_color: [0.6, 0.6, 0.6, 0.0],
+                start_size: 1.0,
+                end_size: 4.0,
+                blend: ParticleBlend::Alpha,
+            },
+        );
+
+        // Background
+        let skybox = Skybox::make(
+            &device,
+            &queue,
+            &scene.background,
+            &environment,
+            &camera_bind_group_layout,
+            Tonemapper::HDR_FORMAT,
+            sample_count,
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("{:?}, drawing no skybox", e);
+            None
+        });
+        let bg_color = match scene.background {
+            Background::Color(color) => color,
+            _ => wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            },
+        };
+        let post_process =
+            PostProcessStack::make(&device, &config, &scene.post_process, scene.anti_aliasing);
+        let tonemapper =
+            Tonemapper::make(&device, &queue, &config, scene.tone_mapping, scene.exposure);
+
         // Root Signature
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader"),
@@ -348,7 +831,12 @@ impl State {
             layout: Some(
                 &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                     label: Some("ShadowRootSignature"),
-                    bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
+                    bind_group_layouts: &[
+                        &material_bind_group_layout,
+                        &camera_bind_group_layout,
+                        &light_bind_group_layout,
+                        &environment_bind_group_layout,
+                    ],
                     push_constant_ranges: &[],
                 }),
             ),
@@ -361,7 +849,7 @@ impl State {
                 module: &shader,
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
+                    format: Tonemapper::HDR_FORMAT,
                     blend: Some(wgpu::BlendState::REPLACE),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
@@ -383,7 +871,7 @@ impl State {
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: Self::SAMPLE_COUNT,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -399,6 +887,7 @@ impl State {
                     bind_group_layouts: &[
                         &camera_light_bind_group_layout,
                         &depth_bind_group_layout,
+                        &environment_bind_group_layout,
                     ],
                     push_constant_ranges: &[],
                 }),
@@ -412,7 +901,7 @@ impl State {
                 module: &solid_shader,
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
+                    format: Tonemapper::HDR_FORMAT,
                     blend: Some(wgpu::BlendState::REPLACE),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
@@ -434,7 +923,7 @@ impl State {
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: Self::SAMPLE_COUNT,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -482,39 +971,79 @@ impl State {
                 multiview: None,
             });
 
+        let gpu_culling = GpuCulling::make(&device);
+        let gpu_camera_instances = GpuCulledInstances::make(&device, "Camera Instance Buffer");
+        let gpu_shadow_instances = GpuCulledInstances::make(&device, "Shadow Instance Buffer");
+        let hi_z = HiZPyramid::make(&device, config.width, config.height);
+        let id_buffer = IdBuffer::make(
+            &device,
+            config.width,
+            config.height,
+            &camera_bind_group_layout,
+        );
+
         Self {
             surface,
             device,
             queue,
             config,
             size,
-            bg_color: wgpu::Color {
-                r: 0.1,
-                g: 0.2,
-                b: 0.3,
-                a: 1.0,
-            },
+            bg_color,
 
             diffuse_pipeline,
             shadow_render_pipline,
             solid_pipeline,
 
-            vertex_buffer,
-            index_buffer,
-            index_len: INDICES.len(),
+            instance_lods,
+            instance_pick_mesh,
+            selection: Selection::make(),
+            pick_mode: PickMode::Ray,
+            id_buffer,
+            cursor_position: cgmath::Point2::new(0.0, 0.0),
+            modifiers: ModifiersState::empty(),
+            bar_lods,
+            lod_selector: LodSelector::make(),
             instance_set,
+            spawned_instances: Vec::new(),
+            scene_graph,
+            animator,
+            spawn_clip,
+            last_update: std::time::Instant::now(),
+            array_animation,
+            skins,
+            particles,
+            culling: CullingMode::Gpu,
+            camera_instances: CulledInstances::make("Camera Instance Buffer"),
+            shadow_instances: CulledInstances::make("Shadow Instance Buffer"),
+            gpu_culling,
+            gpu_camera_instances,
+            gpu_shadow_instances,
+            hi_z,
+            hi_z_ready: false,
+            spark_emitter,
+            blended_skin,
 
             camera,
             light_camera,
             light_moving_direction: 1.0,
+            light,
+            material,
+            environment,
+            skybox,
+            post_process,
+            tonemapper,
+            ambient_occlusion,
 
             depth_texture,
             msaa_texture,
+            sample_count,
             shadow_texture,
 
-            diffuse_bind_group,
+            depth_bind_group_layout,
+            light_bind_group_layout,
             camera_bind_group,
             light_bind_group,
+            light_uniform_bind_group,
             camera_light_bind_group,
             depth_bind_group,
 
@@ -533,11 +1062,145 @@ impl State {
                 self.config.width,
                 self.config.height,
                 "depth_texture",
-                Self::SAMPLE_COUNT,
+                self.sample_count,
+            );
+            self.msaa_texture = Texture::create_msaa_texture(
+                &self.device,
+                &self.config,
+                Tonemapper::HDR_FORMAT,
+                "msaa_texture",
+                self.sample_count,
+            );
+            // the aspect has to follow the window for picking rays to line up with the cursor
+            self.camera.resize(self.config.width, self.config.height);
+            self.post_process.resize(&self.device, &self.config);
+            self.tonemapper.resize(&self.queue, &self.config);
+            self.ambient_occlusion.resize(&self.device, &self.config);
+            self.hi_z
+                .resize(&self.device, self.config.width, self.config.height);
+            self.hi_z_ready = false;
+            self.id_buffer
+                .resize(&self.device, self.config.width, self.config.height);
+            self.depth_bind_group = Self::create_depth_bind_group(
+                &self.device,
+                &self.depth_bind_group_layout,
+                &self.shadow_texture,
+                &self.ambient_occlusion,
+            );
+            self.light_uniform_bind_group = Self::create_light_uniform_bind_group(
+                &self.device,
+                &self.light_bind_group_layout,
+                &self.light,
+                &self.ambient_occlusion,
+            );
+        }
+    }
+
+    // The chain and its full level's triangles for picking
+    fn load_lod_mesh(
+        device: &wgpu::Device,
+        path: &std::path::Path,
+    ) -> anyhow::Result<(LodChain, PickMesh)> {
+        let file = LodFile::load(path)?;
+        let positions: Vec<[f32; 3]> = file.vertices.iter().map(|vertex| vertex.position).collect();
+        let pick_mesh = PickMesh::make(&positions, &file.levels[0].indices);
+        let lods = LodChain::from_file(device, &file, &path.display().to_string())?;
+        Ok((lods, pick_mesh))
+    }
+
+    // The post-process anti-aliasing modes work on a single sampled frame. For MSAA the
+    // requested count is lowered to 4, the one count every format that can be multisampled
+    // supports, or to 1 where the colour and depth formats can't be. The feature flags don't
+    // say which other counts an adapter has.
+    fn choose_sample_count(
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        anti_aliasing: AntiAliasing,
+    ) -> u32 {
+        let requested = match anti_aliasing {
+            AntiAliasing::Msaa(samples) => samples,
+            _ => return 1,
+        };
+        // without adapter specific features only what WebGPU guarantees for the format counts
+        let adapter_specific = device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        let supports = |format: wgpu::TextureFormat, flags| {
+            let features = if adapter_specific {
+                adapter.get_texture_format_features(format).flags
+            } else {
+                format.describe().guaranteed_format_features.flags
+            };
+            features.contains(flags)
+        };
+        let multisample = supports(
+            Tonemapper::HDR_FORMAT,
+            wgpu::TextureFormatFeatureFlags::MULTISAMPLE
+                | wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE,
+        ) && supports(
+            Texture::DEPTH_FORMAT,
+            wgpu::TextureFormatFeatureFlags::MULTISAMPLE,
+        );
+
+        let supported = if multisample && requested >= 4 { 4 } else { 1 };
+        if supported != requested {
+            eprintln!(
+                "{} samples are not supported, using {} instead",
+                requested, supported
             );
-            self.msaa_texture =
-                Texture::create_msaa_texture(&self.device, &self.config, "msaa_texture");
         }
+        supported
+    }
+
+    // Both lighting bind groups hold the ambient occlusion view, so they follow its size
+    fn create_depth_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        shadow_texture: &Texture,
+        ambient_occlusion: &AmbientOcclusion,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&shadow_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(
+                        shadow_texture.sampler.as_ref().unwrap(),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(ambient_occlusion.get_view()),
+                },
+            ],
+            label: Some("depth_bind_group"),
+        })
+    }
+
+    fn create_light_uniform_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        light: &Light,
+        ambient_occlusion: &AmbientOcclusion,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light.get_buffer().unwrap().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(ambient_occlusion.get_view()),
+                },
+            ],
+            label: Some("light_uniform_bind_group"),
+        })
     }
 
     pub fn resize_with_current_size(&mut self) {
@@ -570,6 +1233,74 @@ impl State {
                         let eye_position = &mut self.camera.eye;
                         eye_position.y += 0.5;
                         return true;
+                    } else if input.virtual_keycode == Some(VirtualKeyCode::T) {
+                        let tone_mapping = match self.tonemapper.get_tone_mapping() {
+                            ToneMapping::Reinhard => ToneMapping::Aces,
+                            ToneMapping::Aces => ToneMapping::AgX,
+                            ToneMapping::AgX => ToneMapping::Reinhard,
+                        };
+                        self.tonemapper.set_tone_mapping(&self.queue, tone_mapping);
+                        return true;
+                    } else if input.virtual_keycode == Some(VirtualKeyCode::O) {
+                        let enabled = self.ambient_occlusion.is_enabled();
+                        self.ambient_occlusion.set_enabled(!enabled);
+                        return true;
+                    } else if input.virtual_keycode == Some(VirtualKeyCode::N) {
+                        // spawn a cube in a row above the grid
+                        let x = self.spawned_instances.len() as f32;
+                        let cube = Instance::make(
+                            (3.0 * (x % 10.0) - 14.5, 8.0 + 3.0 * (x / 10.0).floor(), 0.0).into(),
+                            cgmath::Quaternion::from_axis_angle(
+                                cgmath::Vector3::unit_y(),
+                                cgmath::Deg(45.0),
+                            ),
+                            (1.0, 1.0, 1.0).into(),
+                        );
+                        let handle = self.instance_set.insert(cube, RenderGroup::Textured);
+                        self.animator.play(
+                            self.spawn_clip.clone(),
+                            AnimationTarget::Instance(handle),
+                            1.0,
+                            &self.scene_graph,
+                            &self.instance_set,
+                        );
+                        self.spawned_instances.push(handle);
+                        return true;
+                    } else if input.virtual_keycode == Some(VirtualKeyCode::Back) {
+                        if let Some(handle) = self.spawned_instances.pop() {
+                            self.instance_set.remove(handle);
+                        }
+                        return true;
+                    } else if input.virtual_keycode == Some(VirtualKeyCode::V) {
+                        // compare the GPU grid with the CPU path
+                        if let Some(array_animation) = &self.array_animation {
+                            if let Err(e) = array_animation.validate(&self.device, &self.queue) {
+                                eprintln!("{:?}", e);
+                            }
+                        }
+                        return true;
+                    } else if input.virtual_keycode == Some(VirtualKeyCode::C) {
+                        self.culling = match self.culling {
+                            CullingMode::Off => CullingMode::Cpu,
+                            CullingMode::Cpu => CullingMode::Gpu,
+                            CullingMode::Gpu => CullingMode::Off,
+                        };
+                        return true;
+                    } else if input.virtual_keycode == Some(VirtualKeyCode::L) {
+                        let enabled = self.lod_selector.is_enabled();
+                        self.lod_selector.set_enabled(!enabled);
+                        return true;
+                    } else if input.virtual_keycode == Some(VirtualKeyCode::P) {
+                        self.pick_mode = match self.pick_mode {
+                            PickMode::Ray => PickMode::IdBuffer,
+                            PickMode::IdBuffer => PickMode::Ray,
+                        };
+                        return true;
+                    } else if input.virtual_keycode == Some(VirtualKeyCode::B) {
+                        let enabled = self.post_process.is_enabled(PostEffectKind::Bloom);
+                        self.post_process
+                            .set_enabled(PostEffectKind::Bloom, !enabled);
+                        return true;
                     }
                 }
                 false
@@ -586,17 +1317,98 @@ impl State {
                 }
                 false
             }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = cgmath::Point2::new(position.x as f32, position.y as f32);
+                true
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                true
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.pick();
+                true
+            }
             _ => false,
         }
     }
 
+    // Model space bounds of a skinned bar in any pose, the joints bend it out of its bind pose
+    fn skinned_bounds(&self) -> Aabb {
+        self.bar_lods.get_bounds().expand(2.0)
+    }
+
+    // Selects the instance under the cursor, with shift held it's added to the selection. The
+    // ID buffer's answer is applied in a later update.
+    fn pick(&mut self) {
+        if self.pick_mode == PickMode::IdBuffer {
+            self.id_buffer
+                .request(self.cursor_position, self.modifiers.shift());
+            return;
+        }
+        let ray = self
+            .camera
+            .get_ray(self.cursor_position, self.config.width, self.config.height);
+        let hit = picking::pick(&ray, &self.instance_set, |group| match group {
+            // the joints bend the bar away from its triangles
+            RenderGroup::Skinned => PickShape::Bounds(self.skinned_bounds()),
+            RenderGroup::Textured | RenderGroup::Solid => {
+                PickShape::Triangles(&self.instance_pick_mesh)
+            }
+        });
+        self.selection.select(
+            &mut self.instance_set,
+            hit.map(|hit| hit.handle),
+            self.modifiers.shift(),
+        );
+    }
+
+    // Selects what the ID buffer found the same way a ray pick does. The grid can't be selected,
+    // so it's a miss, like an instance removed since the ids were drawn.
+    fn apply_id_pick(&mut self, pick: IdPick) {
+        let handle = match pick.hit {
+            Some(IdHit::Instance(handle)) if self.instance_set.contains(handle) => Some(handle),
+            Some(IdHit::Instance(_)) | Some(IdHit::Array(_)) | None => None,
+        };
+        self.selection
+            .select(&mut self.instance_set, handle, pick.extend);
+    }
+
     pub fn update(&mut self) {
+        if let Some(pick) = self.id_buffer.poll(&self.device) {
+            self.apply_id_pick(pick);
+        }
+        // TAA jitters the projection, everything else renders from the pixel centres
+        self.camera.set_jitter(cgmath::Vector2::new(0.0, 0.0));
+        self.post_process.update(&self.queue, &mut self.camera);
         self.camera.update(&self.queue);
         self.time += 0.5;
-        for instance in self.instance_set.set.iter_mut() {
-            instance.update(self.time);
+        self.instance_set.update(self.time);
+        if let Some(array_animation) = &mut self.array_animation {
+            array_animation.update(&self.queue, self.time);
         }
-        self.instance_set.update_buffer(&self.queue);
+
+        let now = std::time::Instant::now();
+        let delta = (now - self.last_update).as_secs_f32();
+        self.last_update = now;
+        self.animator
+            .update(delta, &mut self.scene_graph, &mut self.instance_set);
+        self.skins
+            .set_blend(self.blended_skin, 0.5 + 0.5 * (0.02 * self.time).sin());
+        self.skins.update(&self.queue, delta);
+        let angle = 0.01 * self.time;
+        self.particles.set_position(
+            self.spark_emitter,
+            (20.0 + 6.0 * angle.sin(), -9.5, 6.0 * angle.cos()).into(),
+        );
+        self.particles.update(&self.queue, delta, &self.camera);
+        self.tonemapper.update(&self.queue, delta);
+        self.scene_graph.update(&mut self.instance_set);
+        self.instance_set.update_buffer(&self.device, &self.queue);
 
         // update light
         {
@@ -608,7 +1420,190 @@ impl State {
             }
             *light_pos_x += self.light_moving_direction * 0.1;
             self.light_camera.update(&self.queue);
+            self.light.update(&self.queue, &self.light_camera);
+            if let Some(skybox) = &mut self.skybox {
+                skybox.update(&self.queue, &self.light_camera);
+            }
+        }
+
+        // the grid animated on the GPU isn't culled or given levels, it has its own instance buffer
+        let lod_chain = |group| match group {
+            RenderGroup::Skinned => &self.bar_lods,
+            RenderGroup::Textured | RenderGroup::Solid => &self.instance_lods,
+        };
+        let (skinned_bounds, instance_bounds) =
+            (self.skinned_bounds(), self.instance_lods.get_bounds());
+        let cull_bounds = |group| match group {
+            RenderGroup::Skinned => skinned_bounds,
+            RenderGroup::Textured | RenderGroup::Solid => instance_bounds,
+        };
+        let bounds = compute_world_bounds(&self.instance_set, cull_bounds);
+        self.lod_selector
+            .update(&self.instance_set, &bounds, &self.camera, lod_chain);
+        let levels = self.lod_selector.get_levels();
+        match self.culling {
+            CullingMode::Off => {}
+            CullingMode::Cpu => {
+                self.camera_instances.update(
+                    &self.device,
+                    &self.queue,
+                    &self.instance_set,
+                    &bounds,
+                    levels,
+                    &self.camera.get_frustum(),
+                );
+                self.shadow_instances.update(
+                    &self.device,
+                    &self.queue,
+                    &self.instance_set,
+                    &bounds,
+                    levels,
+                    &self.light_camera.get_frustum(),
+                );
+            }
+            CullingMode::Gpu => {
+                self.gpu_culling.update(
+                    &self.device,
+                    &self.queue,
+                    &self.instance_set,
+                    levels,
+                    |group, lod| CullMesh {
+                        bounds: cull_bounds(group),
+                        index_count: lod_chain(group).get_level(lod).get_index_len(),
+                    },
+                );
+                self.gpu_camera_instances.update(
+                    &self.device,
+                    &self.queue,
+                    &self.gpu_culling,
+                    &self.instance_set,
+                    self.camera.build_view_projection_matrix(),
+                    self.hi_z_ready.then_some(&self.hi_z),
+                );
+                self.gpu_shadow_instances.update(
+                    &self.device,
+                    &self.queue,
+                    &self.gpu_culling,
+                    &self.instance_set,
+                    self.light_camera.build_view_projection_matrix(),
+                    None,
+                );
+            }
+        }
+    }
+
+    fn get_mesh(&self, group: RenderGroup, lod: u32) -> &Mesh {
+        match group {
+            RenderGroup::Skinned => self.bar_lods.get_level(lod),
+            RenderGroup::Textured | RenderGroup::Solid => self.instance_lods.get_level(lod),
+        }
+    }
+
+    // The instances a pass can see, or all of them with culling off
+    fn get_instances<'a>(
+        &'a self,
+        culled: &'a CulledInstances,
+        gpu_culled: &'a GpuCulledInstances,
+    ) -> PassInstances<'a> {
+        match (self.culling, culled.get_buffer()) {
+            (CullingMode::Cpu, Some(buffer)) => PassInstances::Direct(buffer, culled.draw_ranges()),
+            (CullingMode::Gpu, _) => {
+                PassInstances::Indirect(gpu_culled, self.gpu_culling.draw_ranges(), CullPhase::Both)
+            }
+            _ => PassInstances::Direct(
+                self.instance_set.get_buffer().unwrap(),
+                self.instance_set.draw_ranges(),
+            ),
+        }
+    }
+
+    // Draws the instances with their meshes, for the depth only passes
+    fn draw_instances<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        instances: PassInstances<'a>,
+    ) {
+        instances.bind(render_pass);
+        for (draw_id, range) in instances.draw_ranges().iter().enumerate() {
+            let mesh = self.get_mesh(range.group, range.lod);
+            mesh.bind(render_pass);
+            instances.draw(render_pass, draw_id, range, mesh.get_index_len());
         }
+        if let Some(array_animation) = &self.array_animation {
+            if !instances.is_late() {
+                self.draw_array(render_pass, array_animation);
+            }
+        }
+    }
+
+    fn set_diffuse_pipeline<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.diffuse_pipeline);
+        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.light_uniform_bind_group, &[]);
+        render_pass.set_bind_group(3, self.environment.get_bind_group().unwrap(), &[]);
+    }
+
+    // The GPU animated grid, with whatever pipeline is set
+    fn draw_array<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        array_animation: &'a ArrayAnimation,
+    ) {
+        let mesh = self.instance_lods.get_level(0);
+        mesh.bind(render_pass);
+        render_pass.set_vertex_buffer(1, array_animation.get_buffer().slice(..));
+        render_pass.draw_indexed(0..mesh.get_index_len(), 0, 0..array_animation.count());
+    }
+
+    // Every instance of the set at its level, from the set's own buffer so the instance index is
+    // the buffer index, then the GPU animated grid
+    fn draw_ids<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(self.id_buffer.get_instance_pipeline());
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        if let Some(buffer) = self.instance_set.get_buffer() {
+            render_pass.set_vertex_buffer(1, buffer.slice(..));
+            let levels = self.lod_selector.get_levels();
+            let level = |index: u32| levels.get(index as usize).copied().unwrap_or(0);
+            for range in self.instance_set.draw_ranges() {
+                // one draw per run of instances at the same level
+                let mut start = range.instances.start;
+                while start < range.instances.end {
+                    let lod = level(start);
+                    let end = (start + 1..range.instances.end)
+                        .find(|&index| level(index) != lod)
+                        .unwrap_or(range.instances.end);
+                    let mesh = self.get_mesh(range.group, lod);
+                    mesh.bind(render_pass);
+                    render_pass.draw_indexed(0..mesh.get_index_len(), 0, start..end);
+                    start = end;
+                }
+            }
+        }
+        if let Some(array_animation) = &self.array_animation {
+            render_pass.set_pipeline(self.id_buffer.get_array_pipeline());
+            self.draw_array(render_pass, array_animation);
+        }
+    }
+
+    fn depth_pre_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        load: wgpu::LoadOp<f32>,
+        instances: PassInstances,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Pre-Pass"),
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: self.ambient_occlusion.get_depth_view(),
+                depth_ops: Some(wgpu::Operations { load, store: true }),
+                stencil_ops: None,
+            }),
+            color_attachments: &[],
+        });
+
+        render_pass.set_pipeline(&self.shadow_render_pipline);
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        self.draw_instances(&mut render_pass, instances);
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -622,6 +1617,17 @@ impl State {
                 label: Some("Render CL"),
             });
 
+        if let Some(array_animation) = &self.array_animation {
+            array_animation.compute(&mut encoder);
+        }
+        self.particles.compute(&mut encoder);
+        if self.culling == CullingMode::Gpu {
+            self.gpu_camera_instances
+                .compute(&mut encoder, &self.gpu_culling);
+            self.gpu_shadow_instances
+                .compute(&mut encoder, &self.gpu_culling);
+        }
+
         {
             // shadow Pass
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -637,17 +1643,56 @@ impl State {
                 color_attachments: &[],
             });
 
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_set.get_buffer().unwrap().slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
             render_pass.set_pipeline(&self.shadow_render_pipline);
             render_pass.set_bind_group(0, &self.light_bind_group, &[]);
-            render_pass.draw_indexed(
-                0..self.index_len as u32,
-                0,
-                0..self.instance_set.count() as _,
+            self.draw_instances(
+                &mut render_pass,
+                self.get_instances(&self.shadow_instances, &self.gpu_shadow_instances),
             );
         }
+        // occlusion culling needs the pre-pass whether the ambient occlusion is on or not
+        let occlusion_culling = self.culling == CullingMode::Gpu;
+        if self.ambient_occlusion.is_enabled() || occlusion_culling {
+            // depth pre-pass for the ambient occlusion, same as the shadow pass from the camera.
+            // The late culled instances aren't known before the pyramid is built from it.
+            self.depth_pre_pass(
+                &mut encoder,
+                wgpu::LoadOp::Clear(1.0),
+                self.get_instances(&self.camera_instances, &self.gpu_camera_instances)
+                    .with_phase(CullPhase::Early),
+            );
+        }
+        if occlusion_culling {
+            // for the late culling and the next frame's early culling
+            self.hi_z.build(
+                &self.device,
+                &mut encoder,
+                self.ambient_occlusion.get_depth_view(),
+                self.camera.build_view_projection_matrix(),
+            );
+            self.gpu_camera_instances
+                .compute_late(&mut encoder, &self.gpu_culling);
+            self.depth_pre_pass(
+                &mut encoder,
+                wgpu::LoadOp::Load,
+                PassInstances::Indirect(
+                    &self.gpu_camera_instances,
+                    self.gpu_culling.draw_ranges(),
+                    CullPhase::Late,
+                ),
+            );
+        }
+        self.hi_z_ready = occlusion_culling;
+        if self.id_buffer.is_pass_needed() {
+            {
+                let mut render_pass = self.id_buffer.begin_pass(&mut encoder);
+                self.draw_ids(&mut render_pass);
+            }
+            self.id_buffer
+                .copy(&mut encoder, self.instance_set.handles());
+        }
+        self.ambient_occlusion
+            .render(&mut encoder, &self.camera_bind_group);
         {
             // normal pass
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -662,13 +1707,13 @@ impl State {
                 }),
 
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: if Self::SAMPLE_COUNT > 1 {
+                    view: if self.sample_count > 1 {
                         &self.msaa_texture.view
                     } else {
-                        &surface_view
+                        self.post_process.get_view()
                     },
-                    resolve_target: if Self::SAMPLE_COUNT > 1 {
-                        Some(&surface_view)
+                    resolve_target: if self.sample_count > 1 {
+                        Some(self.post_process.get_view())
                     } else {
                         None
                     },
@@ -679,30 +1724,115 @@ impl State {
                 })],
             });
 
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_set.get_buffer().unwrap().slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.set_pipeline(&self.diffuse_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.draw_indexed(
-                0..self.index_len as u32,
-                0,
-                0..(self.instance_set.count() - 1) as _,
-            );
+            let instances = self.get_instances(&self.camera_instances, &self.gpu_camera_instances);
+            instances.bind(&mut render_pass);
+            let mut current_group = None;
+            for (draw_id, range) in instances.draw_ranges().iter().enumerate() {
+                if current_group != Some(range.group) {
+                    match range.group {
+                        RenderGroup::Textured | RenderGroup::Skinned => {
+                            self.set_diffuse_pipeline(&mut render_pass)
+                        }
+                        RenderGroup::Solid => {
+                            render_pass.set_pipeline(&self.solid_pipeline);
+                            render_pass.set_bind_group(0, &self.camera_light_bind_group, &[]);
+                            render_pass.set_bind_group(1, &self.depth_bind_group, &[]);
+                            render_pass.set_bind_group(
+                                2,
+                                self.environment.get_bind_group().unwrap(),
+                                &[],
+                            );
+                        }
+                    }
+                    current_group = Some(range.group);
+                }
+                // only one material so far, every material index draws with it
+                if range.group != RenderGroup::Solid {
+                    render_pass.set_bind_group(0, self.material.get_bind_group().unwrap(), &[]);
+                }
+                // the levels of a group come one after another, so the mesh changes with each range
+                let mesh = self.get_mesh(range.group, range.lod);
+                mesh.bind(&mut render_pass);
+                instances.draw(&mut render_pass, draw_id, range, mesh.get_index_len());
+            }
+            if let Some(array_animation) = &self.array_animation {
+                self.set_diffuse_pipeline(&mut render_pass);
+                render_pass.set_bind_group(0, self.material.get_bind_group().unwrap(), &[]);
+                self.draw_array(&mut render_pass, array_animation);
+            }
 
-            render_pass.set_pipeline(&self.solid_pipeline);
-            render_pass.set_bind_group(0, &self.camera_light_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.depth_bind_group, &[]);
-            render_pass.draw_indexed(
-                0..self.index_len as u32,
-                0,
-                (self.instance_set.count() - 1) as _..(self.instance_set.count()) as _,
-            );
+            if let Some(skybox) = &self.skybox {
+                skybox.render(&mut render_pass, &self.camera_bind_group);
+            }
+            // transparent, after everything opaque
+            self.particles
+                .render(&mut render_pass, &self.camera_bind_group);
         }
 
+        // hdr -> post-processing -> surface
+        let hdr_view =
+            self.post_process
+                .render(&self.device, &mut encoder, &self.depth_texture.view);
+        self.tonemapper
+            .render(&self.device, &mut encoder, hdr_view, &surface_view);
+
         self.queue.submit(std::iter::once(encoder.finish()));
+        self.id_buffer.map();
         output.present();
         Ok(())
     }
 }
+
+// The instances a pass draws, after whichever culling is on
+enum PassInstances<'a> {
+    Direct(&'a wgpu::Buffer, &'a [DrawRange]),
+    Indirect(&'a GpuCulledInstances, &'a [DrawRange], CullPhase),
+}
+
+impl<'a> PassInstances<'a> {
+    fn draw_ranges(&self) -> &'a [DrawRange] {
+        match self {
+            PassInstances::Direct(_, draw_ranges) | PassInstances::Indirect(_, draw_ranges, _) => {
+                draw_ranges
+            }
+        }
+    }
+
+    // Only some of the GPU culled instances, the others have no phases
+    fn with_phase(self, phase: CullPhase) -> Self {
+        match self {
+            PassInstances::Indirect(culled, draw_ranges, _) => {
+                PassInstances::Indirect(culled, draw_ranges, phase)
+            }
+            direct => direct,
+        }
+    }
+
+    // Drawn by the early passes already
+    fn is_late(&self) -> bool {
+        matches!(self, PassInstances::Indirect(_, _, CullPhase::Late))
+    }
+
+    fn bind(&self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if let PassInstances::Direct(buffer, _) = self {
+            render_pass.set_vertex_buffer(1, buffer.slice(..));
+        }
+    }
+
+    fn draw(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        draw_id: usize,
+        range: &DrawRange,
+        index_len: u32,
+    ) {
+        match self {
+            PassInstances::Direct(..) => {
+                render_pass.draw_indexed(0..index_len, 0, range.instances.clone())
+            }
+            PassInstances::Indirect(culled, _, phase) => {
+                culled.draw(render_pass, draw_id, range, *phase)
+            }
+        }
+    }
+}
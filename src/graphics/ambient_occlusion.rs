@@ -0,0 +1,307 @@
+use wgpu::util::DeviceExt;
+
+use super::scene::AmbientOcclusionDescription;
+use super::texture::Texture;
+
+// SSAO over a single sampled depth pre-pass, the scene depth buffer is multisampled and only
+// written while the main pass runs. The blurred visibility is read back by the lighting
+// shaders with textureLoad and multiplies the ambient term, see ambientOcclusion.wgsl.
+pub struct AmbientOcclusion {
+    enabled: bool,
+    depth_texture: Texture,
+    // holds the raw occlusion, then the final blurred visibility
+    occlusion_texture: Texture,
+    blur_texture: Texture,
+
+    settings_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    occlusion_bind_group: wgpu::BindGroup,
+    blur_horizontal_bind_group: wgpu::BindGroup,
+    blur_vertical_bind_group: wgpu::BindGroup,
+
+    occlusion_pipeline: wgpu::RenderPipeline,
+    blur_horizontal_pipeline: wgpu::RenderPipeline,
+    blur_vertical_pipeline: wgpu::RenderPipeline,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AmbientOcclusionSettings {
+    radius: f32,
+    intensity: f32,
+    bias: f32,
+    sample_count: u32,
+}
+
+impl AmbientOcclusion {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+    const SAMPLE_COUNT: u32 = 16;
+    const BIAS: f32 = 0.025;
+
+    pub fn make(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        description: &AmbientOcclusionDescription,
+    ) -> Self {
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ambient Occlusion Settings Buffer"),
+            contents: bytemuck::cast_slice(&[AmbientOcclusionSettings {
+                radius: description.radius,
+                intensity: description.intensity,
+                bias: Self::BIAS,
+                sample_count: Self::SAMPLE_COUNT,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                Self::create_bind_group_layout_entry(2, wgpu::ShaderStages::FRAGMENT),
+            ],
+            label: Some("ambient_occlusion_bind_group_layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Ambient Occlusion Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../ambientOcclusion.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("AmbientOcclusionRootSignature"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("AmbientOcclusionPSO"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(Self::FORMAT.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let occlusion_pipeline = create_pipeline("fs_occlusion");
+        let blur_horizontal_pipeline = create_pipeline("fs_blur_horizontal");
+        let blur_vertical_pipeline = create_pipeline("fs_blur_vertical");
+
+        let (depth_texture, occlusion_texture, blur_texture) =
+            Self::create_textures(device, config);
+        let (occlusion_bind_group, blur_horizontal_bind_group, blur_vertical_bind_group) =
+            Self::create_bind_groups(
+                device,
+                &bind_group_layout,
+                &settings_buffer,
+                &depth_texture,
+                &occlusion_texture,
+                &blur_texture,
+            );
+
+        Self {
+            enabled: description.enabled,
+            depth_texture,
+            occlusion_texture,
+            blur_texture,
+
+            settings_buffer,
+            bind_group_layout,
+            occlusion_bind_group,
+            blur_horizontal_bind_group,
+            blur_vertical_bind_group,
+
+            occlusion_pipeline,
+            blur_horizontal_pipeline,
+            blur_vertical_pipeline,
+        }
+    }
+
+    // Layout entry for the visibility texture in the lighting shaders' bind groups
+    pub fn create_bind_group_layout_entry(
+        binding: u32,
+        visibility: wgpu::ShaderStages,
+    ) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        }
+    }
+
+    // The pre-pass renders the scene depth into this view, with a single sample
+    pub fn get_depth_view(&self) -> &wgpu::TextureView {
+        &self.depth_texture.view
+    }
+
+    // Ambient visibility, 1 where nothing is occluded
+    pub fn get_view(&self) -> &wgpu::TextureView {
+        &self.occlusion_texture.view
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    // The views change, so bind groups holding `get_view()` have to be remade as well
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        (
+            self.depth_texture,
+            self.occlusion_texture,
+            self.blur_texture,
+        ) = Self::create_textures(device, config);
+        (
+            self.occlusion_bind_group,
+            self.blur_horizontal_bind_group,
+            self.blur_vertical_bind_group,
+        ) = Self::create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            &self.settings_buffer,
+            &self.depth_texture,
+            &self.occlusion_texture,
+            &self.blur_texture,
+        );
+    }
+
+    // Runs after the depth pre-pass. When disabled the visibility is cleared to 1.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, camera_bind_group: &wgpu::BindGroup) {
+        if !self.enabled {
+            Self::begin_pass(encoder, &self.occlusion_texture.view, wgpu::Color::WHITE);
+            return;
+        }
+        let passes = [
+            (
+                &self.occlusion_pipeline,
+                &self.occlusion_bind_group,
+                &self.occlusion_texture.view,
+            ),
+            (
+                &self.blur_horizontal_pipeline,
+                &self.blur_horizontal_bind_group,
+                &self.blur_texture.view,
+            ),
+            (
+                &self.blur_vertical_pipeline,
+                &self.blur_vertical_bind_group,
+                &self.occlusion_texture.view,
+            ),
+        ];
+        for (pipeline, bind_group, target) in passes {
+            let mut render_pass = Self::begin_pass(encoder, target, wgpu::Color::WHITE);
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_bind_group(1, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+
+    fn begin_pass<'a>(
+        encoder: &'a mut wgpu::CommandEncoder,
+        target: &'a wgpu::TextureView,
+        color: wgpu::Color,
+    ) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Ambient Occlusion Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(color),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        })
+    }
+
+    fn create_textures(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> (Texture, Texture, Texture) {
+        (
+            Texture::create_depth_texture(
+                device,
+                config.width,
+                config.height,
+                "ambient_occlusion_depth",
+                1,
+            ),
+            Texture::create_render_target(device, config, Self::FORMAT, "ambient_occlusion"),
+            Texture::create_render_target(device, config, Self::FORMAT, "ambient_occlusion_blur"),
+        )
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        settings_buffer: &wgpu::Buffer,
+        depth_texture: &Texture,
+        occlusion_texture: &Texture,
+        blur_texture: &Texture,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup, wgpu::BindGroup) {
+        // binding 2 is what the pass reads, it is never the texture being written
+        let create_bind_group = |source: &Texture| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: settings_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    },
+                ],
+                label: Some("ambient_occlusion_bind_group"),
+            })
+        };
+        (
+            create_bind_group(blur_texture),
+            create_bind_group(occlusion_texture),
+            create_bind_group(blur_texture),
+        )
+    }
+}
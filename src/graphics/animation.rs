@@ -0,0 +1,545 @@
+use std::rc::Rc;
+
+use cgmath::InnerSpace;
+
+use super::instance::{InstanceHandle, InstanceSet, Transform};
+use super::scene_graph::{NodeId, SceneGraph};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    // hold each keyframe until the next one
+    Step,
+    Linear,
+    // Catmull-Rom through the neighbouring keyframes
+    Cubic,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlayMode {
+    // stop on the last keyframe
+    Once,
+    Loop,
+    // forwards then backwards
+    PingPong,
+}
+
+pub trait Interpolate: Copy {
+    fn linear(a: Self, b: Self, t: f32) -> Self;
+    fn cubic(p0: Self, p1: Self, p2: Self, p3: Self, t: f32) -> Self;
+}
+
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+impl Interpolate for cgmath::Vector3<f32> {
+    fn linear(a: Self, b: Self, t: f32) -> Self {
+        a + (b - a) * t
+    }
+
+    fn cubic(p0: Self, p1: Self, p2: Self, p3: Self, t: f32) -> Self {
+        cgmath::Vector3::new(
+            catmull_rom(p0.x, p1.x, p2.x, p3.x, t),
+            catmull_rom(p0.y, p1.y, p2.y, p3.y, t),
+            catmull_rom(p0.z, p1.z, p2.z, p3.z, t),
+        )
+    }
+}
+
+impl Interpolate for cgmath::Quaternion<f32> {
+    fn linear(a: Self, b: Self, t: f32) -> Self {
+        a.slerp(b, t)
+    }
+
+    // The spline runs on the components, flipped into one hemisphere and normalized after
+    fn cubic(p0: Self, p1: Self, p2: Self, p3: Self, t: f32) -> Self {
+        let align = |q: Self| if p1.dot(q) < 0.0 { -q } else { q };
+        let (p0, p2, p3) = (align(p0), align(p2), align(p3));
+        cgmath::Quaternion::new(
+            catmull_rom(p0.s, p1.s, p2.s, p3.s, t),
+            catmull_rom(p0.v.x, p1.v.x, p2.v.x, p3.v.x, t),
+            catmull_rom(p0.v.y, p1.v.y, p2.v.y, p3.v.y, t),
+            catmull_rom(p0.v.z, p1.v.z, p2.v.z, p3.v.z, t),
+        )
+        .normalize()
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Keyframe<T> {
+    pub time: f32,
+    pub value: T,
+}
+
+pub struct Track<T> {
+    keyframes: Vec<Keyframe<T>>,
+    interpolation: Interpolation,
+}
+
+impl<T: Interpolate> Track<T> {
+    pub fn make(mut keyframes: Vec<Keyframe<T>>, interpolation: Interpolation) -> Self {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self {
+            keyframes,
+            interpolation,
+        }
+    }
+
+    pub fn get_duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    // Holds the first and last keyframe outside of the track
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let keyframes = &self.keyframes;
+        let last = keyframes.len().checked_sub(1)?;
+        // first keyframe after time
+        let next = keyframes.partition_point(|keyframe| keyframe.time <= time);
+        if next == 0 {
+            return Some(keyframes[0].value);
+        } else if next > last {
+            return Some(keyframes[last].value);
+        }
+
+        let current = next - 1;
+        let (from, to) = (&keyframes[current], &keyframes[next]);
+        let t = (time - from.time) / (to.time - from.time);
+        Some(match self.interpolation {
+            Interpolation::Step => from.value,
+            Interpolation::Linear => T::linear(from.value, to.value, t),
+            Interpolation::Cubic => T::cubic(
+                keyframes[current.saturating_sub(1)].value,
+                from.value,
+                to.value,
+                keyframes[(next + 1).min(last)].value,
+                t,
+            ),
+        })
+    }
+}
+
+// Maps the time since a clip started onto its keyframe times
+pub fn wrap_time(mode: PlayMode, time: f32, duration: f32) -> f32 {
+    if duration <= 0.0 {
+        return 0.0;
+    }
+    match mode {
+        PlayMode::Once => time.clamp(0.0, duration),
+        PlayMode::Loop => time.rem_euclid(duration),
+        PlayMode::PingPong => {
+            let time = time.rem_euclid(2.0 * duration);
+            if time > duration {
+                2.0 * duration - time
+            } else {
+                time
+            }
+        }
+    }
+}
+
+// Tracks for the parts of a transform, the parts without one are left as they were
+pub struct AnimationClip {
+    pub translation: Option<Track<cgmath::Vector3<f32>>>,
+    pub rotation: Option<Track<cgmath::Quaternion<f32>>>,
+    pub scale: Option<Track<cgmath::Vector3<f32>>>,
+    pub mode: PlayMode,
+}
+
+impl AnimationClip {
+    pub fn get_duration(&self) -> f32 {
+        let translation = self.translation.as_ref().map_or(0.0, Track::get_duration);
+        let rotation = self.rotation.as_ref().map_or(0.0, Track::get_duration);
+        let scale = self.scale.as_ref().map_or(0.0, Track::get_duration);
+        translation.max(rotation).max(scale)
+    }
+
+    pub fn sample(&self, time: f32, base: Transform) -> Transform {
+        let time = wrap_time(self.mode, time, self.get_duration());
+        Transform {
+            position: self
+                .translation
+                .as_ref()
+                .and_then(|track| track.sample(time))
+                .unwrap_or(base.position),
+            rotation: self
+                .rotation
+                .as_ref()
+                .and_then(|track| track.sample(time))
+                .unwrap_or(base.rotation),
+            scale: self
+                .scale
+                .as_ref()
+                .and_then(|track| track.sample(time))
+                .unwrap_or(base.scale),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AnimationTarget {
+    Node(NodeId),
+    Instance(InstanceHandle),
+}
+
+struct Player {
+    clip: Rc<AnimationClip>,
+    target: AnimationTarget,
+    // the target's transform when the clip started, for the parts the clip doesn't animate
+    base: Transform,
+    time: f32,
+    speed: f32,
+}
+
+impl Player {
+    // Only clips played once end, backwards they end at the start
+    fn is_finished(&self) -> bool {
+        self.clip.mode == PlayMode::Once
+            && if self.speed < 0.0 {
+                self.time <= 0.0
+            } else {
+                self.time >= self.clip.get_duration()
+            }
+    }
+}
+
+// Plays clips on scene nodes and instances. A clip can be shared by any number of targets.
+pub struct Animator {
+    players: Vec<Player>,
+}
+
+impl Animator {
+    pub fn make() -> Self {
+        Self {
+            players: Vec::new(),
+        }
+    }
+
+    // Replaces whatever the target was playing. Fails for instances that have no plain
+    // transform, see MatrixInstance::get_transform.
+    pub fn play(
+        &mut self,
+        clip: Rc<AnimationClip>,
+        target: AnimationTarget,
+        speed: f32,
+        scene_graph: &SceneGraph,
+        instances: &InstanceSet,
+    ) -> bool {
+        let base = match target {
+            AnimationTarget::Node(node) => Some(*scene_graph.get_local(node)),
+            AnimationTarget::Instance(handle) => instances
+                .get(handle)
+                .and_then(|instance| instance.get_transform()),
+        };
+        let base = match base {
+            Some(base) => base,
+            None => return false,
+        };
+        self.stop(target);
+        self.players.push(Player {
+            clip,
+            target,
+            base,
+            time: 0.0,
+            speed,
+        });
+        true
+    }
+
+    pub fn stop(&mut self, target: AnimationTarget) {
+        self.players.retain(|player| player.target != target);
+    }
+
+    // Advances every clip by delta seconds and writes the result into the targets. Players
+    // of removed instances are dropped, and clips played once after their final pose.
+    pub fn update(
+        &mut self,
+        delta: f32,
+        scene_graph: &mut SceneGraph,
+        instances: &mut InstanceSet,
+    ) {
+        self.players.retain_mut(|player| {
+            player.time += delta * player.speed;
+            let transform = player.clip.sample(player.time, player.base);
+            let alive = match player.target {
+                AnimationTarget::Node(node) => {
+                    scene_graph.set_local(node, transform);
+                    true
+                }
+                AnimationTarget::Instance(handle) => match instances.get_mut(handle) {
+                    Some(instance) => {
+                        instance.set_transform(transform);
+                        true
+                    }
+                    None => false,
+                },
+            };
+            alive && !player.is_finished()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::instance::{ArrayInstance, Instance, RenderGroup};
+    use cgmath::Rotation3;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    fn vector_track(values: &[f32], interpolation: Interpolation) -> Track<cgmath::Vector3<f32>> {
+        Track::make(
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, &x)| Keyframe {
+                    time: i as f32,
+                    value: (x, 0.0, 0.0).into(),
+                })
+                .collect(),
+            interpolation,
+        )
+    }
+
+    fn yaw(degrees: f32) -> cgmath::Quaternion<f32> {
+        cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(degrees))
+    }
+
+    fn rotation_track(values: &[cgmath::Quaternion<f32>]) -> Track<cgmath::Quaternion<f32>> {
+        Track::make(
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, &value)| Keyframe {
+                    time: i as f32,
+                    value,
+                })
+                .collect(),
+            Interpolation::Cubic,
+        )
+    }
+
+    #[test]
+    fn wrap_time_once_clamps() {
+        assert_close(wrap_time(PlayMode::Once, -1.0, 2.0), 0.0);
+        assert_close(wrap_time(PlayMode::Once, 0.5, 2.0), 0.5);
+        assert_close(wrap_time(PlayMode::Once, 3.0, 2.0), 2.0);
+    }
+
+    #[test]
+    fn wrap_time_loop_repeats() {
+        assert_close(wrap_time(PlayMode::Loop, 2.5, 2.0), 0.5);
+        assert_close(wrap_time(PlayMode::Loop, 4.0, 2.0), 0.0);
+        assert_close(wrap_time(PlayMode::Loop, -0.5, 2.0), 1.5);
+    }
+
+    #[test]
+    fn wrap_time_ping_pong_runs_back() {
+        assert_close(wrap_time(PlayMode::PingPong, 1.5, 2.0), 1.5);
+        assert_close(wrap_time(PlayMode::PingPong, 2.5, 2.0), 1.5);
+        assert_close(wrap_time(PlayMode::PingPong, 4.5, 2.0), 0.5);
+    }
+
+    #[test]
+    fn wrap_time_without_duration_is_zero() {
+        for mode in [PlayMode::Once, PlayMode::Loop, PlayMode::PingPong] {
+            assert_eq!(wrap_time(mode, 3.0, 0.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn sample_holds_outside_the_track() {
+        let track = vector_track(&[1.0, 3.0], Interpolation::Linear);
+        assert_close(track.sample(-1.0).unwrap().x, 1.0);
+        assert_close(track.sample(5.0).unwrap().x, 3.0);
+        assert!(vector_track(&[], Interpolation::Linear)
+            .sample(0.0)
+            .is_none());
+    }
+
+    #[test]
+    fn sample_interpolates_between_keyframes() {
+        let step = vector_track(&[1.0, 3.0, 7.0], Interpolation::Step);
+        assert_close(step.sample(0.9).unwrap().x, 1.0);
+        assert_close(step.sample(1.0).unwrap().x, 3.0);
+        let linear = vector_track(&[1.0, 3.0, 7.0], Interpolation::Linear);
+        assert_close(linear.sample(0.5).unwrap().x, 2.0);
+        assert_close(linear.sample(1.25).unwrap().x, 4.0);
+        // Catmull-Rom goes through the keyframes and keeps evenly spaced ones on their line
+        let cubic = vector_track(&[0.0, 1.0, 2.0, 3.0], Interpolation::Cubic);
+        assert_close(cubic.sample(1.0).unwrap().x, 1.0);
+        assert_close(cubic.sample(1.5).unwrap().x, 1.5);
+    }
+
+    #[test]
+    fn sample_sorts_the_keyframes() {
+        let track = Track::make(
+            vec![
+                Keyframe {
+                    time: 2.0,
+                    value: cgmath::Vector3::new(2.0, 0.0, 0.0),
+                },
+                Keyframe {
+                    time: 0.0,
+                    value: cgmath::Vector3::new(0.0, 0.0, 0.0),
+                },
+            ],
+            Interpolation::Linear,
+        );
+        assert_close(track.get_duration(), 2.0);
+        assert_close(track.sample(0.5).unwrap().x, 0.5);
+    }
+
+    #[test]
+    fn quaternion_cubic_stays_a_unit_rotation_between_the_keyframes() {
+        let track = rotation_track(&[yaw(0.0), yaw(90.0), yaw(180.0), yaw(270.0)]);
+        let at_key = track.sample(1.0).unwrap();
+        assert_close(at_key.dot(yaw(90.0)).abs(), 1.0);
+        let between = track.sample(1.5).unwrap();
+        assert_close(between.magnitude(), 1.0);
+        // evenly spaced turns about one axis stay on it, halfway between
+        assert_close(between.dot(yaw(135.0)).abs(), 1.0);
+    }
+
+    #[test]
+    fn quaternion_cubic_ignores_the_sign_of_the_keyframes() {
+        let track = rotation_track(&[yaw(0.0), yaw(60.0), yaw(120.0), yaw(180.0)]);
+        // the same rotations, with some on the other hemisphere
+        let flipped = rotation_track(&[-yaw(0.0), yaw(60.0), -yaw(120.0), -yaw(180.0)]);
+        for time in [0.25, 1.5, 2.75] {
+            let (a, b) = (track.sample(time).unwrap(), flipped.sample(time).unwrap());
+            assert_close(a.dot(b).abs(), 1.0);
+        }
+    }
+
+    // slides from x = 0 to 1 over a second
+    fn slide(mode: PlayMode) -> Rc<AnimationClip> {
+        Rc::new(AnimationClip {
+            translation: Some(vector_track(&[0.0, 1.0], Interpolation::Linear)),
+            rotation: None,
+            scale: None,
+            mode,
+        })
+    }
+
+    fn scaled_instance() -> (InstanceSet, InstanceHandle) {
+        let instances = InstanceSet::make(vec![(
+            Instance::make((5.0, 0.0, 0.0).into(), yaw(30.0), (2.0, 2.0, 2.0).into()),
+            RenderGroup::Solid,
+        )]);
+        let handle = instances.handles()[0];
+        (instances, handle)
+    }
+
+    fn transform_of(instances: &InstanceSet, handle: InstanceHandle) -> Transform {
+        instances.get(handle).unwrap().get_transform().unwrap()
+    }
+
+    #[test]
+    fn play_keeps_the_parts_the_clip_does_not_animate() {
+        let (mut instances, handle) = scaled_instance();
+        let mut scene_graph = SceneGraph::make();
+        let mut animator = Animator::make();
+        let target = AnimationTarget::Instance(handle);
+        assert!(animator.play(slide(PlayMode::Loop), target, 1.0, &scene_graph, &instances));
+        animator.update(0.25, &mut scene_graph, &mut instances);
+        let transform = transform_of(&instances, handle);
+        assert_close(transform.position.x, 0.25);
+        assert_close(transform.scale.y, 2.0);
+        assert_close(transform.rotation.dot(yaw(30.0)), 1.0);
+    }
+
+    #[test]
+    fn play_refuses_instances_without_a_transform() {
+        let instances = InstanceSet::make(vec![(ArrayInstance::make(0, 0), RenderGroup::Solid)]);
+        let target = AnimationTarget::Instance(instances.handles()[0]);
+        let mut animator = Animator::make();
+        let scene_graph = SceneGraph::make();
+        assert!(!animator.play(slide(PlayMode::Loop), target, 1.0, &scene_graph, &instances));
+        assert!(animator.players.is_empty());
+    }
+
+    #[test]
+    fn play_replaces_and_stop_ends_the_target_clip() {
+        let (mut instances, handle) = scaled_instance();
+        let mut scene_graph = SceneGraph::make();
+        let node = scene_graph.add_node(None, Transform::default());
+        let mut animator = Animator::make();
+        let target = AnimationTarget::Instance(handle);
+        for clip in [slide(PlayMode::Loop), slide(PlayMode::PingPong)] {
+            animator.play(clip, target, 1.0, &scene_graph, &instances);
+        }
+        let node_target = AnimationTarget::Node(node);
+        animator.play(
+            slide(PlayMode::Loop),
+            node_target,
+            2.0,
+            &scene_graph,
+            &instances,
+        );
+        assert_eq!(animator.players.len(), 2);
+
+        animator.update(0.2, &mut scene_graph, &mut instances);
+        assert_close(scene_graph.get_local(node).position.x, 0.4);
+        animator.stop(target);
+        animator.update(0.2, &mut scene_graph, &mut instances);
+        assert_close(transform_of(&instances, handle).position.x, 0.2);
+        assert_close(scene_graph.get_local(node).position.x, 0.8);
+    }
+
+    #[test]
+    fn once_ends_on_the_final_pose() {
+        let (mut instances, handle) = scaled_instance();
+        let mut scene_graph = SceneGraph::make();
+        let mut animator = Animator::make();
+        let target = AnimationTarget::Instance(handle);
+        animator.play(slide(PlayMode::Once), target, 1.0, &scene_graph, &instances);
+        animator.update(0.6, &mut scene_graph, &mut instances);
+        assert_eq!(animator.players.len(), 1);
+        animator.update(0.6, &mut scene_graph, &mut instances);
+        assert!(animator.players.is_empty());
+        assert_close(transform_of(&instances, handle).position.x, 1.0);
+
+        // backwards it ends at the start
+        animator.play(
+            slide(PlayMode::Once),
+            target,
+            -1.0,
+            &scene_graph,
+            &instances,
+        );
+        animator.update(0.1, &mut scene_graph, &mut instances);
+        assert!(animator.players.is_empty());
+        assert_close(transform_of(&instances, handle).position.x, 0.0);
+    }
+
+    #[test]
+    fn looping_clips_keep_playing() {
+        let (mut instances, handle) = scaled_instance();
+        let mut scene_graph = SceneGraph::make();
+        let mut animator = Animator::make();
+        let target = AnimationTarget::Instance(handle);
+        animator.play(slide(PlayMode::Loop), target, 1.0, &scene_graph, &instances);
+        animator.update(2.5, &mut scene_graph, &mut instances);
+        assert_eq!(animator.players.len(), 1);
+        assert_close(transform_of(&instances, handle).position.x, 0.5);
+    }
+
+    #[test]
+    fn update_drops_players_of_removed_instances() {
+        let (mut instances, handle) = scaled_instance();
+        let mut scene_graph = SceneGraph::make();
+        let mut animator = Animator::make();
+        let target = AnimationTarget::Instance(handle);
+        animator.play(slide(PlayMode::Loop), target, 1.0, &scene_graph, &instances);
+        instances.remove(handle);
+        animator.update(0.1, &mut scene_graph, &mut instances);
+        assert!(animator.players.is_empty());
+    }
+}
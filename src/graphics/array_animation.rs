@@ -0,0 +1,222 @@
+use std::sync::mpsc;
+
+use anyhow::{bail, Context, Result};
+use cgmath::SquareMatrix;
+
+use super::instance::{ArrayInstance, InstanceRaw};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ArrayParams {
+    time: f32,
+    width: u32,
+    depth: u32,
+    stride: u32,
+}
+
+// arrayInstance.wgsl writes InstanceRaw as floats at these offsets
+const _: () = {
+    let float = std::mem::size_of::<f32>();
+    assert!(std::mem::offset_of!(InstanceRaw, normal) == 16 * float);
+    assert!(std::mem::offset_of!(InstanceRaw, tint) == 25 * float);
+    assert!(std::mem::offset_of!(InstanceRaw, material_index) == 29 * float);
+    assert!(std::mem::offset_of!(InstanceRaw, joint_offset) == 30 * float);
+    assert!(std::mem::offset_of!(InstanceRaw, custom) == 31 * float);
+    assert!(std::mem::size_of::<InstanceRaw>() == 35 * float);
+};
+
+// The grid of ArrayInstances animated by a compute shader. The instances never exist on the CPU,
+// the shader writes their InstanceRaw into a buffer that's bound as the instance buffer, drawn
+// with the textured pipeline.
+pub struct ArrayAnimation {
+    width: u32,
+    depth: u32,
+    time: f32,
+    params_buffer: wgpu::Buffer,
+    instance_buffer: wgpu::Buffer,
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ArrayAnimation {
+    const WORKGROUP_SIZE: u32 = 64;
+    // the GPU's sin and cos are less precise than the CPU's
+    const TOLERANCE: f32 = 1e-3;
+
+    pub fn make(device: &wgpu::Device, width: u32, depth: u32) -> Self {
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Array Params Buffer"),
+            size: std::mem::size_of::<ArrayParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let count = (width * depth).max(1) as usize;
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Array Instance Buffer"),
+            size: (count * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("array_animation_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("array_animation_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Array Instance Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../arrayInstance.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Array Instance Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Array Instance Pipeline Layout"),
+                    bind_group_layouts: &[&bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        Self {
+            width,
+            depth,
+            time: 0.0,
+            params_buffer,
+            instance_buffer,
+            pipeline,
+            bind_group,
+        }
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, time: f32) {
+        self.time = time;
+        let params = ArrayParams {
+            time,
+            width: self.width,
+            depth: self.depth,
+            stride: (std::mem::size_of::<InstanceRaw>() / std::mem::size_of::<f32>()) as u32,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+    }
+
+    // Has to run before the passes that draw the grid
+    pub fn compute(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Array Animation Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.count().div_ceil(Self::WORKGROUP_SIZE), 1, 1);
+    }
+
+    pub fn get_buffer(&self) -> &wgpu::Buffer {
+        &self.instance_buffer
+    }
+
+    pub fn count(&self) -> u32 {
+        self.width * self.depth
+    }
+
+    // The same grid through ArrayInstance on the CPU
+    pub fn compute_cpu(&self, time: f32) -> Vec<InstanceRaw> {
+        (0..self.depth)
+            .flat_map(|z| (0..self.width).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                let mut instance = ArrayInstance::make(x, z);
+                instance.update(time);
+                instance.to_raw(&cgmath::Matrix4::identity())
+            })
+            .collect()
+    }
+
+    // Reads the buffer back, blocking until the GPU is done, and compares it with the CPU
+    // path at the time of the last update. Fails if they're further apart than the tolerance.
+    pub fn validate(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        if self.count() == 0 {
+            return Ok(());
+        }
+        let size = self.count() as u64 * std::mem::size_of::<InstanceRaw>() as u64;
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Array Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Array Readback Encoder"),
+        });
+        self.compute(&mut encoder);
+        encoder.copy_buffer_to_buffer(&self.instance_buffer, 0, &staging_buffer, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = staging_buffer.slice(..size);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .context("the readback was dropped")?
+            .context("failed to map the readback buffer")?;
+
+        let error = {
+            let data = slice.get_mapped_range();
+            let gpu: &[InstanceRaw] = bytemuck::cast_slice(&data);
+            let cpu = self.compute_cpu(self.time);
+            gpu.iter()
+                .zip(&cpu)
+                .flat_map(|(gpu, cpu)| {
+                    let gpu: &[f32] = bytemuck::cast_slice(std::slice::from_ref(gpu));
+                    let cpu: &[f32] = bytemuck::cast_slice(std::slice::from_ref(cpu));
+                    gpu.iter().zip(cpu).map(|(a, b)| (a - b).abs())
+                })
+                .fold(0.0, f32::max)
+        };
+        staging_buffer.unmap();
+        if error > Self::TOLERANCE {
+            bail!("the GPU grid is off by {} from the CPU one", error);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1046 @@
+use anyhow::*;
+
+// Software decoders for the BC1-BC7 block formats, used when the adapter can't sample them.
+// Every block covers 4x4 texels and decodes to 16 texels in row-major order.
+
+type BlockDecoder = fn(&[u8], &mut [u8]);
+
+// Decompresses one mip level. BC1-BC3 and BC7 become Rgba8 (keeping the sRGB flag),
+// BC4/BC5 put their channels in R and G like the hardware does and BC6H becomes Rgba16Float.
+pub fn decompress(
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Result<(wgpu::TextureFormat, Vec<u8>)> {
+    use wgpu::TextureFormat as F;
+    let (decoded_format, block_size, decode_block): (_, _, BlockDecoder) = match format {
+        F::Bc1RgbaUnorm => (F::Rgba8Unorm, 8, decode_bc1),
+        F::Bc1RgbaUnormSrgb => (F::Rgba8UnormSrgb, 8, decode_bc1),
+        F::Bc2RgbaUnorm => (F::Rgba8Unorm, 16, decode_bc2),
+        F::Bc2RgbaUnormSrgb => (F::Rgba8UnormSrgb, 16, decode_bc2),
+        F::Bc3RgbaUnorm => (F::Rgba8Unorm, 16, decode_bc3),
+        F::Bc3RgbaUnormSrgb => (F::Rgba8UnormSrgb, 16, decode_bc3),
+        F::Bc4RUnorm => (F::Rgba8Unorm, 8, decode_bc4_unorm),
+        F::Bc4RSnorm => (F::Rgba8Snorm, 8, decode_bc4_snorm),
+        F::Bc5RgUnorm => (F::Rgba8Unorm, 16, decode_bc5_unorm),
+        F::Bc5RgSnorm => (F::Rgba8Snorm, 16, decode_bc5_snorm),
+        F::Bc6hRgbUfloat => (F::Rgba16Float, 16, decode_bc6h_ufloat),
+        F::Bc6hRgbSfloat => (F::Rgba16Float, 16, decode_bc6h_sfloat),
+        F::Bc7RgbaUnorm => (F::Rgba8Unorm, 16, decode_bc7),
+        F::Bc7RgbaUnormSrgb => (F::Rgba8UnormSrgb, 16, decode_bc7),
+        _ => bail!("no software decoder for {:?}", format),
+    };
+    let texel_size = decoded_format.describe().block_size as usize;
+
+    let (width, height) = (width as usize, height as usize);
+    let (blocks_wide, blocks_high) = (width.div_ceil(4), height.div_ceil(4));
+    if data.len() < blocks_wide * blocks_high * block_size {
+        bail!(
+            "{}x{} {:?} level needs {} bytes, got {}",
+            width,
+            height,
+            format,
+            blocks_wide * blocks_high * block_size,
+            data.len()
+        );
+    }
+
+    let mut pixels = vec![0; width * height * texel_size];
+    let mut texels = [0; 16 * 8];
+    for (i, block) in data
+        .chunks_exact(block_size)
+        .take(blocks_wide * blocks_high)
+        .enumerate()
+    {
+        let (bx, by) = (i % blocks_wide * 4, i / blocks_wide * 4);
+        decode_block(block, &mut texels[..16 * texel_size]);
+        // blocks on the right and bottom edge can hang over the image
+        for y in 0..4.min(height - by) {
+            let columns = 4.min(width - bx);
+            let src = y * 4 * texel_size;
+            let dst = ((by + y) * width + bx) * texel_size;
+            pixels[dst..dst + columns * texel_size]
+                .copy_from_slice(&texels[src..src + columns * texel_size]);
+        }
+    }
+    Ok((decoded_format, pixels))
+}
+
+fn rgb565(color: u16) -> [u8; 3] {
+    let (r, g, b) = ((color >> 11) & 31, (color >> 5) & 63, color & 31);
+    [
+        (r << 3 | r >> 2) as u8,
+        (g << 2 | g >> 4) as u8,
+        (b << 3 | b >> 2) as u8,
+    ]
+}
+
+// The colour half shared by BC1-BC3. Only BC1 has the 3 colour + transparent mode.
+fn decode_color(block: &[u8], out: &mut [u8], allow_transparent: bool) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (p0, p1) = (rgb565(c0), rgb565(c1));
+    let mix = |a: u8, b: u8, wa: u32, wb: u32| ((a as u32 * wa + b as u32 * wb) / (wa + wb)) as u8;
+
+    let mut palette = [[0; 4]; 4];
+    for c in 0..3 {
+        palette[0][c] = p0[c];
+        palette[1][c] = p1[c];
+        if c0 > c1 || !allow_transparent {
+            palette[2][c] = mix(p0[c], p1[c], 2, 1);
+            palette[3][c] = mix(p0[c], p1[c], 1, 2);
+        } else {
+            palette[2][c] = mix(p0[c], p1[c], 1, 1);
+        }
+    }
+    palette[0][3] = 255;
+    palette[1][3] = 255;
+    palette[2][3] = 255;
+    palette[3][3] = if c0 > c1 || !allow_transparent {
+        255
+    } else {
+        0
+    };
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (i, texel) in out.chunks_exact_mut(4).enumerate() {
+        texel.copy_from_slice(&palette[(indices >> (2 * i) & 3) as usize]);
+    }
+}
+
+// Single channel block of BC3 alpha, BC4 and BC5: two endpoints and 3 bit indices.
+// With the first endpoint larger there are 6 interpolated values, otherwise 4 plus min and max.
+fn decode_channel(block: &[u8], signed: bool) -> [u8; 16] {
+    let value = |byte: u8| {
+        if signed {
+            (byte as i8).max(-127) as f32
+        } else {
+            byte as f32
+        }
+    };
+    let (e0, e1) = (value(block[0]), value(block[1]));
+    let (min, max) = if signed {
+        (-127.0, 127.0)
+    } else {
+        (0.0, 255.0)
+    };
+
+    let mut palette = [e0, e1, 0.0, 0.0, 0.0, 0.0, min, max];
+    if e0 > e1 {
+        for (i, entry) in palette.iter_mut().enumerate().skip(2) {
+            *entry = ((8 - i) as f32 * e0 + (i - 1) as f32 * e1) / 7.0;
+        }
+    } else {
+        for (i, entry) in palette.iter_mut().enumerate().take(6).skip(2) {
+            *entry = ((6 - i) as f32 * e0 + (i - 1) as f32 * e1) / 5.0;
+        }
+    }
+
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    let mut out = [0; 16];
+    for (i, texel) in out.iter_mut().enumerate() {
+        let v = palette[(indices >> (3 * i) & 7) as usize].round();
+        // snorm values are stored as their two's complement bytes
+        *texel = if signed { v as i8 as u8 } else { v as u8 };
+    }
+    out
+}
+
+fn decode_bc1(block: &[u8], out: &mut [u8]) {
+    decode_color(block, out, true);
+}
+
+fn decode_bc2(block: &[u8], out: &mut [u8]) {
+    decode_color(&block[8..], out, false);
+    for (i, texel) in out.chunks_exact_mut(4).enumerate() {
+        let alpha = block[i / 2] >> (4 * (i % 2)) & 15;
+        texel[3] = alpha << 4 | alpha;
+    }
+}
+
+fn decode_bc3(block: &[u8], out: &mut [u8]) {
+    decode_color(&block[8..], out, false);
+    let alpha = decode_channel(&block[..8], false);
+    for (texel, alpha) in out.chunks_exact_mut(4).zip(alpha) {
+        texel[3] = alpha;
+    }
+}
+
+fn decode_red_green(red: [u8; 16], green: [u8; 16], one: u8, out: &mut [u8]) {
+    for (i, texel) in out.chunks_exact_mut(4).enumerate() {
+        texel.copy_from_slice(&[red[i], green[i], 0, one]);
+    }
+}
+
+fn decode_bc4_unorm(block: &[u8], out: &mut [u8]) {
+    decode_red_green(decode_channel(block, false), [0; 16], 255, out);
+}
+
+fn decode_bc4_snorm(block: &[u8], out: &mut [u8]) {
+    decode_red_green(decode_channel(block, true), [0; 16], 127, out);
+}
+
+fn decode_bc5_unorm(block: &[u8], out: &mut [u8]) {
+    let (red, green) = (
+        decode_channel(&block[..8], false),
+        decode_channel(&block[8..], false),
+    );
+    decode_red_green(red, green, 255, out);
+}
+
+fn decode_bc5_snorm(block: &[u8], out: &mut [u8]) {
+    let (red, green) = (
+        decode_channel(&block[..8], true),
+        decode_channel(&block[8..], true),
+    );
+    decode_red_green(red, green, 127, out);
+}
+
+// BC6H and BC7 fields are packed LSB first across the whole 128 bit block
+struct Bits {
+    bits: u128,
+    position: u32,
+}
+
+impl Bits {
+    fn make(block: &[u8]) -> Bits {
+        Bits {
+            bits: u128::from_le_bytes(block.try_into().unwrap()),
+            position: 0,
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits >> self.position) as u32 & ((1u64 << count) - 1) as u32;
+        self.position += count;
+        value
+    }
+}
+
+const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn weights(index_bits: u32) -> &'static [u32] {
+    match index_bits {
+        2 => &WEIGHTS_2,
+        3 => &WEIGHTS_3,
+        _ => &WEIGHTS_4,
+    }
+}
+
+// Two subset partitions, bit i set when texel i belongs to the second subset
+const PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, 0xc800, 0xffec, 0xfe80, 0xe800,
+    0xffe8, 0xff00, 0xfff0, 0xf000, 0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c, 0xaaaa, 0xf0f0, 0x5a5a, 0x33cc,
+    0x3c3c, 0x55aa, 0x9696, 0xa55a, 0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, 0x9336, 0x9cc6, 0x817e, 0xe718,
+    0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+// Three subset partitions, two bits per texel holding its subset
+const PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+
+// The anchor texel of each subset stores its index with one bit less. Subset 0 always uses texel 0.
+const ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+const ANCHORS_3_SECOND: [u8; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5,
+    15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5,
+    10, 8, 13, 15, 12, 3, 3,
+];
+const ANCHORS_3_THIRD: [u8; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6,
+    10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+];
+
+// Subset of every texel and whether it is that subset's anchor
+fn partition(subsets: usize, shape: usize) -> [(usize, bool); 16] {
+    let mut texels = [(0, false); 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = match subsets {
+            1 => (0, i == 0),
+            2 => {
+                let subset = (PARTITIONS_2[shape] >> i & 1) as usize;
+                (subset, i == 0 || i == ANCHORS_2[shape] as usize)
+            }
+            _ => {
+                let subset = (PARTITIONS_3[shape] >> (2 * i) & 3) as usize;
+                let anchor = i == 0
+                    || i == ANCHORS_3_SECOND[shape] as usize
+                    || i == ANCHORS_3_THIRD[shape] as usize;
+                (subset, anchor)
+            }
+        };
+    }
+    texels
+}
+
+fn read_indices(bits: &mut Bits, texels: &[(usize, bool); 16], index_bits: u32) -> [usize; 16] {
+    let mut indices = [0; 16];
+    for (index, (_, anchor)) in indices.iter_mut().zip(texels) {
+        *index = bits.read(index_bits - *anchor as u32) as usize;
+    }
+    indices
+}
+
+fn interpolate(e0: i32, e1: i32, weight: u32) -> i32 {
+    ((64 - weight as i32) * e0 + weight as i32 * e1 + 32) >> 6
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    // one p-bit per endpoint or one shared by both endpoints of a subset
+    endpoint_p_bits: bool,
+    shared_p_bits: bool,
+    index_bits: u32,
+    // separate alpha indices in modes 4 and 5
+    index2_bits: u32,
+}
+
+#[rustfmt::skip]
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 3, index2_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: true, index_bits: 3, index2_bits: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, index2_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, index2_bits: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, index2_bits: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, index2_bits: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_p_bits: true, shared_p_bits: false, index_bits: 4, index2_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, index2_bits: 0 },
+];
+
+fn decode_bc7(block: &[u8], out: &mut [u8]) {
+    // the mode is the number of zero bits before the first one
+    let mode_number = (block[0] as u32 | 0x100).trailing_zeros() as usize;
+    if mode_number >= 8 {
+        // reserved, decodes to transparent black
+        out.fill(0);
+        return;
+    }
+    let mode = &BC7_MODES[mode_number];
+    let mut bits = Bits::make(block);
+    bits.read(mode_number as u32 + 1);
+
+    let shape = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    let endpoint_count = 2 * mode.subsets;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..4 {
+        let channel_bits = if channel < 3 {
+            mode.color_bits
+        } else {
+            mode.alpha_bits
+        };
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = bits.read(channel_bits);
+        }
+    }
+
+    let has_p_bits = mode.endpoint_p_bits || mode.shared_p_bits;
+    if has_p_bits {
+        let mut p_bits = [0; 6];
+        if mode.endpoint_p_bits {
+            for p_bit in p_bits.iter_mut().take(endpoint_count) {
+                *p_bit = bits.read(1);
+            }
+        } else {
+            for subset in 0..mode.subsets {
+                let p_bit = bits.read(1);
+                p_bits[2 * subset] = p_bit;
+                p_bits[2 * subset + 1] = p_bit;
+            }
+        }
+        for (endpoint, p_bit) in endpoints.iter_mut().zip(p_bits) {
+            for value in endpoint.iter_mut() {
+                *value = *value << 1 | p_bit;
+            }
+        }
+    }
+
+    // expand to 8 bits by replicating the high bits into the low ones
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        for (channel, value) in endpoint.iter_mut().enumerate() {
+            let channel_bits = if channel < 3 {
+                mode.color_bits
+            } else {
+                mode.alpha_bits
+            };
+            if channel_bits == 0 {
+                *value = 255;
+                continue;
+            }
+            let precision = channel_bits + has_p_bits as u32;
+            *value <<= 8 - precision;
+            *value |= *value >> precision;
+        }
+    }
+
+    let texels = partition(mode.subsets, shape);
+    let indices = read_indices(&mut bits, &texels, mode.index_bits);
+    let indices2 = if mode.index2_bits > 0 {
+        read_indices(&mut bits, &partition(1, 0), mode.index2_bits)
+    } else {
+        indices
+    };
+    // the index selection bit swaps which set drives the colour and which the alpha
+    let ((color_indices, color_bits), (alpha_indices, alpha_bits)) = {
+        let primary = (indices, mode.index_bits);
+        let secondary = (indices2, mode.index2_bits.max(mode.index_bits));
+        if index_selection == 1 {
+            (secondary, primary)
+        } else {
+            (primary, secondary)
+        }
+    };
+
+    for (i, texel) in out.chunks_exact_mut(4).enumerate() {
+        let subset = texels[i].0;
+        let (e0, e1) = (endpoints[2 * subset], endpoints[2 * subset + 1]);
+        let mut color = [0u8; 4];
+        for c in 0..3 {
+            let weight = weights(color_bits)[color_indices[i]];
+            color[c] = interpolate(e0[c] as i32, e1[c] as i32, weight) as u8;
+        }
+        let weight = weights(alpha_bits)[alpha_indices[i]];
+        color[3] = interpolate(e0[3] as i32, e1[3] as i32, weight) as u8;
+        if rotation > 0 {
+            color.swap(3, rotation as usize - 1);
+        }
+        texel.copy_from_slice(&color);
+    }
+}
+
+// Endpoint components of BC6H in the order the fields are named in the spec:
+// w and x are the endpoints of the first subset, y and z of the second
+const RW: u8 = 0;
+const GW: u8 = 1;
+const BW: u8 = 2;
+const RX: u8 = 3;
+const GX: u8 = 4;
+const BX: u8 = 5;
+const RY: u8 = 6;
+const GY: u8 = 7;
+const BY: u8 = 8;
+const RZ: u8 = 9;
+const GZ: u8 = 10;
+const BZ: u8 = 11;
+
+struct Bc6hMode {
+    subsets: usize,
+    // endpoint precision and precision of the x, y and z deltas per channel
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    transformed: bool,
+    // (component, lowest bit, bit count) in the order they are stored after the mode bits
+    fields: &'static [(u8, u32, u32)],
+}
+
+// Reversed fields (modes 13 and 14) are listed one bit at a time
+#[rustfmt::skip]
+const BC6H_MODES: [(u32, Bc6hMode); 14] = [
+    (0b00, Bc6hMode { subsets: 2, endpoint_bits: 10, delta_bits: [5, 5, 5], transformed: true, fields: &[
+        (GY, 4, 1), (BY, 4, 1), (BZ, 4, 1), (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 5),
+        (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1),
+        (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+    ] }),
+    (0b01, Bc6hMode { subsets: 2, endpoint_bits: 7, delta_bits: [6, 6, 6], transformed: true, fields: &[
+        (GY, 5, 1), (GZ, 4, 1), (GZ, 5, 1), (RW, 0, 7), (BZ, 0, 1), (BZ, 1, 1), (BY, 4, 1),
+        (GW, 0, 7), (BY, 5, 1), (BZ, 2, 1), (GY, 4, 1), (BW, 0, 7), (BZ, 3, 1), (BZ, 5, 1),
+        (BZ, 4, 1), (RX, 0, 6), (GY, 0, 4), (GX, 0, 6), (GZ, 0, 4), (BX, 0, 6), (BY, 0, 4),
+        (RY, 0, 6), (RZ, 0, 6),
+    ] }),
+    (0b00010, Bc6hMode { subsets: 2, endpoint_bits: 11, delta_bits: [5, 4, 4], transformed: true, fields: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 5), (RW, 10, 1), (GY, 0, 4), (GX, 0, 4),
+        (GW, 10, 1), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 4), (BW, 10, 1), (BZ, 1, 1), (BY, 0, 4),
+        (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+    ] }),
+    (0b00110, Bc6hMode { subsets: 2, endpoint_bits: 11, delta_bits: [4, 5, 4], transformed: true, fields: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 10, 1), (GZ, 4, 1), (GY, 0, 4),
+        (GX, 0, 5), (GW, 10, 1), (GZ, 0, 4), (BX, 0, 4), (BW, 10, 1), (BZ, 1, 1), (BY, 0, 4),
+        (RY, 0, 4), (BZ, 0, 1), (BZ, 2, 1), (RZ, 0, 4), (GY, 4, 1), (BZ, 3, 1),
+    ] }),
+    (0b01010, Bc6hMode { subsets: 2, endpoint_bits: 11, delta_bits: [4, 4, 5], transformed: true, fields: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 10, 1), (BY, 4, 1), (GY, 0, 4),
+        (GX, 0, 4), (GW, 10, 1), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5), (BW, 10, 1), (BY, 0, 4),
+        (RY, 0, 4), (BZ, 1, 1), (BZ, 2, 1), (RZ, 0, 4), (BZ, 4, 1), (BZ, 3, 1),
+    ] }),
+    (0b01110, Bc6hMode { subsets: 2, endpoint_bits: 9, delta_bits: [5, 5, 5], transformed: true, fields: &[
+        (RW, 0, 9), (BY, 4, 1), (GW, 0, 9), (GY, 4, 1), (BW, 0, 9), (BZ, 4, 1), (RX, 0, 5),
+        (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4), (BX, 0, 5), (BZ, 1, 1),
+        (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+    ] }),
+    (0b10010, Bc6hMode { subsets: 2, endpoint_bits: 8, delta_bits: [6, 5, 5], transformed: true, fields: &[
+        (RW, 0, 8), (GZ, 4, 1), (BY, 4, 1), (GW, 0, 8), (BZ, 2, 1), (GY, 4, 1), (BW, 0, 8),
+        (BZ, 3, 1), (BZ, 4, 1), (RX, 0, 6), (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1), (GZ, 0, 4),
+        (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 6), (RZ, 0, 6),
+    ] }),
+    (0b10110, Bc6hMode { subsets: 2, endpoint_bits: 8, delta_bits: [5, 6, 5], transformed: true, fields: &[
+        (RW, 0, 8), (BZ, 0, 1), (BY, 4, 1), (GW, 0, 8), (GY, 5, 1), (GY, 4, 1), (BW, 0, 8),
+        (GZ, 5, 1), (BZ, 4, 1), (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 6), (GZ, 0, 4),
+        (BX, 0, 5), (BZ, 1, 1), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+    ] }),
+    (0b11010, Bc6hMode { subsets: 2, endpoint_bits: 8, delta_bits: [5, 5, 6], transformed: true, fields: &[
+        (RW, 0, 8), (BZ, 1, 1), (BY, 4, 1), (GW, 0, 8), (BY, 5, 1), (GY, 4, 1), (BW, 0, 8),
+        (BZ, 5, 1), (BZ, 4, 1), (RX, 0, 5), (GZ, 4, 1), (GY, 0, 4), (GX, 0, 5), (BZ, 0, 1),
+        (GZ, 0, 4), (BX, 0, 6), (BY, 0, 4), (RY, 0, 5), (BZ, 2, 1), (RZ, 0, 5), (BZ, 3, 1),
+    ] }),
+    (0b11110, Bc6hMode { subsets: 2, endpoint_bits: 6, delta_bits: [6, 6, 6], transformed: false, fields: &[
+        (RW, 0, 6), (GZ, 4, 1), (BZ, 0, 1), (BZ, 1, 1), (BY, 4, 1), (GW, 0, 6), (GY, 5, 1),
+        (BY, 5, 1), (BZ, 2, 1), (GY, 4, 1), (BW, 0, 6), (GZ, 5, 1), (BZ, 3, 1), (BZ, 5, 1),
+        (BZ, 4, 1), (RX, 0, 6), (GY, 0, 4), (GX, 0, 6), (GZ, 0, 4), (BX, 0, 6), (BY, 0, 4),
+        (RY, 0, 6), (RZ, 0, 6),
+    ] }),
+    (0b00011, Bc6hMode { subsets: 1, endpoint_bits: 10, delta_bits: [10, 10, 10], transformed: false, fields: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 10), (GX, 0, 10), (BX, 0, 10),
+    ] }),
+    (0b00111, Bc6hMode { subsets: 1, endpoint_bits: 11, delta_bits: [9, 9, 9], transformed: true, fields: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 9), (RW, 10, 1), (GX, 0, 9), (GW, 10, 1),
+        (BX, 0, 9), (BW, 10, 1),
+    ] }),
+    (0b01011, Bc6hMode { subsets: 1, endpoint_bits: 12, delta_bits: [8, 8, 8], transformed: true, fields: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 8), (RW, 11, 1), (RW, 10, 1), (GX, 0, 8),
+        (GW, 11, 1), (GW, 10, 1), (BX, 0, 8), (BW, 11, 1), (BW, 10, 1),
+    ] }),
+    (0b01111, Bc6hMode { subsets: 1, endpoint_bits: 16, delta_bits: [4, 4, 4], transformed: true, fields: &[
+        (RW, 0, 10), (GW, 0, 10), (BW, 0, 10), (RX, 0, 4), (RW, 15, 1), (RW, 14, 1), (RW, 13, 1),
+        (RW, 12, 1), (RW, 11, 1), (RW, 10, 1), (GX, 0, 4), (GW, 15, 1), (GW, 14, 1), (GW, 13, 1),
+        (GW, 12, 1), (GW, 11, 1), (GW, 10, 1), (BX, 0, 4), (BW, 15, 1), (BW, 14, 1), (BW, 13, 1),
+        (BW, 12, 1), (BW, 11, 1), (BW, 10, 1),
+    ] }),
+];
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    value << shift >> shift
+}
+
+// Endpoint to the 16 bit range interpolation happens in
+fn unquantize(value: i32, bits: u32, signed: bool) -> i32 {
+    if !signed {
+        if bits >= 15 || value == 0 {
+            value
+        } else if value == (1 << bits) - 1 {
+            0xffff
+        } else {
+            ((value << 16) + 0x8000) >> bits
+        }
+    } else if bits >= 16 {
+        value
+    } else {
+        let magnitude = value.abs();
+        let unquantized = if magnitude == 0 {
+            0
+        } else if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7fff
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if value < 0 {
+            -unquantized
+        } else {
+            unquantized
+        }
+    }
+}
+
+// Interpolated value to half float bits
+fn finish_unquantize(value: i32, signed: bool) -> u16 {
+    if !signed {
+        ((value * 31) >> 6) as u16
+    } else if value < 0 {
+        0x8000 | ((-value * 31) >> 5) as u16
+    } else {
+        ((value * 31) >> 5) as u16
+    }
+}
+
+fn decode_bc6h(block: &[u8], out: &mut [u8], signed: bool) {
+    let mut bits = Bits::make(block);
+    let mut mode_bits = bits.read(2);
+    if mode_bits > 1 {
+        mode_bits |= bits.read(3) << 2;
+    }
+    let mode = match BC6H_MODES.iter().find(|(bits, _)| *bits == mode_bits) {
+        Some((_, mode)) => mode,
+        None => {
+            // reserved modes decode to black
+            out.fill(0);
+            return;
+        }
+    };
+
+    let mut components = [0i32; 12];
+    for &(component, lowest_bit, count) in mode.fields {
+        components[component as usize] |= (bits.read(count) << lowest_bit) as i32;
+    }
+    let shape = if mode.subsets == 2 {
+        bits.read(5) as usize
+    } else {
+        0
+    };
+
+    let endpoint_count = 2 * mode.subsets;
+    let mask = (1 << mode.endpoint_bits) - 1;
+    if signed {
+        for value in components.iter_mut().take(3) {
+            *value = sign_extend(*value, mode.endpoint_bits);
+        }
+    }
+    for endpoint in 1..endpoint_count {
+        for channel in 0..3 {
+            let mut value = components[3 * endpoint + channel];
+            if mode.transformed {
+                // x, y and z are stored as deltas from w
+                let delta = sign_extend(value, mode.delta_bits[channel]);
+                value = (components[channel] + delta) & mask;
+            }
+            if signed {
+                value = sign_extend(value, mode.endpoint_bits);
+            }
+            components[3 * endpoint + channel] = value;
+        }
+    }
+    for value in components.iter_mut().take(3 * endpoint_count) {
+        *value = unquantize(*value, mode.endpoint_bits, signed);
+    }
+
+    let texels = partition(mode.subsets, shape);
+    let index_bits = if mode.subsets == 2 { 3 } else { 4 };
+    let indices = read_indices(&mut bits, &texels, index_bits);
+
+    for (i, texel) in out.chunks_exact_mut(8).enumerate() {
+        let subset = texels[i].0;
+        let weight = weights(index_bits)[indices[i]];
+        let mut color = [0u16, 0, 0, 0x3c00];
+        for (c, half) in color.iter_mut().take(3).enumerate() {
+            let e0 = components[6 * subset + c];
+            let e1 = components[6 * subset + 3 + c];
+            *half = finish_unquantize(interpolate(e0, e1, weight), signed);
+        }
+        texel.copy_from_slice(bytemuck::cast_slice(&color));
+    }
+}
+
+fn decode_bc6h_ufloat(block: &[u8], out: &mut [u8]) {
+    decode_bc6h(block, out, false);
+}
+
+fn decode_bc6h_sfloat(block: &[u8], out: &mut [u8]) {
+    decode_bc6h(block, out, true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::TextureFormat as F;
+
+    // texel i uses colour index i % 4
+    const COLOR_INDICES: [u8; 4] = [0xe4; 4];
+    // texel i uses index i % 8, and 7 - i % 8
+    const CHANNEL_INDICES: [u8; 6] = [0x88, 0xc6, 0xfa, 0x88, 0xc6, 0xfa];
+    const CHANNEL_INDICES_REVERSED: [u8; 6] = [0x77, 0x39, 0x05, 0x77, 0x39, 0x05];
+
+    const RED: [u8; 2] = [0x00, 0xf8];
+    const BLUE: [u8; 2] = [0x1f, 0x00];
+
+    fn decode(format: wgpu::TextureFormat, block: &[u8]) -> Vec<[u8; 4]> {
+        let (_, pixels) = decompress(format, 4, 4, block).unwrap();
+        pixels
+            .chunks_exact(4)
+            .map(|texel| texel.try_into().unwrap())
+            .collect()
+    }
+
+    fn color_block(c0: [u8; 2], c1: [u8; 2], indices: [u8; 4]) -> Vec<u8> {
+        [&c0[..], &c1, &indices].concat()
+    }
+
+    #[test]
+    fn bc1_four_colors() {
+        let texels = decode(F::Bc1RgbaUnorm, &color_block(RED, BLUE, COLOR_INDICES));
+        let palette = [
+            [255, 0, 0, 255],
+            [0, 0, 255, 255],
+            [170, 0, 85, 255],
+            [85, 0, 170, 255],
+        ];
+        for (i, texel) in texels.iter().enumerate() {
+            assert_eq!(*texel, palette[i % 4], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc1_three_colors_and_punch_through() {
+        // c0 <= c1 switches to the halfway colour and transparent black
+        let texels = decode(F::Bc1RgbaUnorm, &color_block(BLUE, RED, COLOR_INDICES));
+        let palette = [
+            [0, 0, 255, 255],
+            [255, 0, 0, 255],
+            [127, 0, 127, 255],
+            [0, 0, 0, 0],
+        ];
+        for (i, texel) in texels.iter().enumerate() {
+            assert_eq!(*texel, palette[i % 4], "texel {}", i);
+        }
+
+        let green = [0xe0, 0x07];
+        let texels = decode(F::Bc1RgbaUnorm, &color_block(green, green, [0x0c; 4]));
+        for (i, texel) in texels.iter().enumerate() {
+            let expected = if i % 4 == 1 {
+                [0, 0, 0, 0]
+            } else {
+                [0, 255, 0, 255]
+            };
+            assert_eq!(*texel, expected, "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc3_alpha_and_four_colors() {
+        // 8 alpha values from 255 down to 0, and four colours even with c0 <= c1
+        let block = [
+            &[255, 0][..],
+            &CHANNEL_INDICES,
+            &color_block(BLUE, RED, COLOR_INDICES),
+        ]
+        .concat();
+        let texels = decode(F::Bc3RgbaUnorm, &block);
+        let colors = [[0, 0, 255], [255, 0, 0], [85, 0, 170], [170, 0, 85]];
+        let alphas = [255, 0, 219, 182, 146, 109, 73, 36];
+        for (i, texel) in texels.iter().enumerate() {
+            let [r, g, b] = colors[i % 4];
+            assert_eq!(*texel, [r, g, b, alphas[i % 8]], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc5_both_channel_modes() {
+        // red has e0 <= e1, 4 interpolated values then 0 and 255, green e0 > e1 with 6
+        let block = [
+            &[0, 255][..],
+            &CHANNEL_INDICES,
+            &[200, 100],
+            &CHANNEL_INDICES_REVERSED,
+        ]
+        .concat();
+        let texels = decode(F::Bc5RgUnorm, &block);
+        let reds = [0, 255, 51, 102, 153, 204, 0, 255];
+        let greens = [200, 100, 186, 171, 157, 143, 129, 114];
+        for (i, texel) in texels.iter().enumerate() {
+            let expected = [reds[i % 8], greens[7 - i % 8], 0, 255];
+            assert_eq!(*texel, expected, "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc2_explicit_alpha() {
+        // texel i has 4 bit alpha i, and the colours ignore c0 <= c1
+        let alphas: Vec<u8> = (0..8).map(|i| (2 * i) | (2 * i + 1) << 4).collect();
+        let block = [&alphas[..], &color_block(BLUE, RED, COLOR_INDICES)].concat();
+        let texels = decode(F::Bc2RgbaUnorm, &block);
+        let colors = [[0, 0, 255], [255, 0, 0], [85, 0, 170], [170, 0, 85]];
+        for (i, texel) in texels.iter().enumerate() {
+            let [r, g, b] = colors[i % 4];
+            assert_eq!(*texel, [r, g, b, i as u8 * 17], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc4_unorm_and_snorm() {
+        let block = [&[0, 255][..], &CHANNEL_INDICES].concat();
+        let texels = decode(F::Bc4RUnorm, &block);
+        let reds = [0, 255, 51, 102, 153, 204, 0, 255];
+        for (i, texel) in texels.iter().enumerate() {
+            assert_eq!(*texel, [reds[i % 8], 0, 0, 255], "texel {}", i);
+        }
+
+        // -128 clamps to -127, and alpha is 127, one in snorm
+        let block = [&[0x80, 0x7f][..], &CHANNEL_INDICES].concat();
+        let texels = decode(F::Bc4RSnorm, &block);
+        let reds: [i8; 8] = [-127, 127, -76, -25, 25, 76, -127, 127];
+        for (i, texel) in texels.iter().enumerate() {
+            assert_eq!(*texel, [reds[i % 8] as u8, 0, 0, 127], "texel {}", i);
+        }
+    }
+
+    // (value, bit count) fields packed LSB first into a 128 bit block
+    fn pack(fields: &[(u32, u32)]) -> Vec<u8> {
+        let mut bits = 0u128;
+        let mut position = 0;
+        for &(value, count) in fields {
+            assert!(count == 32 || value >> count == 0);
+            bits |= (value as u128) << position;
+            position += count;
+        }
+        assert_eq!(position, 128);
+        bits.to_le_bytes().to_vec()
+    }
+
+    // one index field per texel, anchors lose their top bit
+    fn indices(bits: u32, anchors: &[usize], index: impl Fn(usize) -> u32) -> Vec<(u32, u32)> {
+        (0..16)
+            .map(|i| {
+                let count = if anchors.contains(&i) { bits - 1 } else { bits };
+                (index(i), count)
+            })
+            .collect()
+    }
+
+    fn channel(values: &[u32], bits: u32) -> Vec<(u32, u32)> {
+        values.iter().map(|&value| (value, bits)).collect()
+    }
+
+    #[test]
+    fn bc7_mode_0_three_subsets_and_endpoint_p_bits() {
+        // partition 0, every texel picks its subset's first endpoint except texel 1
+        let block = pack(
+            &[
+                &[(1, 1), (0, 4)][..],
+                &channel(&[15, 0, 0, 0, 0, 0], 4),
+                &channel(&[0, 0, 15, 0, 0, 0], 4),
+                &channel(&[0, 0, 0, 0, 15, 0], 4),
+                &channel(&[1, 0, 0, 0, 1, 0], 1),
+                &indices(3, &[0, 3, 15], |i| if i == 1 { 7 } else { 0 }),
+            ]
+            .concat(),
+        );
+        let texels = decode(F::Bc7RgbaUnorm, &block);
+        let subsets = [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2];
+        let colors = [[255, 8, 8, 255], [0, 247, 0, 255], [8, 8, 255, 255]];
+        for (i, texel) in texels.iter().enumerate() {
+            let expected = if i == 1 {
+                [0, 0, 0, 255]
+            } else {
+                colors[subsets[i]]
+            };
+            assert_eq!(*texel, expected, "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_1_two_subsets_and_shared_p_bits() {
+        // partition 13 splits the block in half, the second anchor is texel 15
+        let block = pack(
+            &[
+                &[(2, 2), (13, 6)][..],
+                &channel(&[0, 63, 63, 0], 6),
+                &channel(&[0, 63, 0, 0], 6),
+                &channel(&[0, 63, 0, 63], 6),
+                &channel(&[0, 1], 1),
+                &indices(
+                    3,
+                    &[0, 15],
+                    |i| if i < 8 { i as u32 } else { 15 - i as u32 },
+                ),
+            ]
+            .concat(),
+        );
+        let texels = decode(F::Bc7RgbaUnorm, &block);
+        let grays = [0, 36, 71, 107, 146, 182, 217, 253];
+        let second = [
+            [2, 2, 255],
+            [38, 2, 219],
+            [73, 2, 184],
+            [109, 2, 148],
+            [148, 2, 109],
+            [184, 2, 73],
+            [219, 2, 38],
+            [255, 2, 2],
+        ];
+        for (i, texel) in texels.iter().enumerate() {
+            let expected = if i < 8 {
+                [grays[i], grays[i], grays[i], 255]
+            } else {
+                let [r, g, b] = second[i - 8];
+                [r, g, b, 255]
+            };
+            assert_eq!(*texel, expected, "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_4_index_selection() {
+        // the 3 bit indices drive colour and the 2 bit ones alpha
+        let block = pack(
+            &[
+                &[(16, 5), (0, 2), (1, 1)][..],
+                &channel(&[0, 31, 0, 31, 0, 31], 5),
+                &channel(&[0, 63], 6),
+                &indices(2, &[0], |_| 1),
+                &indices(3, &[0], |i| i as u32 % 8),
+            ]
+            .concat(),
+        );
+        let texels = decode(F::Bc7RgbaUnorm, &block);
+        let grays = [0, 36, 72, 108, 147, 183, 219, 255];
+        for (i, texel) in texels.iter().enumerate() {
+            let gray = grays[i % 8];
+            assert_eq!(*texel, [gray, gray, gray, 84], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_5_rotation() {
+        // rotation 1 swaps red and alpha after interpolating
+        let block = pack(
+            &[
+                &[(32, 6), (1, 2)][..],
+                &channel(&[0, 127, 0, 127, 0, 127], 7),
+                &channel(&[255, 0], 8),
+                &indices(2, &[0], |i| i as u32 % 4),
+                &indices(2, &[0], |_| 1),
+            ]
+            .concat(),
+        );
+        let texels = decode(F::Bc7RgbaUnorm, &block);
+        let grays = [0, 84, 171, 255];
+        for (i, texel) in texels.iter().enumerate() {
+            let gray = grays[i % 4];
+            assert_eq!(*texel, [171, gray, gray, gray], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_6_single_subset_with_p_bits() {
+        let block = pack(
+            &[
+                &[(64, 7)][..],
+                &channel(&[0, 127, 64, 64, 127, 0, 127, 0], 7),
+                &channel(&[1, 0], 1),
+                &indices(4, &[0], |i| i as u32),
+            ]
+            .concat(),
+        );
+        let texels = decode(F::Bc7RgbaUnorm, &block);
+        assert_eq!(texels[0], [1, 129, 255, 255]);
+        assert_eq!(texels[8], [135, 128, 120, 120]);
+        assert_eq!(texels[15], [254, 128, 0, 0]);
+    }
+
+    #[test]
+    fn bc7_mode_7_two_subsets_with_alpha() {
+        let block = pack(
+            &[
+                &[(128, 8), (13, 6)][..],
+                &channel(&[31, 0, 0, 0], 5),
+                &channel(&[0, 0, 31, 0], 5),
+                &channel(&[0, 31, 0, 0], 5),
+                &channel(&[31, 0, 15, 0], 5),
+                &channel(&[1, 0, 0, 1], 1),
+                &indices(2, &[0, 15], |i| match i {
+                    0..=7 => i as u32 % 4,
+                    15 => 1,
+                    _ => 3,
+                }),
+            ]
+            .concat(),
+        );
+        let texels = decode(F::Bc7RgbaUnorm, &block);
+        let first = [
+            [255, 4, 4, 255],
+            [171, 3, 85, 171],
+            [84, 1, 170, 84],
+            [0, 0, 251, 0],
+        ];
+        for (i, texel) in texels.iter().enumerate() {
+            let expected = match i {
+                0..=7 => first[i % 4],
+                15 => [1, 170, 1, 83],
+                _ => [4, 4, 4, 4],
+            };
+            assert_eq!(*texel, expected, "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn bc7_reserved_mode_is_black() {
+        assert!(decode(F::Bc7RgbaUnorm, &[0; 16])
+            .iter()
+            .all(|texel| *texel == [0; 4]));
+    }
+
+    fn decode_halves(format: wgpu::TextureFormat, block: &[u8]) -> Vec<[u16; 4]> {
+        let (_, pixels) = decompress(format, 4, 4, block).unwrap();
+        pixels
+            .chunks_exact(8)
+            .map(|texel| {
+                let half = |i: usize| u16::from_le_bytes([texel[2 * i], texel[2 * i + 1]]);
+                [half(0), half(1), half(2), half(3)]
+            })
+            .collect()
+    }
+
+    // mode 11 (0b00011) stores both endpoints as plain 10 bit values
+    fn bc6h_mode_11(w: [u32; 3], x: [u32; 3], index: impl Fn(usize) -> u32) -> Vec<u8> {
+        pack(
+            &[
+                &[(0b00011, 5)][..],
+                &channel(&w, 10),
+                &channel(&x, 10),
+                &indices(4, &[0], index),
+            ]
+            .concat(),
+        )
+    }
+
+    #[test]
+    fn bc6h_unsigned_mode_11() {
+        let block = bc6h_mode_11([0, 512, 1023], [1023, 512, 0], |i| i as u32);
+        let texels = decode_halves(F::Bc6hRgbUfloat, &block);
+        // 1023 unquantizes to the largest finite half, 0x7bff
+        assert_eq!(texels[0], [0x0000, 0x3e0f, 0x7bff, 0x3c00]);
+        assert_eq!(texels[8], [0x41df, 0x3e0f, 0x3a20, 0x3c00]);
+        assert_eq!(texels[15], [0x7bff, 0x3e0f, 0x0000, 0x3c00]);
+    }
+
+    #[test]
+    fn bc6h_signed_mode_11() {
+        // -1, -512 and 100 against 511, 0 and -1, as 10 bit two's complement
+        let block = bc6h_mode_11([0x3ff, 0x200, 100], [0x1ff, 0, 0x3ff], |i| {
+            if i < 8 {
+                0
+            } else {
+                15
+            }
+        });
+        let texels = decode_halves(F::Bc6hRgbSfloat, &block);
+        for (i, texel) in texels.iter().enumerate() {
+            let expected = if i < 8 {
+                [0x805d, 0xfbff, 0x1857, 0x3c00]
+            } else {
+                [0x7bff, 0x0000, 0x805d, 0x3c00]
+            };
+            assert_eq!(*texel, expected, "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn rejects_short_data() {
+        assert!(decompress(F::Bc1RgbaUnorm, 8, 4, &[0; 8]).is_err());
+    }
+}
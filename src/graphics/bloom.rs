@@ -0,0 +1,287 @@
+use wgpu::util::DeviceExt;
+
+use super::post_process::{PostEffect, PostFrame};
+use super::scene::{BloomDescription, PostEffectKind};
+use super::texture::{Filtering, SamplerOptions};
+use super::tonemapping::Tonemapper;
+
+// Downsamples the frame into a half resolution mip chain and blurs it back up, see bloom.wgsl
+pub struct Bloom {
+    mip_views: Vec<wgpu::TextureView>,
+    // mip i is downsampled from mip i - 1, mip 0 from the input
+    downsample_bind_groups: Vec<wgpu::BindGroup>,
+    // mip i is blended with the upsampled mip i + 1
+    upsample_bind_groups: Vec<wgpu::BindGroup>,
+
+    settings_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    downsample_first_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomSettings {
+    filter_radius: f32,
+    strength: f32,
+    _padding: [f32; 2],
+}
+
+impl Bloom {
+    const MAX_MIP_COUNT: u32 = 6;
+
+    pub fn make(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        description: &BloomDescription,
+    ) -> Self {
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Settings Buffer"),
+            contents: bytemuck::cast_slice(&[BloomSettings {
+                filter_radius: description.filter_radius,
+                strength: description.strength,
+                _padding: [0.0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let sampler = SamplerOptions {
+            filtering: Filtering::Bilinear,
+            ..Default::default()
+        }
+        .create_sampler(device);
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                texture_entry(3),
+            ],
+            label: Some("bloom_bind_group_layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bloom Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../bloom.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("BloomRootSignature"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("BloomPSO"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: Tonemapper::HDR_FORMAT,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+
+        let mut bloom = Self {
+            mip_views: Vec::new(),
+            downsample_bind_groups: Vec::new(),
+            upsample_bind_groups: Vec::new(),
+            downsample_first_pipeline: create_pipeline(
+                "fs_downsample_first",
+                wgpu::BlendState::REPLACE,
+            ),
+            downsample_pipeline: create_pipeline("fs_downsample", wgpu::BlendState::REPLACE),
+            upsample_pipeline: create_pipeline("fs_upsample", additive),
+            composite_pipeline: create_pipeline("fs_composite", wgpu::BlendState::REPLACE),
+            settings_buffer,
+            sampler,
+            bind_group_layout,
+        };
+        bloom.resize(device, config);
+        bloom
+    }
+
+    fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        source: &wgpu::TextureView,
+        bloom: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(bloom),
+                },
+            ],
+            label: Some("bloom_bind_group"),
+        })
+    }
+
+    fn draw(
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Bloom Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl PostEffect for Bloom {
+    fn kind(&self) -> PostEffectKind {
+        PostEffectKind::Bloom
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        let width = (config.width / 2).max(1);
+        let height = (config.height / 2).max(1);
+        // stop before the smallest side drops below a couple of pixels
+        let mip_count = (width.min(height).max(1).ilog2()).clamp(1, Self::MAX_MIP_COUNT);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("bloom_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Tonemapper::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        self.mip_views = (0..mip_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    base_mip_level: mip,
+                    mip_level_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        // the unused second texture must not be the target either
+        self.downsample_bind_groups = (1..mip_count as usize)
+            .map(|mip| {
+                self.create_bind_group(device, &self.mip_views[mip - 1], &self.mip_views[mip - 1])
+            })
+            .collect();
+        self.upsample_bind_groups = (0..mip_count as usize - 1)
+            .map(|mip| {
+                self.create_bind_group(device, &self.mip_views[mip + 1], &self.mip_views[mip + 1])
+            })
+            .collect();
+    }
+
+    fn render(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, frame: &PostFrame) {
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        let first = self.create_bind_group(device, frame.input, frame.input);
+        Self::draw(
+            encoder,
+            &self.downsample_first_pipeline,
+            &first,
+            &self.mip_views[0],
+            clear,
+        );
+        for (mip, bind_group) in self.downsample_bind_groups.iter().enumerate() {
+            Self::draw(
+                encoder,
+                &self.downsample_pipeline,
+                bind_group,
+                &self.mip_views[mip + 1],
+                clear,
+            );
+        }
+        for (mip, bind_group) in self.upsample_bind_groups.iter().enumerate().rev() {
+            Self::draw(
+                encoder,
+                &self.upsample_pipeline,
+                bind_group,
+                &self.mip_views[mip],
+                wgpu::LoadOp::Load,
+            );
+        }
+
+        let composite = self.create_bind_group(device, frame.input, &self.mip_views[0]);
+        Self::draw(
+            encoder,
+            &self.composite_pipeline,
+            &composite,
+            frame.output,
+            clear,
+        );
+    }
+}
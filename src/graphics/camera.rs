@@ -1,10 +1,15 @@
 use wgpu::util::DeviceExt;
 
+use super::culling::Frustum;
+use super::picking::Ray;
+
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     target: cgmath::Point3<f32>,
     up: cgmath::Vector3<f32>,
     projector: Box<dyn Projector>,
+    // sub-pixel offset in NDC, moved every frame by temporal anti-aliasing
+    jitter: cgmath::Vector2<f32>,
     buffer: Option<wgpu::Buffer>,
 }
 #[rustfmt::skip]
@@ -16,18 +21,66 @@ const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 );
 
 impl Camera {
-    fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+    pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        // shifting clip space by jitter * w moves the whole image by jitter after the divide
+        let jitter = cgmath::Matrix4::from_translation(self.jitter.extend(0.0));
+        jitter * self.build_unjittered_view_projection_matrix()
+    }
+
+    pub fn build_unjittered_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
         let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
         let proj = self.projector.get_projection_matrix();
         return OPENGL_TO_WGPU_MATRIX * proj * view;
     }
 
+    pub fn get_frustum(&self) -> Frustum {
+        Frustum::from_matrix(&self.build_view_projection_matrix())
+    }
+
+    // The fraction of the screen height a sphere covers, shrinking with distance under a
+    // perspective projection
+    pub fn get_screen_size(&self, center: cgmath::Vector3<f32>, radius: f32) -> f32 {
+        let clip = self.build_unjittered_view_projection_matrix() * center.extend(1.0);
+        let scale = self.projector.get_projection_matrix()[1][1];
+        radius * scale / clip.w.max(f32::EPSILON)
+    }
+
+    // Through the cursor, in pixels from the top left of a width by height window. Starts on
+    // the near plane.
+    pub fn get_ray(&self, cursor: cgmath::Point2<f32>, width: u32, height: u32) -> Ray {
+        use cgmath::SquareMatrix;
+        let x = 2.0 * cursor.x / width.max(1) as f32 - 1.0;
+        let y = 1.0 - 2.0 * cursor.y / height.max(1) as f32;
+        let inverse = self
+            .build_unjittered_view_projection_matrix()
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity);
+        let unproject =
+            |z: f32| cgmath::Point3::from_homogeneous(inverse * cgmath::vec4(x, y, z, 1.0));
+        let near = unproject(0.0);
+        Ray::make(near, unproject(1.0) - near)
+    }
+
+    pub fn set_jitter(&mut self, jitter: cgmath::Vector2<f32>) {
+        self.jitter = jitter;
+    }
+
     pub fn get_view_projection_matrix(&self) -> CameraUniform {
         let mut uniform = CameraUniform::new();
         uniform.update_view_proj(&self);
         uniform
     }
 
+    pub fn get_forward(&self) -> cgmath::Vector3<f32> {
+        use cgmath::InnerSpace;
+        (self.target - self.eye).normalize()
+    }
+
+    pub fn get_right(&self) -> cgmath::Vector3<f32> {
+        use cgmath::InnerSpace;
+        self.get_forward().cross(self.up).normalize()
+    }
+
     pub fn get_view_projection_buffer(&self) -> Option<&wgpu::Buffer> {
         self.buffer.as_ref()
     }
@@ -78,6 +131,7 @@ impl Camera {
             up,
             buffer: None,
             projector: Box::new(perspective),
+            jitter: cgmath::Vector2::new(0.0, 0.0),
         }
     }
 
@@ -107,6 +161,7 @@ impl Camera {
             up,
             buffer: None,
             projector: Box::new(ortho),
+            jitter: cgmath::Vector2::new(0.0, 0.0),
         }
     }
 }
@@ -119,6 +174,10 @@ pub struct CameraUniform {
     // We can't use cgmath with bytemuck directly so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
+    // Lighting needs the eye position in world space, padded to a vec4
+    view_position: [f32; 4],
+    // Full screen passes turn clip space back into world space directions with this
+    inv_view_proj: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
@@ -126,11 +185,20 @@ impl CameraUniform {
         use cgmath::SquareMatrix;
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            view_position: [0.0; 4],
+            inv_view_proj: cgmath::Matrix4::identity().into(),
         }
     }
 
     fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into();
+        use cgmath::SquareMatrix;
+        let view_proj = camera.build_view_projection_matrix();
+        self.view_proj = view_proj.into();
+        self.view_position = camera.eye.to_homogeneous().into();
+        self.inv_view_proj = view_proj
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity)
+            .into();
     }
 }
 
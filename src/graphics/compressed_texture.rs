@@ -0,0 +1,263 @@
+use anyhow::*;
+use std::path::Path;
+
+use super::bcn;
+
+// A block compressed image with a pre-built mip chain, as shipped by the asset pipeline.
+// Each level holds tightly packed rows of blocks, largest level first.
+pub struct CompressedImage {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    // Only the file extension decides the container, everything else goes through `image`
+    pub fn is_compressed_path(path: &Path) -> bool {
+        matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("ktx2" | "KTX2" | "dds" | "DDS")
+        )
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let image = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ktx2" | "KTX2") => Self::from_ktx2(&bytes),
+            _ => Self::from_dds(&bytes),
+        };
+        image.with_context(|| format!("failed to load {}", path.display()))
+    }
+
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self> {
+        let reader = ktx2::Reader::new(bytes).map_err(|e| anyhow!("invalid KTX2 file: {:?}", e))?;
+        let header = reader.header();
+        if let Some(scheme) = header.supercompression_scheme {
+            bail!("supercompressed KTX2 ({:?}) is not supported", scheme);
+        }
+        if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+            bail!("only single 2D KTX2 images are supported");
+        }
+        let format = header
+            .format
+            .and_then(ktx2_format)
+            .with_context(|| format!("unsupported KTX2 format {:?}", header.format))?;
+
+        Ok(CompressedImage {
+            format,
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            levels: reader.levels().map(|level| level.to_vec()).collect(),
+        })
+    }
+
+    pub fn from_dds(bytes: &[u8]) -> Result<Self> {
+        let dds = ddsfile::Dds::read(bytes).map_err(|e| anyhow!("invalid DDS file: {}", e))?;
+        if dds.get_depth() > 1 || dds.get_num_array_layers() > 1 {
+            bail!("only single 2D DDS images are supported");
+        }
+        let format = dds
+            .get_dxgi_format()
+            .and_then(dxgi_format)
+            .or_else(|| dds.get_d3d_format().and_then(d3d_format))
+            .context("unsupported DDS format")?;
+
+        // DDS stores the whole chain back to back, split it by the size of each level
+        let (width, height) = (dds.get_width(), dds.get_height());
+        let mut data = dds
+            .get_data(0)
+            .map_err(|e| anyhow!("invalid DDS file: {}", e))?;
+        let mut levels = Vec::new();
+        for level in 0..dds.get_num_mipmap_levels() {
+            let size = level_size(format, (width >> level).max(1), (height >> level).max(1));
+            if data.len() < size {
+                bail!("DDS data ends in mip level {}", level);
+            }
+            let (level, rest) = data.split_at(size);
+            levels.push(level.to_vec());
+            data = rest;
+        }
+
+        Ok(CompressedImage {
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    // Formats that come in both flavours follow the colour space the texture is loaded with,
+    // so an albedo map stored as UNORM still gets decoded from sRGB
+    pub fn with_srgb(mut self, srgb: bool) -> Self {
+        self.format = set_srgb(self.format, srgb);
+        self
+    }
+
+    pub fn level_size(&self, level: u32) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    // The device can't sample the format: decode every level on the CPU instead
+    pub fn decompress(&self) -> Result<(wgpu::TextureFormat, Vec<Vec<u8>>)> {
+        let mut format = self.format;
+        let mut levels = Vec::new();
+        for (level, data) in self.levels.iter().enumerate() {
+            let (width, height) = self.level_size(level as u32);
+            let (decoded_format, pixels) = bcn::decompress(self.format, width, height, data)?;
+            format = decoded_format;
+            levels.push(pixels);
+        }
+        Ok((format, levels))
+    }
+}
+
+// Bytes of one mip level, partial blocks at the edges are stored whole
+pub fn level_size(format: wgpu::TextureFormat, width: u32, height: u32) -> usize {
+    let info = format.describe();
+    let (block_width, block_height) = (
+        info.block_dimensions.0 as u32,
+        info.block_dimensions.1 as u32,
+    );
+    let blocks = width.div_ceil(block_width) * height.div_ceil(block_height);
+    blocks as usize * info.block_size as usize
+}
+
+fn ktx2_format(format: ktx2::Format) -> Option<wgpu::TextureFormat> {
+    use ktx2::Format as K;
+    use wgpu::TextureFormat as F;
+    Some(match format {
+        K::R8G8B8A8_UNORM => F::Rgba8Unorm,
+        K::R8G8B8A8_SRGB => F::Rgba8UnormSrgb,
+        K::R16G16B16A16_SFLOAT => F::Rgba16Float,
+        // the alpha-less BC1 variant decodes the same, its transparent index just goes unused
+        K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => F::Bc1RgbaUnorm,
+        K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => F::Bc1RgbaUnormSrgb,
+        K::BC2_UNORM_BLOCK => F::Bc2RgbaUnorm,
+        K::BC2_SRGB_BLOCK => F::Bc2RgbaUnormSrgb,
+        K::BC3_UNORM_BLOCK => F::Bc3RgbaUnorm,
+        K::BC3_SRGB_BLOCK => F::Bc3RgbaUnormSrgb,
+        K::BC4_UNORM_BLOCK => F::Bc4RUnorm,
+        K::BC4_SNORM_BLOCK => F::Bc4RSnorm,
+        K::BC5_UNORM_BLOCK => F::Bc5RgUnorm,
+        K::BC5_SNORM_BLOCK => F::Bc5RgSnorm,
+        K::BC6H_UFLOAT_BLOCK => F::Bc6hRgbUfloat,
+        K::BC6H_SFLOAT_BLOCK => F::Bc6hRgbSfloat,
+        K::BC7_UNORM_BLOCK => F::Bc7RgbaUnorm,
+        K::BC7_SRGB_BLOCK => F::Bc7RgbaUnormSrgb,
+        K::ETC2_R8G8B8_UNORM_BLOCK => F::Etc2Rgb8Unorm,
+        K::ETC2_R8G8B8_SRGB_BLOCK => F::Etc2Rgb8UnormSrgb,
+        K::ETC2_R8G8B8A1_UNORM_BLOCK => F::Etc2Rgb8A1Unorm,
+        K::ETC2_R8G8B8A1_SRGB_BLOCK => F::Etc2Rgb8A1UnormSrgb,
+        K::ETC2_R8G8B8A8_UNORM_BLOCK => F::Etc2Rgba8Unorm,
+        K::ETC2_R8G8B8A8_SRGB_BLOCK => F::Etc2Rgba8UnormSrgb,
+        K::EAC_R11_UNORM_BLOCK => F::EacR11Unorm,
+        K::EAC_R11_SNORM_BLOCK => F::EacR11Snorm,
+        K::EAC_R11G11_UNORM_BLOCK => F::EacRg11Unorm,
+        K::EAC_R11G11_SNORM_BLOCK => F::EacRg11Snorm,
+        _ => return astc_format(format.0.get()),
+    })
+}
+
+// The Vulkan ASTC formats come in UNORM/SRGB pairs ordered by block size
+fn astc_format(vk_format: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::AstcBlock as B;
+    const ASTC_4X4_UNORM_BLOCK: u32 = 157;
+    const BLOCKS: [B; 14] = [
+        B::B4x4,
+        B::B5x4,
+        B::B5x5,
+        B::B6x5,
+        B::B6x6,
+        B::B8x5,
+        B::B8x6,
+        B::B8x8,
+        B::B10x5,
+        B::B10x6,
+        B::B10x8,
+        B::B10x10,
+        B::B12x10,
+        B::B12x12,
+    ];
+    let index = vk_format.checked_sub(ASTC_4X4_UNORM_BLOCK)? as usize;
+    let block = *BLOCKS.get(index / 2)?;
+    let channel = if index.is_multiple_of(2) {
+        wgpu::AstcChannel::Unorm
+    } else {
+        wgpu::AstcChannel::UnormSrgb
+    };
+    Some(wgpu::TextureFormat::Astc { block, channel })
+}
+
+fn dxgi_format(format: ddsfile::DxgiFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::DxgiFormat as D;
+    use wgpu::TextureFormat as F;
+    Some(match format {
+        D::R8G8B8A8_UNorm => F::Rgba8Unorm,
+        D::R8G8B8A8_UNorm_sRGB => F::Rgba8UnormSrgb,
+        D::R16G16B16A16_Float => F::Rgba16Float,
+        D::BC1_Typeless | D::BC1_UNorm => F::Bc1RgbaUnorm,
+        D::BC1_UNorm_sRGB => F::Bc1RgbaUnormSrgb,
+        D::BC2_Typeless | D::BC2_UNorm => F::Bc2RgbaUnorm,
+        D::BC2_UNorm_sRGB => F::Bc2RgbaUnormSrgb,
+        D::BC3_Typeless | D::BC3_UNorm => F::Bc3RgbaUnorm,
+        D::BC3_UNorm_sRGB => F::Bc3RgbaUnormSrgb,
+        D::BC4_Typeless | D::BC4_UNorm => F::Bc4RUnorm,
+        D::BC4_SNorm => F::Bc4RSnorm,
+        D::BC5_Typeless | D::BC5_UNorm => F::Bc5RgUnorm,
+        D::BC5_SNorm => F::Bc5RgSnorm,
+        D::BC6H_Typeless | D::BC6H_UF16 => F::Bc6hRgbUfloat,
+        D::BC6H_SF16 => F::Bc6hRgbSfloat,
+        D::BC7_Typeless | D::BC7_UNorm => F::Bc7RgbaUnorm,
+        D::BC7_UNorm_sRGB => F::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+// Legacy DDS files without the DX10 header
+fn d3d_format(format: ddsfile::D3DFormat) -> Option<wgpu::TextureFormat> {
+    use ddsfile::D3DFormat as D;
+    use wgpu::TextureFormat as F;
+    Some(match format {
+        D::DXT1 => F::Bc1RgbaUnorm,
+        D::DXT2 | D::DXT3 => F::Bc2RgbaUnorm,
+        D::DXT4 | D::DXT5 => F::Bc3RgbaUnorm,
+        D::A8B8G8R8 => F::Rgba8Unorm,
+        _ => return None,
+    })
+}
+
+fn set_srgb(format: wgpu::TextureFormat, srgb: bool) -> wgpu::TextureFormat {
+    use wgpu::TextureFormat as F;
+    let (unorm, srgb_format) = match format {
+        F::Rgba8Unorm | F::Rgba8UnormSrgb => (F::Rgba8Unorm, F::Rgba8UnormSrgb),
+        F::Bc1RgbaUnorm | F::Bc1RgbaUnormSrgb => (F::Bc1RgbaUnorm, F::Bc1RgbaUnormSrgb),
+        F::Bc2RgbaUnorm | F::Bc2RgbaUnormSrgb => (F::Bc2RgbaUnorm, F::Bc2RgbaUnormSrgb),
+        F::Bc3RgbaUnorm | F::Bc3RgbaUnormSrgb => (F::Bc3RgbaUnorm, F::Bc3RgbaUnormSrgb),
+        F::Bc7RgbaUnorm | F::Bc7RgbaUnormSrgb => (F::Bc7RgbaUnorm, F::Bc7RgbaUnormSrgb),
+        F::Etc2Rgb8Unorm | F::Etc2Rgb8UnormSrgb => (F::Etc2Rgb8Unorm, F::Etc2Rgb8UnormSrgb),
+        F::Etc2Rgb8A1Unorm | F::Etc2Rgb8A1UnormSrgb => (F::Etc2Rgb8A1Unorm, F::Etc2Rgb8A1UnormSrgb),
+        F::Etc2Rgba8Unorm | F::Etc2Rgba8UnormSrgb => (F::Etc2Rgba8Unorm, F::Etc2Rgba8UnormSrgb),
+        F::Astc {
+            block,
+            channel: wgpu::AstcChannel::Unorm | wgpu::AstcChannel::UnormSrgb,
+        } => (
+            F::Astc {
+                block,
+                channel: wgpu::AstcChannel::Unorm,
+            },
+            F::Astc {
+                block,
+                channel: wgpu::AstcChannel::UnormSrgb,
+            },
+        ),
+        // data formats (BC4-BC6H, EAC, float) have no sRGB variant
+        _ => return format,
+    };
+    if srgb {
+        srgb_format
+    } else {
+        unorm
+    }
+}
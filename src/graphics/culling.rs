@@ -0,0 +1,334 @@
+use cgmath::{InnerSpace, Matrix};
+
+use super::instance::{DrawRange, InstanceRaw, InstanceSet, RenderGroup};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CullingMode {
+    // every pass draws every instance at full detail
+    Off,
+    // CulledInstances, a compacted buffer per pass made on the CPU
+    Cpu,
+    // GpuCulledInstances, culled by a compute pass and drawn indirectly
+    Gpu,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: cgmath::Vector3<f32>,
+    pub max: cgmath::Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn make(min: cgmath::Vector3<f32>, max: cgmath::Vector3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    // None for no points
+    pub fn from_points(points: impl IntoIterator<Item = cgmath::Vector3<f32>>) -> Option<Self> {
+        points.into_iter().fold(None, |bounds, point| {
+            Some(match bounds {
+                None => Self::make(point, point),
+                Some(Self { min, max }) => Self::make(
+                    cgmath::vec3(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z)),
+                    cgmath::vec3(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z)),
+                ),
+            })
+        })
+    }
+
+    // Grown by margin on every side, for meshes that deform past their bind pose
+    pub fn expand(self, margin: f32) -> Self {
+        let margin = cgmath::vec3(margin, margin, margin);
+        Self::make(self.min - margin, self.max + margin)
+    }
+
+    pub fn get_center(&self) -> cgmath::Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn get_extent(&self) -> cgmath::Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    // The box around the transformed box, it only ever grows
+    pub fn transform(&self, matrix: &cgmath::Matrix4<f32>) -> Self {
+        let center = (matrix * self.get_center().extend(1.0)).truncate();
+        let extent = self.get_extent();
+        let row_extent = |row: cgmath::Vector4<f32>| {
+            row.x.abs() * extent.x + row.y.abs() * extent.y + row.z.abs() * extent.z
+        };
+        let extent = cgmath::vec3(
+            row_extent(matrix.row(0)),
+            row_extent(matrix.row(1)),
+            row_extent(matrix.row(2)),
+        );
+        Self::make(center - extent, center + extent)
+    }
+}
+
+// Planes pointing inwards, xyz the normal and w the distance, in the order left, right,
+// bottom, top, near, far
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frustum {
+    planes: [cgmath::Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // From a view projection matrix with wgpu's 0 to 1 clip depth, like
+    // Camera::build_view_projection_matrix
+    pub fn from_matrix(view_proj: &cgmath::Matrix4<f32>) -> Self {
+        let (x, y, z, w) = (
+            view_proj.row(0),
+            view_proj.row(1),
+            view_proj.row(2),
+            view_proj.row(3),
+        );
+        let normalize = |plane: cgmath::Vector4<f32>| {
+            let length = plane.truncate().magnitude();
+            if length > 0.0 {
+                plane / length
+            } else {
+                plane
+            }
+        };
+        Self {
+            planes: [w + x, w - x, w + y, w - y, z, w - z].map(normalize),
+        }
+    }
+
+    pub fn get_planes(&self) -> [[f32; 4]; 6] {
+        self.planes.map(Into::into)
+    }
+
+    // False only if the box is entirely outside one of the planes, boxes near a corner of the
+    // frustum can be kept without being visible
+    pub fn intersects(&self, bounds: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the normal
+            let furthest = |normal: f32, min: f32, max: f32| if normal >= 0.0 { max } else { min };
+            let corner = cgmath::vec3(
+                furthest(plane.x, bounds.min.x, bounds.max.x),
+                furthest(plane.y, bounds.min.y, bounds.max.y),
+                furthest(plane.z, bounds.min.z, bounds.max.z),
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+// World space bounds of every instance in the set, in buffer order. Valid until the set
+// changes, so it's computed once a frame and shared by the passes.
+pub fn compute_world_bounds(
+    instance_set: &InstanceSet,
+    local_bounds: impl Fn(RenderGroup) -> Aabb,
+) -> Vec<Aabb> {
+    let raw = instance_set.get_raw();
+    let mut bounds = Vec::with_capacity(raw.len());
+    for range in instance_set.draw_ranges() {
+        let local = local_bounds(range.group);
+        bounds.extend(
+            raw[range.instances.start as usize..range.instances.end as usize]
+                .iter()
+                .map(|instance| local.transform(&instance.model.into())),
+        );
+    }
+    bounds
+}
+
+// The instances one pass can see, copied into a buffer of their own with draw ranges to match
+pub struct CulledInstances {
+    label: &'static str,
+    buffer: Option<wgpu::Buffer>,
+    // in instances, doubles like the InstanceSet's
+    capacity: usize,
+    draw_ranges: Vec<DrawRange>,
+    visible: Vec<InstanceRaw>,
+}
+
+impl CulledInstances {
+    const MIN_CAPACITY: usize = 16;
+
+    pub fn make(label: &'static str) -> Self {
+        Self {
+            label,
+            buffer: None,
+            capacity: 0,
+            draw_ranges: Vec::new(),
+            visible: Vec::new(),
+        }
+    }
+
+    // Keeps the instances whose bounds touch the frustum, bounds from compute_world_bounds and
+    // levels from LodSelector. Each draw range is split into one range per level.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instance_set: &InstanceSet,
+        bounds: &[Aabb],
+        levels: &[u32],
+        frustum: &Frustum,
+    ) {
+        let raw = instance_set.get_raw();
+        self.visible.clear();
+        self.draw_ranges.clear();
+        for range in instance_set.draw_ranges() {
+            let instances = range.instances.start as usize..range.instances.end as usize;
+            let level_count = levels[instances.clone()]
+                .iter()
+                .max()
+                .map_or(0, |&max| max + 1);
+            for lod in 0..level_count {
+                let start = self.visible.len() as u32;
+                for index in instances.clone() {
+                    if levels[index] == lod && frustum.intersects(&bounds[index]) {
+                        self.visible.push(raw[index]);
+                    }
+                }
+                let end = self.visible.len() as u32;
+                if start < end {
+                    self.draw_ranges.push(DrawRange {
+                        group: range.group,
+                        material_index: range.material_index,
+                        lod,
+                        instances: start..end,
+                    });
+                }
+            }
+        }
+
+        if self.buffer.is_none() || self.visible.len() > self.capacity {
+            let mut capacity = self.capacity.max(Self::MIN_CAPACITY);
+            while capacity < self.visible.len() {
+                capacity *= 2;
+            }
+            self.buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(self.label),
+                size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+            self.capacity = capacity;
+        }
+        if !self.visible.is_empty() {
+            queue.write_buffer(
+                self.buffer.as_ref().unwrap(),
+                0,
+                bytemuck::cast_slice(&self.visible),
+            );
+        }
+    }
+
+    pub fn get_buffer(&self) -> Option<&wgpu::Buffer> {
+        self.buffer.as_ref()
+    }
+
+    pub fn draw_ranges(&self) -> &[DrawRange] {
+        &self.draw_ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::camera::Camera;
+
+    fn assert_close(a: cgmath::Vector3<f32>, b: cgmath::Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    fn bounds(min: [f32; 3], max: [f32; 3]) -> Aabb {
+        Aabb::make(min.into(), max.into())
+    }
+
+    // At z = 10 looking down -z with a 90 degree field of view, so the frustum is as wide as it
+    // is far from the eye. The near plane is at z = 9 and the far plane at z = -10.
+    fn frustum() -> Frustum {
+        Camera::make_perspective(
+            (0.0, 0.0, 10.0).into(),
+            (0.0, 0.0, 0.0).into(),
+            cgmath::Vector3::unit_y(),
+            1.0,
+            90.0,
+            1.0,
+            20.0,
+        )
+        .get_frustum()
+    }
+
+    #[test]
+    fn frustum_keeps_boxes_inside_and_straddling() {
+        let frustum = frustum();
+        for (min, max) in [
+            ([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0]),
+            // across the left, top, near and far planes
+            ([-11.0, -1.0, -1.0], [-9.0, 1.0, 1.0]),
+            ([-1.0, 9.0, -1.0], [1.0, 11.0, 1.0]),
+            ([-0.1, -0.1, 8.5], [0.1, 0.1, 9.5]),
+            ([-1.0, -1.0, -11.0], [1.0, 1.0, -9.0]),
+            // around the whole frustum
+            ([-50.0, -50.0, -50.0], [50.0, 50.0, 50.0]),
+        ] {
+            assert!(frustum.intersects(&bounds(min, max)), "{:?} {:?}", min, max);
+        }
+    }
+
+    #[test]
+    fn frustum_rejects_boxes_outside() {
+        let frustum = frustum();
+        for (min, max) in [
+            ([-31.0, -1.0, -1.0], [-29.0, 1.0, 1.0]),
+            ([29.0, -1.0, -1.0], [31.0, 1.0, 1.0]),
+            ([-1.0, -31.0, -1.0], [1.0, -29.0, 1.0]),
+            // behind the eye and past the far plane
+            ([-1.0, -1.0, 14.0], [1.0, 1.0, 16.0]),
+            ([-1.0, -1.0, -16.0], [1.0, 1.0, -14.0]),
+            // between the eye and the near plane, clip depth below 0
+            ([-0.1, -0.1, 9.2], [0.1, 0.1, 9.8]),
+        ] {
+            assert!(
+                !frustum.intersects(&bounds(min, max)),
+                "{:?} {:?}",
+                min,
+                max
+            );
+        }
+    }
+
+    #[test]
+    fn frustum_planes_are_normalized() {
+        for plane in frustum().get_planes() {
+            let normal = cgmath::Vector3::new(plane[0], plane[1], plane[2]);
+            assert!((normal.magnitude() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn transform_rotated_box_grows_to_hold_it() {
+        let rotation = cgmath::Matrix4::from_angle_z(cgmath::Deg(45.0));
+        let transformed = bounds([-1.0; 3], [1.0; 3]).transform(&rotation);
+        let diagonal = 2.0f32.sqrt();
+        assert_close(transformed.min, cgmath::vec3(-diagonal, -diagonal, -1.0));
+        assert_close(transformed.max, cgmath::vec3(diagonal, diagonal, 1.0));
+    }
+
+    #[test]
+    fn transform_scaled_and_moved_box() {
+        let matrix = cgmath::Matrix4::from_translation(cgmath::vec3(1.0, 0.0, -2.0))
+            * cgmath::Matrix4::from_nonuniform_scale(2.0, 3.0, 0.5);
+        let transformed = bounds([-1.0, 0.0, -1.0], [1.0, 2.0, 1.0]).transform(&matrix);
+        assert_close(transformed.min, cgmath::vec3(-1.0, 0.0, -2.5));
+        assert_close(transformed.max, cgmath::vec3(3.0, 6.0, -1.5));
+    }
+
+    #[test]
+    fn transform_rotated_and_scaled_box() {
+        // stretched along x first, then turned so x points along the diagonal
+        let matrix = cgmath::Matrix4::from_angle_z(cgmath::Deg(45.0))
+            * cgmath::Matrix4::from_nonuniform_scale(2.0, 1.0, 1.0);
+        let transformed = bounds([-1.0; 3], [1.0; 3]).transform(&matrix);
+        let extent = 3.0 / 2.0f32.sqrt();
+        assert_close(transformed.min, cgmath::vec3(-extent, -extent, -1.0));
+        assert_close(transformed.max, cgmath::vec3(extent, extent, 1.0));
+    }
+}
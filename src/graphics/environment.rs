@@ -0,0 +1,552 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use super::scene::Environment;
+use super::texture::Texture;
+
+// Image based lighting baked from an equirectangular HDR image:
+// - environment: the source as a cube map, also drawn as the skybox
+// - irradiance: cosine convolution, used for the diffuse ambient term
+// - prefiltered: GGX filtered mip chain, roughness 0 at mip 0 and 1 at the last mip
+// - brdf_lut: split-sum scale/bias for F0, indexed by (n_dot_v, roughness)
+pub struct EnvironmentMap {
+    environment: Texture,
+    irradiance: Texture,
+    prefiltered: Texture,
+    brdf_lut: Texture,
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PrefilterParams {
+    roughness: f32,
+    sample_count: u32,
+    _padding: [u32; 2],
+}
+
+impl EnvironmentMap {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const CUBE_SIZE: u32 = 512;
+    const IRRADIANCE_SIZE: u32 = 32;
+    const PREFILTERED_SIZE: u32 = 128;
+    // must match MAX_REFLECTION_LOD + 1 in shader.wgsl
+    const PREFILTERED_MIP_COUNT: u32 = 5;
+    const BRDF_LUT_SIZE: u32 = 256;
+    const WORKGROUP_SIZE: u32 = 8;
+
+    pub fn from_scene(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment: &Environment,
+    ) -> Self {
+        match environment {
+            Environment::HdrFile(path) => {
+                Self::from_hdr_path(device, queue, path).unwrap_or_else(|e| {
+                    eprintln!("{:?}, falling back to a sky gradient", e);
+                    Self::from_scene(device, queue, &Environment::DEFAULT_GRADIENT)
+                })
+            }
+            Environment::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => Self::from_sky_gradient(device, queue, *zenith, *horizon, *ground),
+        }
+    }
+
+    pub fn from_hdr_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+    ) -> Result<Self> {
+        let img = image::load_from_memory_with_format(bytes, image::ImageFormat::Hdr)?;
+        Ok(Self::from_equirect(device, queue, &img.to_rgba32f()))
+    }
+
+    pub fn from_hdr_path(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Self> {
+        let bytes = std::fs::read(path.as_ref())
+            .with_context(|| format!("failed to read {}", path.as_ref().display()))?;
+        Self::from_hdr_bytes(device, queue, &bytes)
+    }
+
+    // Used when no HDR file is available: a simple sky above a darker ground
+    pub fn from_sky_gradient(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    ) -> Self {
+        let (width, height) = (128, 64);
+        let img = image::Rgba32FImage::from_fn(width, height, |_x, y| {
+            // +1 at the zenith, -1 at the nadir
+            let elevation = 1.0 - 2.0 * (y as f32 + 0.5) / height as f32;
+            let (from, to, t) = if elevation >= 0.0 {
+                (horizon, zenith, elevation.sqrt())
+            } else {
+                (horizon, ground, (-elevation).sqrt())
+            };
+            let lerp = |i: usize| from[i] + (to[i] - from[i]) * t;
+            image::Rgba([lerp(0), lerp(1), lerp(2), 1.0])
+        });
+        Self::from_equirect(device, queue, &img)
+    }
+
+    // Projects an equirectangular image onto a new cube texture with faces of the given size
+    pub fn cube_from_equirect(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::Rgba32FImage,
+        size: u32,
+    ) -> Texture {
+        let equirect_size = wgpu::Extent3d {
+            width: img.width(),
+            height: img.height(),
+            depth_or_array_layers: 1,
+        };
+        let equirect = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("equirect_texture"),
+            size: equirect_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &equirect,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            bytemuck::cast_slice(img.as_raw()),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(16 * img.width()),
+                rows_per_image: std::num::NonZeroU32::new(img.height()),
+            },
+            equirect_size,
+        );
+        let equirect_view = equirect.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let cube = Texture::create_cube_texture(device, size, 1, Self::FORMAT, "cube_texture");
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Equirect CL"),
+        });
+        {
+            let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    Self::storage_layout_entry(1, wgpu::TextureViewDimension::D2Array),
+                ],
+                label: Some("equirect_bind_group_layout"),
+            });
+            let dst_view = cube.create_cube_mip_view(0);
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&equirect_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&dst_view),
+                    },
+                ],
+                label: Some("equirect_bind_group"),
+            });
+            let pipeline = Self::create_compute_pipeline(
+                device,
+                &layout,
+                include_str!("../equirectToCube.wgsl"),
+                "EquirectToCubePSO",
+            );
+            Self::dispatch(&mut encoder, &pipeline, &bind_group, size, 6);
+        }
+
+        queue.submit(std::iter::once(encoder.finish()));
+
+        cube
+    }
+
+    pub fn from_equirect(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::Rgba32FImage,
+    ) -> Self {
+        let environment = Self::cube_from_equirect(device, queue, img, Self::CUBE_SIZE);
+        let irradiance = Texture::create_cube_texture(
+            device,
+            Self::IRRADIANCE_SIZE,
+            1,
+            Self::FORMAT,
+            "irradiance_texture",
+        );
+        let prefiltered = Texture::create_cube_texture(
+            device,
+            Self::PREFILTERED_SIZE,
+            Self::PREFILTERED_MIP_COUNT,
+            Self::FORMAT,
+            "prefiltered_texture",
+        );
+        let brdf_lut = Self::create_brdf_lut_texture(device);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment CL"),
+        });
+
+        // Irradiance
+        let cube_layout = Self::create_cube_filter_bind_group_layout(device, false);
+        {
+            let dst_view = irradiance.create_cube_mip_view(0);
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &cube_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&environment.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(
+                            environment.sampler.as_ref().unwrap(),
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&dst_view),
+                    },
+                ],
+                label: Some("irradiance_bind_group"),
+            });
+            let pipeline = Self::create_compute_pipeline(
+                device,
+                &cube_layout,
+                include_str!("../irradiance.wgsl"),
+                "IrradiancePSO",
+            );
+            Self::dispatch(
+                &mut encoder,
+                &pipeline,
+                &bind_group,
+                Self::IRRADIANCE_SIZE,
+                6,
+            );
+        }
+
+        // Prefiltered specular, one dispatch per roughness level
+        {
+            let layout = Self::create_cube_filter_bind_group_layout(device, true);
+            let pipeline = Self::create_compute_pipeline(
+                device,
+                &layout,
+                include_str!("../prefilter.wgsl"),
+                "PrefilterPSO",
+            );
+            for mip in 0..Self::PREFILTERED_MIP_COUNT {
+                let params = PrefilterParams {
+                    roughness: mip as f32 / (Self::PREFILTERED_MIP_COUNT - 1) as f32,
+                    sample_count: if mip == 0 { 1 } else { 1024 },
+                    _padding: [0; 2],
+                };
+                let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Prefilter Params Buffer"),
+                    contents: bytemuck::cast_slice(&[params]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                let dst_view = prefiltered.create_cube_mip_view(mip);
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&environment.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(
+                                environment.sampler.as_ref().unwrap(),
+                            ),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&dst_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: params_buffer.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("prefilter_bind_group"),
+                });
+                let mip_size = (Self::PREFILTERED_SIZE >> mip).max(1);
+                Self::dispatch(&mut encoder, &pipeline, &bind_group, mip_size, 6);
+            }
+        }
+
+        // BRDF LUT
+        {
+            let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[Self::storage_layout_entry(
+                    0,
+                    wgpu::TextureViewDimension::D2,
+                )],
+                label: Some("brdf_lut_bind_group_layout"),
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&brdf_lut.view),
+                }],
+                label: Some("brdf_lut_bind_group"),
+            });
+            let pipeline = Self::create_compute_pipeline(
+                device,
+                &layout,
+                include_str!("../brdfLut.wgsl"),
+                "BrdfLutPSO",
+            );
+            Self::dispatch(&mut encoder, &pipeline, &bind_group, Self::BRDF_LUT_SIZE, 1);
+        }
+
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Self {
+            environment,
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            bind_group: None,
+        }
+    }
+
+    pub fn get_environment(&self) -> &Texture {
+        &self.environment
+    }
+
+    pub fn get_bind_group(&self) -> Option<&wgpu::BindGroup> {
+        self.bind_group.as_ref()
+    }
+
+    pub fn create_bind_group(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) {
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.irradiance.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.prefiltered.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(
+                        self.prefiltered.sampler.as_ref().unwrap(),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.brdf_lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(
+                        self.brdf_lut.sampler.as_ref().unwrap(),
+                    ),
+                },
+            ],
+            label: Some("environment_bind_group"),
+        }));
+    }
+
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let cube_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                cube_entry(0),
+                cube_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("environment_bind_group_layout"),
+        })
+    }
+
+    fn create_brdf_lut_texture(device: &wgpu::Device) -> Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("brdf_lut_texture"),
+            size: wgpu::Extent3d {
+                width: Self::BRDF_LUT_SIZE,
+                height: Self::BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        Texture {
+            texture,
+            view,
+            sampler: Some(sampler),
+        }
+    }
+
+    fn storage_layout_entry(
+        binding: u32,
+        view_dimension: wgpu::TextureViewDimension,
+    ) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: Self::FORMAT,
+                view_dimension,
+            },
+            count: None,
+        }
+    }
+
+    // source cube + sampler + destination, optionally followed by a uniform
+    fn create_cube_filter_bind_group_layout(
+        device: &wgpu::Device,
+        with_params: bool,
+    ) -> wgpu::BindGroupLayout {
+        let mut entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            Self::storage_layout_entry(2, wgpu::TextureViewDimension::D2Array),
+        ];
+        if with_params {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            });
+        }
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("cube_filter_bind_group_layout"),
+        })
+    }
+
+    fn create_compute_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        source: &str,
+        label: &str,
+    ) -> wgpu::ComputePipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(label),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some(label),
+                    bind_group_layouts: &[layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            module: &shader,
+            entry_point: "cs_main",
+        })
+    }
+
+    fn dispatch(
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::ComputePipeline,
+        bind_group: &wgpu::BindGroup,
+        size: u32,
+        layers: u32,
+    ) {
+        let groups = size.div_ceil(Self::WORKGROUP_SIZE);
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Environment Pass"),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.dispatch_workgroups(groups, groups, layers);
+    }
+}
@@ -0,0 +1,83 @@
+use super::post_process::{self, PostEffect, PostFrame};
+use super::scene::PostEffectKind;
+use super::texture::{Filtering, SamplerOptions};
+use super::tonemapping::Tonemapper;
+
+// Single pass edge smoothing, see fxaa.wgsl
+pub struct Fxaa {
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Fxaa {
+    pub fn make(device: &wgpu::Device) -> Self {
+        let sampler = SamplerOptions {
+            filtering: Filtering::Bilinear,
+            ..Default::default()
+        }
+        .create_sampler(device);
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                post_process::texture_layout_entry(0, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("fxaa_bind_group_layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("FXAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../fxaa.wgsl").into()),
+        });
+        let pipeline = post_process::create_full_screen_pipeline(
+            device,
+            "FxaaPSO",
+            &[&bind_group_layout],
+            &shader,
+            "fs_main",
+            &[Some(Tonemapper::HDR_FORMAT.into())],
+        );
+
+        Self {
+            sampler,
+            bind_group_layout,
+            pipeline,
+        }
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn kind(&self) -> PostEffectKind {
+        PostEffectKind::Fxaa
+    }
+
+    fn resize(&mut self, _device: &wgpu::Device, _config: &wgpu::SurfaceConfiguration) {}
+
+    fn render(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, frame: &PostFrame) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(frame.input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("fxaa_bind_group"),
+        });
+        post_process::draw_full_screen(
+            encoder,
+            "FXAA Pass",
+            &self.pipeline,
+            &bind_group,
+            &[frame.output],
+        );
+    }
+}
@@ -0,0 +1,717 @@
+use super::culling::{Aabb, Frustum};
+use super::instance::{DrawRange, InstanceRaw, InstanceSet, RenderGroup};
+use super::lod::bucket_by_level;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullParams {
+    occlusion_view_proj: [[f32; 4]; 4],
+    planes: [[f32; 4]; 6],
+    instance_count: u32,
+    stride: u32,
+    occlusion: u32,
+    hi_z_levels: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawInfo {
+    bounds_min: [f32; 4],
+    bounds_max: [f32; 4],
+    first_instance: u32,
+    _padding: [u32; 3],
+}
+
+// The layout draw_indexed_indirect reads
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawArguments {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+// What the culling needs to know about the mesh a group is drawn with
+#[derive(Copy, Clone, Debug)]
+pub struct CullMesh {
+    pub bounds: Aabb,
+    pub index_count: u32,
+}
+
+// Farthest depth per texel at every mip level, for occlusion culling. Built from a single
+// sampled depth buffer after it's drawn, read by the late culling of the same frame and the
+// early culling of the next.
+pub struct HiZPyramid {
+    view: wgpu::TextureView,
+    level_views: Vec<wgpu::TextureView>,
+    size: (u32, u32),
+    // the depth buffer's, so the next frame can test its instances where they were drawn
+    view_proj: cgmath::Matrix4<f32>,
+    copy_layout: wgpu::BindGroupLayout,
+    downsample_layout: wgpu::BindGroupLayout,
+    copy_pipeline: wgpu::ComputePipeline,
+    downsample_pipeline: wgpu::ComputePipeline,
+}
+
+impl HiZPyramid {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+    const WORKGROUP_SIZE: u32 = 8;
+
+    pub fn make(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let storage_entry = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: Self::FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let copy_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_layout_entry(0, wgpu::TextureSampleType::Depth),
+                storage_entry,
+            ],
+            label: Some("hi_z_copy_bind_group_layout"),
+        });
+        let downsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_layout_entry(1, wgpu::TextureSampleType::Float { filterable: false }),
+                storage_entry,
+            ],
+            label: Some("hi_z_downsample_bind_group_layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Hi-Z Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../hiZ.wgsl").into()),
+        });
+        let copy_pipeline =
+            create_compute_pipeline(device, &copy_layout, &shader, "cs_copy", "Hi-Z Copy");
+        let downsample_pipeline = create_compute_pipeline(
+            device,
+            &downsample_layout,
+            &shader,
+            "cs_downsample",
+            "Hi-Z Downsample",
+        );
+        let (view, level_views) = Self::create_texture(device, width, height);
+        Self {
+            view,
+            level_views,
+            size: (width.max(1), height.max(1)),
+            view_proj: cgmath::SquareMatrix::identity(),
+            copy_layout,
+            downsample_layout,
+            copy_pipeline,
+            downsample_pipeline,
+        }
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::TextureView, Vec<wgpu::TextureView>) {
+        let (width, height) = (width.max(1), height.max(1));
+        let level_count = 32 - width.max(height).leading_zeros();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("hi_z_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let level_views = (0..level_count)
+            .map(|level| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("hi_z_level_view"),
+                    base_mip_level: level,
+                    mip_level_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        (view, level_views)
+    }
+
+    // Same size as the depth buffer it's built from
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let (view, level_views) = Self::create_texture(device, width, height);
+        self.view = view;
+        self.level_views = level_views;
+        self.size = (width.max(1), height.max(1));
+    }
+
+    pub fn get_view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn get_level_count(&self) -> u32 {
+        self.level_views.len() as u32
+    }
+
+    // Of the depth buffer the pyramid was last built from
+    pub fn get_view_proj(&self) -> cgmath::Matrix4<f32> {
+        self.view_proj
+    }
+
+    // view_proj is the one the depth was drawn with
+    pub fn build(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        depth: &wgpu::TextureView,
+        view_proj: cgmath::Matrix4<f32>,
+    ) {
+        self.view_proj = view_proj;
+        let copy_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.copy_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.level_views[0]),
+                },
+            ],
+            label: Some("hi_z_copy_bind_group"),
+        });
+        let downsample_bind_groups: Vec<_> = (1..self.level_views.len())
+            .map(|level| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.downsample_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(
+                                &self.level_views[level - 1],
+                            ),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&self.level_views[level]),
+                        },
+                    ],
+                    label: Some("hi_z_downsample_bind_group"),
+                })
+            })
+            .collect();
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Hi-Z Pass"),
+        });
+        compute_pass.set_pipeline(&self.copy_pipeline);
+        compute_pass.set_bind_group(0, &copy_bind_group, &[]);
+        let (width, height) = self.size;
+        compute_pass.dispatch_workgroups(
+            width.div_ceil(Self::WORKGROUP_SIZE),
+            height.div_ceil(Self::WORKGROUP_SIZE),
+            1,
+        );
+
+        compute_pass.set_pipeline(&self.downsample_pipeline);
+        for (index, bind_group) in downsample_bind_groups.iter().enumerate() {
+            let level = index + 1;
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            let (width, height) = ((width >> level).max(1), (height >> level).max(1));
+            compute_pass.dispatch_workgroups(
+                width.div_ceil(Self::WORKGROUP_SIZE),
+                height.div_ceil(Self::WORKGROUP_SIZE),
+                1,
+            );
+        }
+    }
+}
+
+// Inputs shared by every view: which draw each instance belongs to and the bounds of the
+// draws' meshes. The draws are the InstanceSet's draw ranges split by level.
+pub struct GpuCulling {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    late_pipeline: wgpu::ComputePipeline,
+    // bound in place of a pyramid when there's no occlusion culling
+    placeholder_view: wgpu::TextureView,
+    draw_ranges: Vec<DrawRange>,
+    draw_ids: Vec<u32>,
+    draws: Vec<DrawInfo>,
+    index_counts: Vec<u32>,
+    draw_ids_buffer: wgpu::Buffer,
+    draws_buffer: wgpu::Buffer,
+    draw_id_capacity: usize,
+    draw_capacity: usize,
+}
+
+impl GpuCulling {
+    const WORKGROUP_SIZE: u32 = 64;
+    const MIN_CAPACITY: usize = 16;
+
+    pub fn make(device: &wgpu::Device) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, true),
+                storage_entry(4, false),
+                storage_entry(5, false),
+                texture_layout_entry(6, wgpu::TextureSampleType::Float { filterable: false }),
+                storage_entry(7, false),
+            ],
+            label: Some("cull_bind_group_layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Cull Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../cull.wgsl").into()),
+        });
+        let pipeline = create_compute_pipeline(device, &layout, &shader, "cs_main", "Cull");
+        let late_pipeline =
+            create_compute_pipeline(device, &layout, &shader, "cs_late", "Late Cull");
+        let (placeholder_view, _) = HiZPyramid::create_texture(device, 1, 1);
+        Self {
+            placeholder_view,
+            draw_ids_buffer: Self::create_storage_buffer(
+                device,
+                "Cull Draw Id Buffer",
+                Self::MIN_CAPACITY * std::mem::size_of::<u32>(),
+            ),
+            draws_buffer: Self::create_storage_buffer(
+                device,
+                "Cull Draw Buffer",
+                Self::MIN_CAPACITY * std::mem::size_of::<DrawInfo>(),
+            ),
+            layout,
+            pipeline,
+            late_pipeline,
+            draw_ranges: Vec::new(),
+            draw_ids: Vec::new(),
+            draws: Vec::new(),
+            index_counts: Vec::new(),
+            draw_id_capacity: Self::MIN_CAPACITY,
+            draw_capacity: Self::MIN_CAPACITY,
+        }
+    }
+
+    fn create_storage_buffer(device: &wgpu::Device, label: &str, size: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: size as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Uploads the draws again when the set's draw ranges or the instances' levels have changed.
+    // There's a draw per level of each range, levels from LodSelector.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instance_set: &InstanceSet,
+        levels: &[u32],
+        mesh: impl Fn(RenderGroup, u32) -> CullMesh,
+    ) {
+        let (draw_ranges, draw_ids) = bucket_by_level(instance_set.draw_ranges(), levels);
+        if self.draw_ranges == draw_ranges && self.draw_ids == draw_ids {
+            return;
+        }
+        self.draw_ranges = draw_ranges;
+        self.draw_ids = draw_ids;
+        self.draws.clear();
+        self.index_counts.clear();
+        for range in &self.draw_ranges {
+            let mesh = mesh(range.group, range.lod);
+            self.draws.push(DrawInfo {
+                bounds_min: mesh.bounds.min.extend(0.0).into(),
+                bounds_max: mesh.bounds.max.extend(0.0).into(),
+                first_instance: range.instances.start,
+                _padding: [0; 3],
+            });
+            self.index_counts.push(mesh.index_count);
+        }
+
+        if self.draw_ids.len() > self.draw_id_capacity {
+            self.draw_id_capacity = grow(self.draw_id_capacity, self.draw_ids.len());
+            self.draw_ids_buffer = Self::create_storage_buffer(
+                device,
+                "Cull Draw Id Buffer",
+                self.draw_id_capacity * std::mem::size_of::<u32>(),
+            );
+        }
+        if self.draws.len() > self.draw_capacity {
+            self.draw_capacity = grow(self.draw_capacity, self.draws.len());
+            self.draws_buffer = Self::create_storage_buffer(
+                device,
+                "Cull Draw Buffer",
+                self.draw_capacity * std::mem::size_of::<DrawInfo>(),
+            );
+        }
+        if !self.draw_ids.is_empty() {
+            queue.write_buffer(
+                &self.draw_ids_buffer,
+                0,
+                bytemuck::cast_slice(&self.draw_ids),
+            );
+            queue.write_buffer(&self.draws_buffer, 0, bytemuck::cast_slice(&self.draws));
+        }
+    }
+
+    pub fn draw_ranges(&self) -> &[DrawRange] {
+        &self.draw_ranges
+    }
+}
+
+// Which of a view's culling passes a draw covers
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CullPhase {
+    // visible against the previous frame's pyramid, what the depth pre-pass draws
+    Early,
+    // hidden from the previous frame's pyramid but not from this frame's
+    Late,
+    Both,
+}
+
+// The visible instances of one pass, packed from the start of their draw, and one set of
+// indirect arguments per draw
+struct CullOutput {
+    params_buffer: wgpu::Buffer,
+    instance_buffer: wgpu::Buffer,
+    arguments_buffer: wgpu::Buffer,
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+impl CullOutput {
+    fn make(device: &wgpu::Device, label: &str) -> Self {
+        Self {
+            params_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Cull Params Buffer"),
+                size: std::mem::size_of::<CullParams>() as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            instance_buffer: Self::create_instance_buffer(device, label, GpuCulling::MIN_CAPACITY),
+            arguments_buffer: Self::create_arguments_buffer(device, GpuCulling::MIN_CAPACITY),
+            bind_group: None,
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, label: &str, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_arguments_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect Arguments Buffer"),
+            size: (capacity * std::mem::size_of::<DrawArguments>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // The params of this frame, and no instances in any draw yet
+    fn reset(&self, queue: &wgpu::Queue, culling: &GpuCulling, params: CullParams) {
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+        let arguments: Vec<_> = culling
+            .index_counts
+            .iter()
+            .map(|&index_count| DrawArguments {
+                index_count,
+                instance_count: 0,
+                first_index: 0,
+                base_vertex: 0,
+                // instances are bound from the start of the draw, so the GPU doesn't need
+                // indirect first instance support
+                first_instance: 0,
+            })
+            .collect();
+        if !arguments.is_empty() {
+            queue.write_buffer(&self.arguments_buffer, 0, bytemuck::cast_slice(&arguments));
+        }
+    }
+
+    fn bind(
+        &mut self,
+        device: &wgpu::Device,
+        culling: &GpuCulling,
+        instances: Option<&wgpu::Buffer>,
+        hi_z: &wgpu::TextureView,
+        occluded: &wgpu::Buffer,
+    ) {
+        self.bind_group = instances.map(|instances| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &culling.layout,
+                entries: &[
+                    buffer_entry(0, &self.params_buffer),
+                    buffer_entry(1, instances),
+                    buffer_entry(2, &culling.draw_ids_buffer),
+                    buffer_entry(3, &culling.draws_buffer),
+                    buffer_entry(4, &self.instance_buffer),
+                    buffer_entry(5, &self.arguments_buffer),
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: wgpu::BindingResource::TextureView(hi_z),
+                    },
+                    buffer_entry(7, occluded),
+                ],
+                label: Some("cull_bind_group"),
+            })
+        });
+    }
+
+    fn compute(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::ComputePipeline,
+        instance_count: u32,
+    ) {
+        let bind_group = match &self.bind_group {
+            Some(bind_group) if instance_count > 0 => bind_group,
+            _ => return,
+        };
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cull Pass"),
+        });
+        compute_pass.set_pipeline(pipeline);
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.dispatch_workgroups(instance_count.div_ceil(GpuCulling::WORKGROUP_SIZE), 1, 1);
+    }
+
+    fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        draw_id: usize,
+        range: &DrawRange,
+    ) {
+        let stride = std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress;
+        render_pass.set_vertex_buffer(
+            1,
+            self.instance_buffer
+                .slice(range.instances.start as wgpu::BufferAddress * stride..),
+        );
+        render_pass.draw_indexed_indirect(
+            &self.arguments_buffer,
+            (draw_id * std::mem::size_of::<DrawArguments>()) as wgpu::BufferAddress,
+        );
+    }
+}
+
+// One view's culling output. With occlusion culling the late pass's instances are kept apart,
+// the depth pre-pass draws only the early ones before the pyramid they're tested against is
+// built from it.
+pub struct GpuCulledInstances {
+    label: &'static str,
+    early: CullOutput,
+    late: CullOutput,
+    // 1 per instance the early pass found occluded
+    occluded_buffer: wgpu::Buffer,
+    // in instances and draws
+    instance_capacity: usize,
+    draw_capacity: usize,
+    instance_count: u32,
+    occlusion: bool,
+}
+
+impl GpuCulledInstances {
+    pub fn make(device: &wgpu::Device, label: &'static str) -> Self {
+        Self {
+            label,
+            early: CullOutput::make(device, label),
+            late: CullOutput::make(device, label),
+            occluded_buffer: Self::create_occluded_buffer(device, GpuCulling::MIN_CAPACITY),
+            instance_capacity: GpuCulling::MIN_CAPACITY,
+            draw_capacity: GpuCulling::MIN_CAPACITY,
+            instance_count: 0,
+            occlusion: false,
+        }
+    }
+
+    fn create_occluded_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        GpuCulling::create_storage_buffer(
+            device,
+            "Cull Occluded Buffer",
+            capacity * std::mem::size_of::<u32>(),
+        )
+    }
+
+    // Resets the instance counts and points the culling at this frame's instances. With a
+    // pyramid the early pass tests against it from the view it was built with, and the late
+    // pass against the one compute_late is given. Frustum culling only without.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        culling: &GpuCulling,
+        instance_set: &InstanceSet,
+        view_proj: cgmath::Matrix4<f32>,
+        hi_z: Option<&HiZPyramid>,
+    ) {
+        let instance_count = instance_set.count();
+        if instance_count > self.instance_capacity {
+            self.instance_capacity = grow(self.instance_capacity, instance_count);
+            for output in [&mut self.early, &mut self.late] {
+                output.instance_buffer =
+                    CullOutput::create_instance_buffer(device, self.label, self.instance_capacity);
+            }
+            self.occluded_buffer = Self::create_occluded_buffer(device, self.instance_capacity);
+        }
+        if culling.draws.len() > self.draw_capacity {
+            self.draw_capacity = grow(self.draw_capacity, culling.draws.len());
+            for output in [&mut self.early, &mut self.late] {
+                output.arguments_buffer =
+                    CullOutput::create_arguments_buffer(device, self.draw_capacity);
+            }
+        }
+        self.instance_count = instance_count as u32;
+        self.occlusion = hi_z.is_some();
+
+        let params = |occlusion_view_proj: cgmath::Matrix4<f32>| CullParams {
+            occlusion_view_proj: occlusion_view_proj.into(),
+            planes: Frustum::from_matrix(&view_proj).get_planes(),
+            instance_count: instance_count as u32,
+            stride: (std::mem::size_of::<InstanceRaw>() / std::mem::size_of::<f32>()) as u32,
+            occlusion: hi_z.is_some() as u32,
+            hi_z_levels: hi_z.map_or(1, HiZPyramid::get_level_count),
+        };
+        let hi_z_view = hi_z.map_or(&culling.placeholder_view, HiZPyramid::get_view);
+        self.early.reset(
+            queue,
+            culling,
+            params(hi_z.map_or(view_proj, HiZPyramid::get_view_proj)),
+        );
+        self.early.bind(
+            device,
+            culling,
+            instance_set.get_buffer(),
+            hi_z_view,
+            &self.occluded_buffer,
+        );
+        if self.occlusion {
+            self.late.reset(queue, culling, params(view_proj));
+            self.late.bind(
+                device,
+                culling,
+                instance_set.get_buffer(),
+                hi_z_view,
+                &self.occluded_buffer,
+            );
+        }
+    }
+
+    pub fn compute(&self, encoder: &mut wgpu::CommandEncoder, culling: &GpuCulling) {
+        self.early
+            .compute(encoder, &culling.pipeline, self.instance_count);
+    }
+
+    // After the pyramid update was given has been built again from the early instances' depth
+    pub fn compute_late(&self, encoder: &mut wgpu::CommandEncoder, culling: &GpuCulling) {
+        if self.occlusion {
+            self.late
+                .compute(encoder, &culling.late_pipeline, self.instance_count);
+        }
+    }
+
+    // The draw's visible instances start at its first instance in the set
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        draw_id: usize,
+        range: &DrawRange,
+        phase: CullPhase,
+    ) {
+        if phase != CullPhase::Late {
+            self.early.draw(render_pass, draw_id, range);
+        }
+        if phase != CullPhase::Early && self.occlusion {
+            self.late.draw(render_pass, draw_id, range);
+        }
+    }
+}
+
+fn grow(capacity: usize, required: usize) -> usize {
+    let mut capacity = capacity.max(GpuCulling::MIN_CAPACITY);
+    while capacity < required {
+        capacity *= 2;
+    }
+    capacity
+}
+
+fn texture_layout_entry(
+    binding: u32,
+    sample_type: wgpu::TextureSampleType,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type,
+        },
+        count: None,
+    }
+}
+
+fn create_compute_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    label: &str,
+) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(label),
+        layout: Some(
+            &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            }),
+        ),
+        module: shader,
+        entry_point,
+    })
+}
+
+fn buffer_entry(binding: u32, buffer: &wgpu::Buffer) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: buffer.as_entire_binding(),
+    }
+}
@@ -0,0 +1,334 @@
+use std::sync::mpsc;
+
+use super::instance::{Instance, InstanceHandle};
+use super::texture::Texture;
+use super::vertex::Vertex;
+
+// What the pixel under the cursor belongs to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IdHit {
+    Instance(InstanceHandle),
+    // index in the GPU animated grid, which has no handles
+    Array(u32),
+}
+
+// A finished pick, hit is None where the cursor was over the background
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IdPick {
+    pub hit: Option<IdHit>,
+    pub extend: bool,
+}
+
+struct PickRequest {
+    cursor: cgmath::Point2<f32>,
+    extend: bool,
+}
+
+// A pixel copied into the readback buffer, waiting for the GPU
+struct Readback {
+    extend: bool,
+    // the set's handles when the ids were drawn, buffer index to handle
+    handles: Vec<InstanceHandle>,
+    // set once the commands are submitted and the buffer is being mapped
+    mapped: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+// Pixel exact picking. The instance id of every pixel is drawn into an R32Uint target and the
+// one under the cursor read back without waiting on the GPU, the answer comes a frame or two
+// later through poll. Integer targets can't be multisampled, so the ids get their own single
+// sampled pass, only on frames with a pick waiting.
+pub struct IdBuffer {
+    id_texture: Texture,
+    depth_texture: Texture,
+    size: (u32, u32),
+    instance_pipeline: wgpu::RenderPipeline,
+    array_pipeline: wgpu::RenderPipeline,
+    readback_buffer: wgpu::Buffer,
+    requested: Option<PickRequest>,
+    readback: Option<Readback>,
+}
+
+impl IdBuffer {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+    const ARRAY_BIT: u32 = 1 << 31;
+
+    pub fn make(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Instance Id Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../instanceId.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("InstanceIdRootSignature"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("InstanceIdPSO"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point,
+                    buffers: &[Vertex::desc(), Instance::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(Self::FORMAT.into())],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let instance_pipeline = create_pipeline("vs_main");
+        let array_pipeline = create_pipeline("vs_array");
+
+        // a copy's rows are aligned to 256 bytes, even for a single pixel
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Id Readback Buffer"),
+            size: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let (id_texture, depth_texture) = Self::create_textures(device, width, height);
+        Self {
+            id_texture,
+            depth_texture,
+            size: (width.max(1), height.max(1)),
+            instance_pipeline,
+            array_pipeline,
+            readback_buffer,
+            requested: None,
+            readback: None,
+        }
+    }
+
+    fn create_textures(device: &wgpu::Device, width: u32, height: u32) -> (Texture, Texture) {
+        let (width, height) = (width.max(1), height.max(1));
+        let id_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Instance Id Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = id_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (
+            Texture {
+                texture: id_texture,
+                view,
+                sampler: None,
+            },
+            Texture::create_depth_texture(device, width, height, "Instance Id Depth Texture", 1),
+        )
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.id_texture, self.depth_texture) = Self::create_textures(device, width, height);
+        self.size = (width.max(1), height.max(1));
+    }
+
+    // Picks at the cursor, in physical pixels. A later request before the ids are drawn
+    // replaces this one.
+    pub fn request(&mut self, cursor: cgmath::Point2<f32>, extend: bool) {
+        self.requested = Some(PickRequest { cursor, extend });
+    }
+
+    // Whether the ids have to be drawn this frame. One pick is read back at a time, a request
+    // waits while the one before it is still on its way.
+    pub fn is_pass_needed(&self) -> bool {
+        self.requested.is_some() && self.readback.is_none()
+    }
+
+    // Draw with get_instance_pipeline and every instance of the set bound from its start, then
+    // the grid with get_array_pipeline
+    pub fn begin_pass<'a>(&'a self, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Instance Id Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.id_texture.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        })
+    }
+
+    pub fn get_instance_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.instance_pipeline
+    }
+
+    pub fn get_array_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.array_pipeline
+    }
+
+    // After the pass, copies the pixel under the cursor out. handles are the set's in buffer
+    // order when the ids were drawn.
+    pub fn copy(&mut self, encoder: &mut wgpu::CommandEncoder, handles: &[InstanceHandle]) {
+        let request = match self.requested.take() {
+            Some(request) => request,
+            None => return,
+        };
+        let x = (request.cursor.x.max(0.0) as u32).min(self.size.0 - 1);
+        let y = (request.cursor.y.max(0.0) as u32).min(self.size.1 - 1);
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.id_texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.readback = Some(Readback {
+            extend: request.extend,
+            handles: handles.to_vec(),
+            mapped: None,
+        });
+    }
+
+    // Once the copy is submitted, a buffer can't be mapped while commands using it are pending
+    pub fn map(&mut self) {
+        let readback = match &mut self.readback {
+            Some(readback) if readback.mapped.is_none() => readback,
+            _ => return,
+        };
+        let (sender, receiver) = mpsc::channel();
+        self.readback_buffer
+            .slice(..4)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        readback.mapped = Some(receiver);
+    }
+
+    // The finished pick, if the readback has arrived. Doesn't block.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<IdPick> {
+        let receiver = self.readback.as_ref()?.mapped.as_ref()?;
+        device.poll(wgpu::Maintain::Poll);
+        let result = match receiver.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => Err(wgpu::BufferAsyncError),
+        };
+        let readback = self.readback.take()?;
+        if let Err(e) = result {
+            eprintln!("failed to read the instance id back: {:?}", e);
+            return None;
+        }
+        let id = {
+            let data = self.readback_buffer.slice(..4).get_mapped_range();
+            u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+        };
+        self.readback_buffer.unmap();
+        Some(IdPick {
+            hit: decode(id, &readback.handles),
+            extend: readback.extend,
+        })
+    }
+}
+
+// What an id drawn by instanceId.wgsl stands for. 0 is the background, the grid's ids have
+// ARRAY_BIT set and the rest are a buffer index plus one, looked up in the handles the pass
+// drew with.
+fn decode(id: u32, handles: &[InstanceHandle]) -> Option<IdHit> {
+    match id {
+        0 => None,
+        id if id & IdBuffer::ARRAY_BIT != 0 => Some(IdHit::Array(id & !IdBuffer::ARRAY_BIT)),
+        id => handles
+            .get(id as usize - 1)
+            .map(|&handle| IdHit::Instance(handle)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::instance::{ArrayInstance, InstanceSet, RenderGroup};
+
+    fn handles() -> Vec<InstanceHandle> {
+        InstanceSet::make(
+            (0..3)
+                .map(|x| (ArrayInstance::make(x, 0), RenderGroup::Solid))
+                .collect(),
+        )
+        .handles()
+        .to_vec()
+    }
+
+    #[test]
+    fn zero_is_the_background() {
+        assert_eq!(decode(0, &handles()), None);
+    }
+
+    #[test]
+    fn array_bit_marks_the_grid() {
+        assert_eq!(
+            decode(IdBuffer::ARRAY_BIT, &handles()),
+            Some(IdHit::Array(0))
+        );
+        assert_eq!(
+            decode(IdBuffer::ARRAY_BIT | 41, &[]),
+            Some(IdHit::Array(41))
+        );
+    }
+
+    #[test]
+    fn other_ids_are_buffer_index_plus_one() {
+        let handles = handles();
+        for (index, &handle) in handles.iter().enumerate() {
+            assert_eq!(
+                decode(index as u32 + 1, &handles),
+                Some(IdHit::Instance(handle))
+            );
+        }
+        // past the instances the pass drew, a miss
+        assert_eq!(decode(handles.len() as u32 + 1, &handles), None);
+        assert_eq!(decode(IdBuffer::ARRAY_BIT - 1, &handles), None);
+    }
+}
@@ -1,13 +1,94 @@
 use cgmath::*;
-use wgpu::util::DeviceExt;
+// Everything an instance passes to the shaders. Only the model matrix is required, the rest
+// defaults to an untinted instance of the first material.
 pub trait MatrixInstance {
-    fn to_raw(&self) -> InstanceRaw;
-    fn update(&mut self, time: f32) {}
+    fn model(&self) -> cgmath::Matrix4<f32>;
+    fn tint(&self) -> [f32; 4] {
+        [1.0, 1.0, 1.0, 1.0]
+    }
+    fn material_index(&self) -> u32 {
+        0
+    }
+    // first matrix of the instance's skin in the joint palette, see skin.rs
+    fn joint_offset(&self) -> u32 {
+        0
+    }
+    // free for the shaders to use
+    fn custom(&self) -> [f32; 4] {
+        [0.0; 4]
+    }
+    // parent is the world matrix of the scene graph node the instance is attached to
+    fn to_raw(&self, parent: &cgmath::Matrix4<f32>) -> InstanceRaw {
+        let model = parent * self.model();
+        // inverse transpose of the upper 3x3, keeps normals perpendicular under non-uniform scale
+        let linear = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        let normal = linear
+            .invert()
+            .map(|inverse| inverse.transpose())
+            .unwrap_or(linear);
+        InstanceRaw {
+            model: model.into(),
+            normal: normal.into(),
+            tint: self.tint(),
+            material_index: self.material_index(),
+            joint_offset: self.joint_offset(),
+            custom: self.custom(),
+        }
+    }
+    // Instances with a plain transform can be driven by animation clips
+    fn get_transform(&self) -> Option<Transform> {
+        None
+    }
+    fn set_transform(&mut self, _transform: Transform) {}
+    // Returns whether anything the shaders see changed, so unchanged instances aren't uploaded
+    fn update(&mut self, _time: f32) -> bool {
+        false
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    pub position: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
 }
+
+impl Transform {
+    pub fn make(
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+        scale: cgmath::Vector3<f32>,
+    ) -> Self {
+        Self {
+            position,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn to_matrix(self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::make(
+            cgmath::Vector3::new(0.0, 0.0, 0.0),
+            cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            cgmath::Vector3::new(1.0, 1.0, 1.0),
+        )
+    }
+}
+
 pub struct Instance {
     position: cgmath::Vector3<f32>,
     rotation: cgmath::Quaternion<f32>,
     scale: cgmath::Vector3<f32>,
+    tint: [f32; 4],
+    joint_offset: u32,
 }
 
 pub struct ArrayInstance {
@@ -20,20 +101,98 @@ pub struct ArrayInstance {
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     pub model: [[f32; 4]; 4],
+    pub normal: [[f32; 3]; 3],
+    pub tint: [f32; 4],
+    pub material_index: u32,
+    pub joint_offset: u32,
+    pub custom: [f32; 4],
 }
 
-impl MatrixInstance for Instance {
-    fn to_raw(&self) -> InstanceRaw {
-        InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation)
-                * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z))
-            .into(),
+// The InstanceRaw fields in order, as (format, locations). A matrix takes one location per
+// column. A new attribute needs a field above, an entry here and a location in the shaders.
+const INSTANCE_LAYOUT: [(wgpu::VertexFormat, u32); 5] = [
+    (wgpu::VertexFormat::Float32x4, 4), // model
+    (wgpu::VertexFormat::Float32x3, 3), // normal
+    (wgpu::VertexFormat::Float32x4, 1), // tint
+    (wgpu::VertexFormat::Uint32x2, 1),  // material_index and joint_offset share a location
+    (wgpu::VertexFormat::Float32x4, 1), // custom
+];
+
+const fn instance_location_count() -> usize {
+    let mut count = 0;
+    let mut i = 0;
+    while i < INSTANCE_LAYOUT.len() {
+        count += INSTANCE_LAYOUT[i].1 as usize;
+        i += 1;
+    }
+    count
+}
+
+const INSTANCE_LOCATION_COUNT: usize = instance_location_count();
+
+const fn instance_attributes() -> [wgpu::VertexAttribute; INSTANCE_LOCATION_COUNT] {
+    let mut attributes = [wgpu::VertexAttribute {
+        format: wgpu::VertexFormat::Float32,
+        offset: 0,
+        shader_location: 0,
+    }; INSTANCE_LOCATION_COUNT];
+    let mut offset = 0;
+    let mut location = 0;
+    let mut i = 0;
+    while i < INSTANCE_LAYOUT.len() {
+        let (format, count) = INSTANCE_LAYOUT[i];
+        let mut column = 0;
+        while column < count {
+            attributes[location] = wgpu::VertexAttribute {
+                format,
+                offset,
+                shader_location: Instance::FIRST_LOCATION + location as u32,
+            };
+            offset += format.size();
+            location += 1;
+            column += 1;
         }
+        i += 1;
+    }
+    attributes
+}
+
+const INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; INSTANCE_LOCATION_COUNT] = instance_attributes();
+
+// the layout has to cover InstanceRaw exactly
+const _: () = {
+    let last = INSTANCE_ATTRIBUTES[INSTANCE_LOCATION_COUNT - 1];
+    assert!(last.offset + last.format.size() == std::mem::size_of::<InstanceRaw>() as u64);
+};
+
+impl MatrixInstance for Instance {
+    fn model(&self) -> cgmath::Matrix4<f32> {
+        Transform::make(self.position, self.rotation, self.scale).to_matrix()
+    }
+
+    fn tint(&self) -> [f32; 4] {
+        self.tint
+    }
+
+    fn joint_offset(&self) -> u32 {
+        self.joint_offset
+    }
+
+    fn get_transform(&self) -> Option<Transform> {
+        Some(Transform::make(self.position, self.rotation, self.scale))
+    }
+
+    fn set_transform(&mut self, transform: Transform) {
+        self.position = transform.position;
+        self.rotation = transform.rotation;
+        self.scale = transform.scale;
     }
 }
 
 impl Instance {
+    // Vertex uses 0 to 5, the instance attributes follow
+    const FIRST_LOCATION: u32 = 6;
+
     pub fn make(
         position: cgmath::Vector3<f32>,
         rotation: cgmath::Quaternion<f32>,
@@ -43,43 +202,34 @@ impl Instance {
             position,
             rotation,
             scale,
+            tint: [1.0, 1.0, 1.0, 1.0],
+            joint_offset: 0,
         })
     }
-    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
+
+    // Deformed by the skin whose joints start at joint_offset
+    pub fn make_skinned(
+        position: cgmath::Vector3<f32>,
+        rotation: cgmath::Quaternion<f32>,
+        scale: cgmath::Vector3<f32>,
+        joint_offset: u32,
+    ) -> Box<dyn MatrixInstance> {
+        Box::new(Instance {
+            position,
+            rotation,
+            scale,
+            tint: [1.0, 1.0, 1.0, 1.0],
+            joint_offset,
+        })
+    }
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
             // We need to switch from using a step mode of Vertex to Instance
             // This means that our shaders will only change to use the next
             // instance when the shader starts processing a new instance
             step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials we'll
-                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5 not conflict with them later
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
-                // for each vec4. We'll have to reassemble the mat4 in
-                // the shader.
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-            ],
+            attributes: &INSTANCE_ATTRIBUTES,
         }
     }
 }
@@ -100,55 +250,537 @@ impl ArrayInstance {
                     cgmath::Deg(0.0),
                 ),
                 scale: cgmath::vec3(1.0, 1.0, 1.0),
+                // a faint gradient across the grid
+                tint: [
+                    0.8 + 0.2 * x as f32 / 9.0,
+                    0.9,
+                    0.8 + 0.2 * z as f32 / 9.0,
+                    1.0,
+                ],
+                joint_offset: 0,
             },
         })
     }
 }
 
 impl MatrixInstance for ArrayInstance {
-    fn to_raw(&self) -> InstanceRaw {
-        self.instance.to_raw()
+    fn model(&self) -> cgmath::Matrix4<f32> {
+        self.instance.model()
+    }
+
+    fn tint(&self) -> [f32; 4] {
+        self.instance.tint()
     }
 
-    fn update(&mut self, time: f32) {
+    fn update(&mut self, time: f32) -> bool {
         let angle = self.z as f32 * time;
         let x = self.x as f32;
         self.instance.position.y = (0.2 * time + x).sin() * (1.0 + 2.0 * x / 9.0);
         self.instance.rotation =
             cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(angle));
+        true
     }
 }
+// Which pipeline draws the instance
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RenderGroup {
+    // PBR material with image based lighting
+    Textured,
+    // the same, on the skinned bar mesh
+    Skinned,
+    // flat colour with shadows
+    Solid,
+}
+
+// Instances of one group and material, contiguous in the buffer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DrawRange {
+    pub group: RenderGroup,
+    pub material_index: u32,
+    // which mesh of the group's LOD chain, the InstanceSet's own ranges are all full detail
+    pub lod: u32,
+    pub instances: std::ops::Range<u32>,
+}
+
+// Stays valid while other instances are added and removed. The generation tells a removed
+// instance apart from a later one that reuses its slot.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InstanceHandle {
+    slot: u32,
+    generation: u32,
+}
+
+struct Slot {
+    generation: u32,
+    // position in the packed list, None while the slot is free
+    index: Option<usize>,
+}
+
+// The instances are kept packed and sorted by group and material so each pipeline and material
+// is one draw, the handles point into them through the slots.
 pub struct InstanceSet {
-    pub set: Vec<Box<dyn MatrixInstance>>,
+    set: Vec<Box<dyn MatrixInstance>>,
+    handles: Vec<InstanceHandle>,
+    groups: Vec<RenderGroup>,
+    parents: Vec<cgmath::Matrix4<f32>>,
+    // replaces the instance's tint while it's selected
+    highlights: Vec<Option<[f32; 4]>>,
+    slots: Vec<Slot>,
+    free_slots: Vec<u32>,
+    // cleared by anything that can change the order, the next update_buffer sorts again
+    sorted: bool,
+    draw_ranges: Vec<DrawRange>,
+
+    // what's in the buffer, kept for culling
+    raw: Vec<InstanceRaw>,
     buffer: Option<wgpu::Buffer>,
+    // in instances, doubles whenever the set outgrows the buffer
+    capacity: usize,
+    // instances changed since the last upload
+    dirty: Vec<std::ops::Range<usize>>,
 }
 
 impl InstanceSet {
+    const MIN_CAPACITY: usize = 16;
+
     pub fn create_buffer(&mut self, device: &wgpu::Device) {
-        let instance_raw_data: Vec<_> = self.set.iter().map(|i| i.to_raw()).collect();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        let mut capacity = self.capacity.max(Self::MIN_CAPACITY);
+        while capacity < self.set.len() {
+            capacity *= 2;
+        }
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_raw_data),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            // read by the GPU culling
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: true,
         });
+        self.raw = (0..self.set.len())
+            .map(|index| self.to_raw(index))
+            .collect();
+        {
+            let contents = bytemuck::cast_slice(&self.raw);
+            instance_buffer.slice(..).get_mapped_range_mut()[..contents.len()]
+                .copy_from_slice(contents);
+        }
+        instance_buffer.unmap();
         self.buffer = Some(instance_buffer);
+        self.capacity = capacity;
+        self.dirty.clear();
     }
 
-    pub fn update_buffer(&self, queue: &wgpu::Queue) {
-        let instance_raw_data: Vec<_> = self.set.iter().map(|i| i.to_raw()).collect();
-        queue.write_buffer(
-            self.get_buffer().unwrap(),
-            0,
-            bytemuck::cast_slice(&instance_raw_data),
+    // Uploads the dirty ranges, or everything into a bigger buffer if the set has outgrown it
+    pub fn update_buffer(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if !self.sorted {
+            self.sort();
+        }
+        if self.buffer.is_none() || self.set.len() > self.capacity {
+            self.create_buffer(device);
+            return;
+        }
+
+        let merged = self.take_dirty();
+        let buffer = self.buffer.as_ref().unwrap();
+        self.raw.resize(
+            self.set.len(),
+            <InstanceRaw as bytemuck::Zeroable>::zeroed(),
         );
+        for range in merged {
+            let end = range.end.min(self.set.len());
+            if range.start >= end {
+                continue;
+            }
+            for index in range.start..end {
+                self.raw[index] = self.to_raw(index);
+            }
+            queue.write_buffer(
+                buffer,
+                (range.start * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+                bytemuck::cast_slice(&self.raw[range.start..end]),
+            );
+        }
+    }
+
+    // The dirty ranges in order, merged where they overlap or touch
+    fn take_dirty(&mut self) -> Vec<std::ops::Range<usize>> {
+        self.dirty.sort_by_key(|range| range.start);
+        let mut merged: Vec<std::ops::Range<usize>> = Vec::new();
+        for range in self.dirty.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    fn to_raw(&self, index: usize) -> InstanceRaw {
+        let mut raw = self.set[index].to_raw(&self.parents[index]);
+        if let Some(highlight) = self.highlights[index] {
+            raw.tint = highlight;
+        }
+        raw
+    }
+
+    // Orders the instances by group and material and rebuilds the draw ranges. A stable sort,
+    // so instances that compare equal keep their order and already sorted sets upload nothing.
+    fn sort(&mut self) {
+        let key = |set: &[Box<dyn MatrixInstance>], groups: &[RenderGroup], index: usize| {
+            (groups[index], set[index].material_index())
+        };
+        let mut order: Vec<usize> = (0..self.set.len()).collect();
+        order.sort_by_key(|&index| key(&self.set, &self.groups, index));
+        if order.iter().enumerate().any(|(index, &from)| index != from) {
+            let mut set: Vec<_> = self.set.drain(..).map(Some).collect();
+            self.set = order
+                .iter()
+                .map(|&from| set[from].take().unwrap())
+                .collect();
+            self.handles = order.iter().map(|&from| self.handles[from]).collect();
+            self.groups = order.iter().map(|&from| self.groups[from]).collect();
+            self.parents = order.iter().map(|&from| self.parents[from]).collect();
+            self.highlights = order.iter().map(|&from| self.highlights[from]).collect();
+            for (index, handle) in self.handles.iter().enumerate() {
+                self.slots[handle.slot as usize].index = Some(index);
+            }
+            self.mark_range_dirty(0..self.set.len());
+        }
+
+        self.draw_ranges.clear();
+        for index in 0..self.set.len() {
+            let (group, material_index) = key(&self.set, &self.groups, index);
+            match self.draw_ranges.last_mut() {
+                Some(range) if range.group == group && range.material_index == material_index => {
+                    range.instances.end += 1
+                }
+                _ => self.draw_ranges.push(DrawRange {
+                    group,
+                    material_index,
+                    lod: 0,
+                    instances: index as u32..index as u32 + 1,
+                }),
+            }
+        }
+        self.sorted = true;
+    }
+
+    pub fn make(set: Vec<(Box<dyn MatrixInstance>, RenderGroup)>) -> InstanceSet {
+        let mut instance_set = InstanceSet {
+            set: Vec::with_capacity(set.len()),
+            handles: Vec::with_capacity(set.len()),
+            groups: Vec::with_capacity(set.len()),
+            parents: Vec::with_capacity(set.len()),
+            highlights: Vec::with_capacity(set.len()),
+            slots: Vec::with_capacity(set.len()),
+            free_slots: Vec::new(),
+            sorted: true,
+            draw_ranges: Vec::new(),
+            raw: Vec::with_capacity(set.len()),
+            buffer: None,
+            capacity: 0,
+            dirty: Vec::new(),
+        };
+        for (instance, group) in set {
+            instance_set.insert(instance, group);
+        }
+        instance_set.sort();
+        instance_set
+    }
+
+    pub fn insert(
+        &mut self,
+        instance: Box<dyn MatrixInstance>,
+        group: RenderGroup,
+    ) -> InstanceHandle {
+        let index = self.set.len();
+        let handle = match self.free_slots.pop() {
+            Some(slot) => {
+                let entry = &mut self.slots[slot as usize];
+                entry.index = Some(index);
+                InstanceHandle {
+                    slot,
+                    generation: entry.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    index: Some(index),
+                });
+                InstanceHandle {
+                    slot: (self.slots.len() - 1) as u32,
+                    generation: 0,
+                }
+            }
+        };
+        self.set.push(instance);
+        self.handles.push(handle);
+        self.groups.push(group);
+        self.parents.push(cgmath::Matrix4::identity());
+        self.highlights.push(None);
+        self.mark_dirty(index);
+        self.sorted = false;
+        handle
     }
-    pub fn make(set: Vec<Box<dyn MatrixInstance>>) -> InstanceSet {
-        InstanceSet { set, buffer: None }
+
+    pub fn remove(&mut self, handle: InstanceHandle) -> Option<Box<dyn MatrixInstance>> {
+        let index = self.index_of(handle)?;
+        let slot = &mut self.slots[handle.slot as usize];
+        slot.index = None;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(handle.slot);
+
+        let instance = self.set.swap_remove(index);
+        self.handles.swap_remove(index);
+        self.groups.swap_remove(index);
+        self.parents.swap_remove(index);
+        self.highlights.swap_remove(index);
+        if let Some(moved) = self.handles.get(index) {
+            self.slots[moved.slot as usize].index = Some(index);
+            self.mark_dirty(index);
+        }
+        self.sorted = false;
+        Some(instance)
     }
+
+    pub fn contains(&self, handle: InstanceHandle) -> bool {
+        self.index_of(handle).is_some()
+    }
+
+    pub fn get(&self, handle: InstanceHandle) -> Option<&dyn MatrixInstance> {
+        self.index_of(handle).map(|index| self.set[index].as_ref())
+    }
+
+    // The instance is uploaded again on the next update_buffer, and moved if its material changed
+    pub fn get_mut(&mut self, handle: InstanceHandle) -> Option<&mut Box<dyn MatrixInstance>> {
+        let index = self.index_of(handle)?;
+        self.mark_dirty(index);
+        self.sorted = false;
+        Some(&mut self.set[index])
+    }
+
+    // Places the instance relative to a scene graph node, see SceneGraph::update
+    pub fn set_parent_matrix(&mut self, handle: InstanceHandle, parent: cgmath::Matrix4<f32>) {
+        if let Some(index) = self.index_of(handle) {
+            self.parents[index] = parent;
+            self.mark_dirty(index);
+        }
+    }
+
+    // Drawn with this tint instead of its own until it's set back to None
+    pub fn set_highlight(&mut self, handle: InstanceHandle, highlight: Option<[f32; 4]>) {
+        if let Some(index) = self.index_of(handle) {
+            self.highlights[index] = highlight;
+            self.mark_dirty(index);
+        }
+    }
+
+    // The tint it's drawn with, the highlight while one is set
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn get_tint(&self, handle: InstanceHandle) -> Option<[f32; 4]> {
+        self.index_of(handle).map(|index| self.to_raw(index).tint)
+    }
+
+    pub fn get_group(&self, handle: InstanceHandle) -> Option<RenderGroup> {
+        self.index_of(handle).map(|index| self.groups[index])
+    }
+
+    // The instance's model matrix placed by its parent, as it's uploaded
+    pub fn get_world_matrix(&self, handle: InstanceHandle) -> Option<cgmath::Matrix4<f32>> {
+        self.index_of(handle)
+            .map(|index| self.parents[index] * self.set[index].model())
+    }
+
+    // Valid after update_buffer, drawing them covers every instance once
+    pub fn draw_ranges(&self) -> &[DrawRange] {
+        &self.draw_ranges
+    }
+
+    // Position of the instance in the buffer, changes when other instances are added or removed
+    pub fn index_of(&self, handle: InstanceHandle) -> Option<usize> {
+        self.slots
+            .get(handle.slot as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.index)
+    }
+
+    pub fn handles(&self) -> &[InstanceHandle] {
+        &self.handles
+    }
+
+    // Animates every instance, only the ones that changed get uploaded. An animation can't move
+    // an instance to another group, so the order stays.
+    pub fn update(&mut self, time: f32) {
+        for index in 0..self.set.len() {
+            if self.set[index].update(time) {
+                self.mark_dirty(index);
+            }
+        }
+    }
+
+    fn mark_dirty(&mut self, index: usize) {
+        self.mark_range_dirty(index..index + 1);
+    }
+
+    fn mark_range_dirty(&mut self, range: std::ops::Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        match self.dirty.last_mut() {
+            Some(last) if range.start <= last.end && last.start <= range.end => {
+                last.start = last.start.min(range.start);
+                last.end = last.end.max(range.end);
+            }
+            _ => self.dirty.push(range),
+        }
+    }
+
     pub fn get_buffer(&self) -> Option<&wgpu::Buffer> {
         self.buffer.as_ref()
     }
+
+    // The uploaded instances in buffer order, valid after update_buffer
+    pub fn get_raw(&self) -> &[InstanceRaw] {
+        &self.raw
+    }
+
     pub fn count(&self) -> usize {
         self.set.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // told apart by x, with a material to sort by
+    struct Tagged {
+        x: f32,
+        material_index: u32,
+    }
+
+    impl MatrixInstance for Tagged {
+        fn model(&self) -> cgmath::Matrix4<f32> {
+            cgmath::Matrix4::from_translation(cgmath::vec3(self.x, 0.0, 0.0))
+        }
+
+        fn material_index(&self) -> u32 {
+            self.material_index
+        }
+    }
+
+    fn tagged(x: f32, material_index: u32) -> Box<dyn MatrixInstance> {
+        Box::new(Tagged { x, material_index })
+    }
+
+    fn x_of(set: &InstanceSet, handle: InstanceHandle) -> Option<f32> {
+        set.get(handle).map(|instance| instance.model().w.x)
+    }
+
+    // the x of every instance in buffer order
+    fn order(set: &InstanceSet) -> Vec<f32> {
+        set.set
+            .iter()
+            .map(|instance| instance.model().w.x)
+            .collect()
+    }
+
+    #[test]
+    fn removed_handles_stay_stale_when_the_slot_is_reused() {
+        let mut set = InstanceSet::make(Vec::new());
+        let old = set.insert(tagged(1.0, 0), RenderGroup::Solid);
+        assert!(set.remove(old).is_some());
+        assert!(!set.contains(old));
+        assert!(set.remove(old).is_none());
+
+        let new = set.insert(tagged(2.0, 0), RenderGroup::Solid);
+        assert_eq!(new.slot, old.slot);
+        assert_ne!(new.generation, old.generation);
+        assert!(set.get(old).is_none());
+        assert!(set.get_mut(old).is_none());
+        assert!(set.remove(old).is_none());
+        assert_eq!(x_of(&set, new), Some(2.0));
+        assert_eq!(set.count(), 1);
+    }
+
+    #[test]
+    fn remove_moves_the_last_instance_into_the_gap() {
+        let mut set = InstanceSet::make(Vec::new());
+        let handles: Vec<_> = (0..4)
+            .map(|x| set.insert(tagged(x as f32, 0), RenderGroup::Solid))
+            .collect();
+        set.dirty.clear();
+        set.remove(handles[1]);
+        assert_eq!(order(&set), vec![0.0, 3.0, 2.0]);
+        assert_eq!(set.index_of(handles[3]), Some(1));
+        assert_eq!(set.handles(), &[handles[0], handles[3], handles[2]]);
+        // only the moved instance has to be uploaded again
+        assert_eq!(set.take_dirty(), vec![1..2]);
+
+        // removing the last one moves nothing
+        set.remove(handles[2]);
+        assert_eq!(order(&set), vec![0.0, 3.0]);
+        assert!(set.take_dirty().is_empty());
+        for &handle in &[handles[0], handles[3]] {
+            assert_eq!(set.handles()[set.index_of(handle).unwrap()], handle);
+        }
+    }
+
+    #[test]
+    fn sort_groups_by_group_and_material_keeping_the_order() {
+        let mut set = InstanceSet::make(Vec::new());
+        let handles: Vec<_> = [
+            (0.0, 1, RenderGroup::Solid),
+            (1.0, 0, RenderGroup::Textured),
+            (2.0, 0, RenderGroup::Solid),
+            (3.0, 1, RenderGroup::Textured),
+            (4.0, 1, RenderGroup::Solid),
+            (5.0, 0, RenderGroup::Textured),
+        ]
+        .into_iter()
+        .map(|(x, material_index, group)| set.insert(tagged(x, material_index), group))
+        .collect();
+        set.sort();
+        assert_eq!(order(&set), vec![1.0, 5.0, 3.0, 2.0, 0.0, 4.0]);
+        let range = |group, material_index, instances| DrawRange {
+            group,
+            material_index,
+            lod: 0,
+            instances,
+        };
+        assert_eq!(
+            set.draw_ranges(),
+            &[
+                range(RenderGroup::Textured, 0, 0..2),
+                range(RenderGroup::Textured, 1, 2..3),
+                range(RenderGroup::Solid, 0, 3..4),
+                range(RenderGroup::Solid, 1, 4..6),
+            ]
+        );
+        for handle in handles {
+            assert_eq!(set.handles()[set.index_of(handle).unwrap()], handle);
+        }
+        assert_eq!(set.take_dirty(), vec![0..6]);
+
+        // sorted already, nothing moves and nothing is uploaded
+        set.sort();
+        assert!(set.take_dirty().is_empty());
+        assert_eq!(set.draw_ranges().len(), 4);
+    }
+
+    #[test]
+    fn dirty_ranges_merge_where_they_overlap_or_touch() {
+        let mut set = InstanceSet::make(Vec::new());
+        set.dirty = vec![5..6, 0..2, 1..3, 9..10, 6..7];
+        assert_eq!(set.take_dirty(), vec![0..3, 5..7, 9..10]);
+        assert!(set.dirty.is_empty());
+
+        // neighbours marked one after another are merged as they come
+        for index in [3, 4, 2, 8] {
+            set.mark_dirty(index);
+        }
+        assert_eq!(set.dirty, vec![2..5, 8..9]);
+    }
+}
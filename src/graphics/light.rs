@@ -0,0 +1,62 @@
+use wgpu::util::DeviceExt;
+
+use super::camera::Camera;
+
+// A directional light. Its direction follows the camera used to render the shadow map,
+// so the lit shading and the shadow always agree.
+pub struct Light {
+    pub color: cgmath::Vector3<f32>,
+    pub intensity: f32,
+    buffer: Option<wgpu::Buffer>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    // direction the light travels in, w unused
+    direction: [f32; 4],
+    // rgb radiance, already multiplied by the intensity
+    color: [f32; 4],
+}
+
+impl Light {
+    pub fn make(color: cgmath::Vector3<f32>, intensity: f32) -> Light {
+        Light {
+            color,
+            intensity,
+            buffer: None,
+        }
+    }
+
+    pub fn get_light_uniform(&self, light_camera: &Camera) -> LightUniform {
+        let direction = light_camera.get_forward();
+        let radiance = self.color * self.intensity;
+        LightUniform {
+            direction: direction.extend(0.0).into(),
+            color: radiance.extend(1.0).into(),
+        }
+    }
+
+    pub fn get_buffer(&self) -> Option<&wgpu::Buffer> {
+        self.buffer.as_ref()
+    }
+
+    pub fn create_buffer(&mut self, device: &wgpu::Device, light_camera: &Camera) {
+        let light_uniform = self.get_light_uniform(light_camera);
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&[light_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        self.buffer = Some(light_buffer);
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, light_camera: &Camera) {
+        let light_uniform = self.get_light_uniform(light_camera);
+        queue.write_buffer(
+            self.get_buffer().unwrap(),
+            0,
+            bytemuck::cast_slice(&[light_uniform]),
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+use super::camera::Camera;
+use super::culling::Aabb;
+use super::instance::{DrawRange, InstanceHandle, InstanceSet, RenderGroup};
+use super::lod_file::LodFile;
+use super::mesh::Mesh;
+use super::simplify::simplify;
+use super::vertex::{generate_tangents, Vertex};
+
+// Meshes of one model from full detail down, the first level is the full mesh
+pub struct LodChain {
+    meshes: Vec<Mesh>,
+    thresholds: LodThresholds,
+}
+
+impl LodChain {
+    // How far past a threshold the screen size has to go before an instance changes level
+    const HYSTERESIS: f32 = 0.15;
+
+    // Hand made levels as (mesh, min screen size), finest first. The last level is drawn
+    // however small the instance gets.
+    pub fn make(levels: Vec<(Mesh, f32)>) -> Result<Self> {
+        if levels.is_empty() {
+            bail!("A LOD chain needs at least one level");
+        }
+        let last = levels.len() - 1;
+        if levels[..last].windows(2).any(|pair| pair[1].1 >= pair[0].1) {
+            bail!("LOD screen sizes have to get smaller with each level");
+        }
+        let (meshes, mut min_screen_sizes): (Vec<_>, Vec<_>) = levels.into_iter().unzip();
+        min_screen_sizes[last] = 0.0;
+        Ok(Self {
+            meshes,
+            thresholds: LodThresholds { min_screen_sizes },
+        })
+    }
+
+    // Simplifies the mesh once per level. Each level is (fraction of the triangles to keep,
+    // min screen size), full_screen_size is the min screen size of the full mesh.
+    pub fn generate(
+        device: &wgpu::Device,
+        vertices: &[Vertex],
+        indices: &[u16],
+        full_screen_size: f32,
+        levels: &[(f32, f32)],
+        label: &str,
+    ) -> Result<Self> {
+        let positions: Vec<[f32; 3]> = vertices.iter().map(Vertex::get_position).collect();
+        let source: Vec<u32> = indices.iter().map(|&index| index as u32).collect();
+        let mut chain = vec![(
+            Mesh::make(device, vertices, indices, label),
+            full_screen_size,
+        )];
+        for (level, &(ratio, min_screen_size)) in levels.iter().enumerate() {
+            let target = (source.len() as f32 * ratio) as usize;
+            let simplified: Vec<u16> = simplify(&positions, &source, target)
+                .into_iter()
+                .map(|index| index as u16)
+                .collect();
+            let label = format!("{} LOD {}", label, level + 1);
+            chain.push((
+                Mesh::make(device, vertices, &simplified, &label),
+                min_screen_size,
+            ));
+        }
+        Self::make(chain)
+    }
+
+    // A chain made offline by the lod_tool binary, see LodFile::load
+    pub fn from_file(device: &wgpu::Device, file: &LodFile, label: &str) -> Result<Self> {
+        if file.vertices.len() > u16::MAX as usize + 1 {
+            bail!(
+                "{} has {} vertices, meshes take 16 bit indices",
+                label,
+                file.vertices.len()
+            );
+        }
+        let mut vertices: Vec<Vertex> = file
+            .vertices
+            .iter()
+            .map(|vertex| Vertex::make(vertex.position, vertex.uv, vertex.normal))
+            .collect();
+        generate_tangents(&mut vertices, &file.levels[0].indices);
+        Self::make(
+            file.levels
+                .iter()
+                .enumerate()
+                .map(|(level, lod)| {
+                    let indices: Vec<u16> = lod.indices.iter().map(|&index| index as u16).collect();
+                    let label = format!("{} LOD {}", label, level);
+                    (
+                        Mesh::make(device, &vertices, &indices, &label),
+                        lod.min_screen_size,
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub fn get_level(&self, lod: u32) -> &Mesh {
+        &self.meshes[(lod as usize).min(self.meshes.len() - 1)]
+    }
+
+    // Of the full mesh, the levels are all inside it
+    pub fn get_bounds(&self) -> Aabb {
+        self.meshes[0].get_bounds()
+    }
+
+    // The coarsest level the screen size allows. Once an instance has a level it only moves
+    // when the size is past the threshold by the hysteresis, so it doesn't flicker between two.
+    pub fn select(&self, screen_size: f32, current: Option<u32>) -> u32 {
+        self.thresholds.select(screen_size, current)
+    }
+}
+
+// The selection apart from the meshes, so it can be tested without a device
+struct LodThresholds {
+    // level i is drawn while the instance covers at least min_screen_sizes[i] of the screen
+    // height, 0 for the last level
+    min_screen_sizes: Vec<f32>,
+}
+
+impl LodThresholds {
+    fn select(&self, screen_size: f32, current: Option<u32>) -> u32 {
+        let last = self.min_screen_sizes.len() - 1;
+        let level_for = |scale: f32| {
+            self.min_screen_sizes
+                .iter()
+                .position(|&min_screen_size| screen_size >= min_screen_size * scale)
+                .unwrap_or(last) as u32
+        };
+        match current {
+            None => level_for(1.0),
+            Some(current) => {
+                let current = current.min(last as u32);
+                let coarser = level_for(1.0 - LodChain::HYSTERESIS);
+                let finer = level_for(1.0 + LodChain::HYSTERESIS);
+                if coarser > current {
+                    coarser
+                } else if finer < current {
+                    finer
+                } else {
+                    current
+                }
+            }
+        }
+    }
+}
+
+// The level of every instance, picked once a frame from the main camera and shared by every
+// pass so the shadows match what's drawn
+pub struct LodSelector {
+    enabled: bool,
+    // kept between frames for the hysteresis
+    current: HashMap<InstanceHandle, u32>,
+    // in buffer order
+    levels: Vec<u32>,
+}
+
+impl LodSelector {
+    pub fn make() -> Self {
+        Self {
+            enabled: true,
+            current: HashMap::new(),
+            levels: Vec::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Disabled, every instance is drawn at full detail
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.current.clear();
+    }
+
+    // bounds from compute_world_bounds
+    pub fn update<'a>(
+        &mut self,
+        instance_set: &InstanceSet,
+        bounds: &[Aabb],
+        camera: &Camera,
+        chain: impl Fn(RenderGroup) -> &'a LodChain,
+    ) {
+        self.levels.clear();
+        self.levels.resize(instance_set.count(), 0);
+        if !self.enabled {
+            return;
+        }
+        let handles = instance_set.handles();
+        let mut current = HashMap::with_capacity(handles.len());
+        for range in instance_set.draw_ranges() {
+            let chain = chain(range.group);
+            for index in range.instances.start as usize..range.instances.end as usize {
+                let bounds = &bounds[index];
+                let radius = cgmath::InnerSpace::magnitude(bounds.get_extent());
+                let screen_size = camera.get_screen_size(bounds.get_center(), radius);
+                let level = chain.select(screen_size, self.current.get(&handles[index]).copied());
+                self.levels[index] = level;
+                current.insert(handles[index], level);
+            }
+        }
+        // dropping the removed instances
+        self.current = current;
+    }
+
+    // The level of every instance in buffer order, valid after update
+    pub fn get_levels(&self) -> &[u32] {
+        &self.levels
+    }
+}
+
+// Splits every draw range into one bucket per level, for the instances laid out bucket after
+// bucket from 0. Returns the buckets and the bucket of each instance in buffer order.
+pub fn bucket_by_level(draw_ranges: &[DrawRange], levels: &[u32]) -> (Vec<DrawRange>, Vec<u32>) {
+    let mut buckets = Vec::new();
+    let mut bucket_of = vec![0; levels.len()];
+    let mut first = 0;
+    for range in draw_ranges {
+        let instances = range.instances.start as usize..range.instances.end as usize;
+        let level_count = levels[instances.clone()]
+            .iter()
+            .max()
+            .map_or(0, |&max| max + 1);
+        for lod in 0..level_count {
+            let count = levels[instances.clone()]
+                .iter()
+                .filter(|&&level| level == lod)
+                .count() as u32;
+            if count == 0 {
+                continue;
+            }
+            for index in instances.clone() {
+                if levels[index] == lod {
+                    bucket_of[index] = buckets.len() as u32;
+                }
+            }
+            buckets.push(DrawRange {
+                group: range.group,
+                material_index: range.material_index,
+                lod,
+                instances: first..first + count,
+            });
+            first += count;
+        }
+    }
+    (buckets, bucket_of)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thresholds() -> LodThresholds {
+        LodThresholds {
+            min_screen_sizes: vec![0.5, 0.25, 0.1, 0.0],
+        }
+    }
+
+    #[test]
+    fn select_switches_at_the_thresholds() {
+        let thresholds = thresholds();
+        for (screen_size, level) in [
+            (1.0, 0),
+            (0.5, 0),
+            (0.49, 1),
+            (0.25, 1),
+            (0.24, 2),
+            (0.1, 2),
+            (0.09, 3),
+            (0.0, 3),
+        ] {
+            assert_eq!(
+                thresholds.select(screen_size, None),
+                level,
+                "{}",
+                screen_size
+            );
+        }
+    }
+
+    #[test]
+    fn select_keeps_the_level_inside_the_hysteresis_band() {
+        let thresholds = thresholds();
+        // getting smaller, level 0 holds down to 0.5 * 0.85
+        assert_eq!(thresholds.select(0.45, Some(0)), 0);
+        assert_eq!(thresholds.select(0.43, Some(0)), 0);
+        assert_eq!(thresholds.select(0.42, Some(0)), 1);
+        // getting bigger, level 1 holds up to 0.5 * 1.15
+        assert_eq!(thresholds.select(0.55, Some(1)), 1);
+        assert_eq!(thresholds.select(0.57, Some(1)), 1);
+        assert_eq!(thresholds.select(0.58, Some(1)), 0);
+        // and down to 0.25 * 0.85
+        assert_eq!(thresholds.select(0.22, Some(1)), 1);
+        assert_eq!(thresholds.select(0.21, Some(1)), 2);
+    }
+
+    #[test]
+    fn select_jumps_several_levels_and_clamps_the_current_one() {
+        let thresholds = thresholds();
+        assert_eq!(thresholds.select(0.05, Some(0)), 3);
+        assert_eq!(thresholds.select(1.0, Some(3)), 0);
+        assert_eq!(thresholds.select(0.01, Some(7)), 3);
+        assert_eq!(thresholds.select(0.3, Some(7)), 1);
+    }
+
+    #[test]
+    fn bucket_by_level_orders_by_range_then_level() {
+        let range = |group, instances| DrawRange {
+            group,
+            material_index: 0,
+            lod: 0,
+            instances,
+        };
+        let draw_ranges = [
+            range(RenderGroup::Textured, 0..4),
+            range(RenderGroup::Solid, 4..7),
+        ];
+        // the textured range has nothing at level 1
+        let levels = [2, 0, 2, 0, 1, 0, 1];
+        let (buckets, bucket_of) = bucket_by_level(&draw_ranges, &levels);
+        let bucket = |group, lod, instances| DrawRange {
+            group,
+            material_index: 0,
+            lod,
+            instances,
+        };
+        assert_eq!(
+            buckets,
+            vec![
+                bucket(RenderGroup::Textured, 0, 0..2),
+                bucket(RenderGroup::Textured, 2, 2..4),
+                bucket(RenderGroup::Solid, 0, 4..5),
+                bucket(RenderGroup::Solid, 1, 5..7),
+            ]
+        );
+        assert_eq!(bucket_of, vec![1, 0, 1, 0, 3, 2, 3]);
+    }
+}
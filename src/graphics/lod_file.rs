@@ -0,0 +1,210 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+// A mesh and its LOD chain, written by the lod_tool binary. Little endian:
+//
+//   magic "LODM", version u32, vertex count u32, level count u32
+//   per vertex: position 3 x f32, uv 2 x f32, normal 3 x f32
+//   per level: min screen size f32, index count u32, that many u32 indices
+//
+// Every level indexes the same vertices, finest level first.
+pub const MAGIC: &[u8; 4] = b"LODM";
+pub const VERSION: u32 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LodVertex {
+    pub position: [f32; 3],
+    pub uv: [f32; 2],
+    pub normal: [f32; 3],
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LodFileLevel {
+    // see LodChain::make, ignored for the last level
+    pub min_screen_size: f32,
+    pub indices: Vec<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LodFile {
+    pub vertices: Vec<LodVertex>,
+    pub levels: Vec<LodFileLevel>,
+}
+
+impl LodFile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&bytes).with_context(|| format!("invalid LOD file {}", path.display()))
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            bail!("not a LOD file");
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            bail!("version {}, expected {}", version, VERSION);
+        }
+        let vertex_count = reader.u32()? as usize;
+        let level_count = reader.u32()? as usize;
+        if level_count == 0 {
+            bail!("no levels");
+        }
+
+        let mut vertices = Vec::with_capacity(vertex_count.min(bytes.len() / 32));
+        for _ in 0..vertex_count {
+            vertices.push(LodVertex {
+                position: reader.floats()?,
+                uv: reader.floats()?,
+                normal: reader.floats()?,
+            });
+        }
+        let mut levels = Vec::with_capacity(level_count.min(bytes.len() / 8));
+        for level in 0..level_count {
+            let min_screen_size = reader.f32()?;
+            let index_count = reader.u32()? as usize;
+            if !index_count.is_multiple_of(3) {
+                bail!(
+                    "level {} has {} indices, not whole triangles",
+                    level,
+                    index_count
+                );
+            }
+            let indices = (0..index_count)
+                .map(|_| reader.u32())
+                .collect::<Result<Vec<_>>>()?;
+            if let Some(index) = indices
+                .iter()
+                .find(|&&index| index as usize >= vertex_count)
+            {
+                bail!(
+                    "level {} indexes vertex {} of {}",
+                    level,
+                    index,
+                    vertex_count
+                );
+            }
+            levels.push(LodFileLevel {
+                min_screen_size,
+                indices,
+            });
+        }
+        if !reader.bytes.is_empty() {
+            bail!("{} bytes past the last level", reader.bytes.len());
+        }
+        Ok(Self { vertices, levels })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < count {
+            bail!("file ends early");
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn floats<const N: usize>(&mut self) -> Result<[f32; N]> {
+        let mut floats = [0.0; N];
+        for float in floats.iter_mut() {
+            *float = self.f32()?;
+        }
+        Ok(floats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A triangle with one level, laid out by hand
+    fn triangle_bytes() -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for value in [VERSION, 3, 1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for position in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            for value in position.iter().chain(&[0.0, 0.0]).chain(&[0.0, 0.0, 1.0]) {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&0.5f32.to_le_bytes());
+        for value in [3u32, 0, 1, 2] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        LodFile::parse(bytes).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_a_triangle() {
+        let file = LodFile::parse(&triangle_bytes()).unwrap();
+        assert_eq!(file.vertices.len(), 3);
+        assert_eq!(file.vertices[1].position, [1.0, 0.0, 0.0]);
+        assert_eq!(file.vertices[2].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(
+            file.levels,
+            vec![LodFileLevel {
+                min_screen_size: 0.5,
+                indices: vec![0, 1, 2],
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let bytes = triangle_bytes();
+        // cut inside the header, a vertex and the indices
+        for length in [0, 6, 30, bytes.len() - 1] {
+            assert_eq!(error(&bytes[..length]), "file ends early");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = triangle_bytes();
+        bytes[..4].copy_from_slice(b"OBJ ");
+        assert_eq!(error(&bytes), "not a LOD file");
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut bytes = triangle_bytes();
+        bytes.extend_from_slice(&[0; 3]);
+        assert_eq!(error(&bytes), "3 bytes past the last level");
+    }
+
+    #[test]
+    fn rejects_bad_indices() {
+        let mut bytes = triangle_bytes();
+        let last = bytes.len() - 4;
+        bytes[last..].copy_from_slice(&3u32.to_le_bytes());
+        assert_eq!(error(&bytes), "level 0 indexes vertex 3 of 3");
+
+        let mut bytes = triangle_bytes();
+        bytes.truncate(bytes.len() - 4);
+        let count = bytes.len() - 12;
+        bytes[count..count + 4].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(error(&bytes), "level 0 has 2 indices, not whole triangles");
+    }
+}
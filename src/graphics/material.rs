@@ -0,0 +1,192 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use std::rc::Rc;
+
+use super::texture::Texture;
+
+// Metallic-roughness material following the glTF 2.0 conventions:
+// - albedo (base color) is sRGB, multiplied by base_color_factor
+// - metallic_roughness stores roughness in G and metallic in B (linear)
+// - occlusion stores ambient occlusion in R (linear)
+// - emissive is sRGB, multiplied by emissive_factor
+// - normal is a tangent space normal map (linear), xy scaled by normal_scale
+// Maps are shared handles, usually from the TextureCache, so materials can reuse them.
+pub struct Material {
+    pub base_color_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    pub normal_scale: f32,
+
+    albedo: Rc<Texture>,
+    metallic_roughness: Rc<Texture>,
+    occlusion: Rc<Texture>,
+    emissive: Rc<Texture>,
+    normal: Rc<Texture>,
+
+    buffer: Option<wgpu::Buffer>,
+    bind_group: Option<wgpu::BindGroup>,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    base_color_factor: [f32; 4],
+    // rgb emissive factor, w is the occlusion strength
+    emissive_factor: [f32; 4],
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    _padding: f32,
+}
+
+pub struct MaterialTextures {
+    pub albedo: Option<Rc<Texture>>,
+    pub metallic_roughness: Option<Rc<Texture>>,
+    pub occlusion: Option<Rc<Texture>>,
+    pub emissive: Option<Rc<Texture>>,
+    pub normal: Option<Rc<Texture>>,
+}
+
+impl Material {
+    pub fn make(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        textures: MaterialTextures,
+        metallic_factor: f32,
+        roughness_factor: f32,
+    ) -> Result<Material> {
+        // Missing maps are replaced by white so the factors alone drive the result
+        let or_default = |texture: Option<Rc<Texture>>, color, label| match texture {
+            Some(texture) => Ok(texture),
+            None => Texture::from_color(device, queue, color, label).map(Rc::new),
+        };
+        let white = [255, 255, 255, 255];
+        Ok(Material {
+            base_color_factor: [1.0, 1.0, 1.0, 1.0],
+            metallic_factor,
+            roughness_factor,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0, 0.0, 0.0],
+            normal_scale: 1.0,
+
+            albedo: or_default(textures.albedo, white, "default_albedo")?,
+            metallic_roughness: or_default(
+                textures.metallic_roughness,
+                white,
+                "default_metallic_roughness",
+            )?,
+            occlusion: or_default(textures.occlusion, white, "default_occlusion")?,
+            emissive: or_default(textures.emissive, white, "default_emissive")?,
+            // straight up in tangent space
+            normal: or_default(textures.normal, [128, 128, 255, 255], "default_normal")?,
+
+            buffer: None,
+            bind_group: None,
+        })
+    }
+
+    pub fn get_material_uniform(&self) -> MaterialUniform {
+        let [r, g, b] = self.emissive_factor;
+        MaterialUniform {
+            base_color_factor: self.base_color_factor,
+            emissive_factor: [r, g, b, self.occlusion_strength],
+            metallic_factor: self.metallic_factor,
+            roughness_factor: self.roughness_factor,
+            normal_scale: self.normal_scale,
+            _padding: 0.0,
+        }
+    }
+
+    pub fn get_bind_group(&self) -> Option<&wgpu::BindGroup> {
+        self.bind_group.as_ref()
+    }
+
+    pub fn create_bind_group(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Buffer"),
+            contents: bytemuck::cast_slice(&[self.get_material_uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let maps = [
+            &self.albedo,
+            &self.metallic_roughness,
+            &self.occlusion,
+            &self.emissive,
+            &self.normal,
+        ];
+        let mut entries = maps
+            .iter()
+            .enumerate()
+            .flat_map(|(i, texture)| {
+                [
+                    wgpu::BindGroupEntry {
+                        binding: 2 * i as u32,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2 * i as u32 + 1,
+                        resource: wgpu::BindingResource::Sampler(texture.sampler.as_ref().unwrap()),
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+        entries.push(wgpu::BindGroupEntry {
+            binding: Self::UNIFORM_BINDING,
+            resource: buffer.as_entire_binding(),
+        });
+
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some("material_bind_group"),
+        }));
+        self.buffer = Some(buffer);
+    }
+
+    const MAP_COUNT: u32 = 5;
+    const UNIFORM_BINDING: u32 = 2 * Self::MAP_COUNT;
+
+    pub fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let mut entries = (0..Self::MAP_COUNT)
+            .flat_map(|i| {
+                [
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2 * i,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2 * i + 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+        entries.push(wgpu::BindGroupLayoutEntry {
+            binding: Self::UNIFORM_BINDING,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("material_bind_group_layout"),
+        })
+    }
+}
@@ -0,0 +1,52 @@
+use wgpu::util::DeviceExt;
+
+use super::culling::Aabb;
+use super::vertex::Vertex;
+
+// Vertices and 16 bit indices on the GPU
+pub struct Mesh {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_len: u32,
+    bounds: Aabb,
+}
+
+impl Mesh {
+    pub fn make(device: &wgpu::Device, vertices: &[Vertex], indices: &[u16], label: &str) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", label)),
+            usage: wgpu::BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(vertices),
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", label)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let bounds = Aabb::from_points(vertices.iter().map(|vertex| vertex.get_position().into()))
+            .unwrap_or_else(|| {
+                Aabb::make(cgmath::vec3(0.0, 0.0, 0.0), cgmath::vec3(0.0, 0.0, 0.0))
+            });
+        Self {
+            vertex_buffer,
+            index_buffer,
+            index_len: indices.len() as u32,
+            bounds,
+        }
+    }
+
+    // Binds the mesh to vertex slot 0, instances go in slot 1
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+    }
+
+    pub fn get_index_len(&self) -> u32 {
+        self.index_len
+    }
+
+    // In model space, around the undeformed vertices
+    pub fn get_bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
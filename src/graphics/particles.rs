@@ -0,0 +1,399 @@
+use cgmath::InnerSpace;
+
+use super::camera::Camera;
+use super::texture::Texture;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParticleBlend {
+    // brightens what's behind, order doesn't matter. Sparks, fire.
+    Additive,
+    // covers what's behind by the alpha, drawn unsorted. Smoke, dust.
+    Alpha,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct EmitterDescription {
+    pub position: cgmath::Vector3<f32>,
+    // particles alive at once, spawns that find every slot taken are dropped
+    pub max_particles: u32,
+    // particles per second
+    pub rate: f32,
+    // in seconds, each particle picks one in the range
+    pub lifetime: (f32, f32),
+    pub direction: cgmath::Vector3<f32>,
+    // half angle in degrees of the cone the particles start in
+    pub cone_angle: f32,
+    pub speed: (f32, f32),
+    pub gravity: cgmath::Vector3<f32>,
+    // blended over the life of a particle
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    pub start_size: f32,
+    pub end_size: f32,
+    pub blend: ParticleBlend,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Particle {
+    position: [f32; 3],
+    age: f32,
+    velocity: [f32; 3],
+    lifetime: f32,
+}
+
+// Shared by the simulation and the billboards
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct EmitterUniform {
+    position: [f32; 3],
+    spawn_count: u32,
+    direction: [f32; 3],
+    cone_cos: f32,
+    gravity: [f32; 3],
+    delta: f32,
+    start_color: [f32; 4],
+    end_color: [f32; 4],
+    lifetime: [f32; 2],
+    speed: [f32; 2],
+    start_size: f32,
+    end_size: f32,
+    seed: u32,
+    max_particles: u32,
+    camera_right: [f32; 4],
+    camera_up: [f32; 4],
+}
+
+struct Emitter {
+    description: EmitterDescription,
+    // fraction of a particle carried over to the next frame
+    pending: f32,
+    uniform_buffer: wgpu::Buffer,
+    spawn_buffer: wgpu::Buffer,
+    compute_bind_group: wgpu::BindGroup,
+    render_bind_group: wgpu::BindGroup,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EmitterId(usize);
+
+// Emitters whose particles live only on the GPU. A compute pass moves and respawns them, the
+// main pass draws them after the opaque geometry, tested against its depth without writing it.
+pub struct ParticleSystem {
+    emitters: Vec<Emitter>,
+    frame: u32,
+    compute_bind_group_layout: wgpu::BindGroupLayout,
+    render_bind_group_layout: wgpu::BindGroupLayout,
+    compute_pipeline: wgpu::ComputePipeline,
+    additive_pipeline: wgpu::RenderPipeline,
+    alpha_pipeline: wgpu::RenderPipeline,
+}
+
+impl ParticleSystem {
+    const WORKGROUP_SIZE: u32 = 64;
+
+    pub fn make(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    buffer_layout_entry(
+                        0,
+                        wgpu::ShaderStages::COMPUTE,
+                        wgpu::BufferBindingType::Uniform,
+                    ),
+                    buffer_layout_entry(
+                        1,
+                        wgpu::ShaderStages::COMPUTE,
+                        wgpu::BufferBindingType::Storage { read_only: false },
+                    ),
+                    buffer_layout_entry(
+                        2,
+                        wgpu::ShaderStages::COMPUTE,
+                        wgpu::BufferBindingType::Storage { read_only: false },
+                    ),
+                ],
+                label: Some("particle_simulation_bind_group_layout"),
+            });
+        let render_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    buffer_layout_entry(
+                        0,
+                        wgpu::ShaderStages::VERTEX,
+                        wgpu::BufferBindingType::Uniform,
+                    ),
+                    buffer_layout_entry(
+                        1,
+                        wgpu::ShaderStages::VERTEX,
+                        wgpu::BufferBindingType::Storage { read_only: true },
+                    ),
+                ],
+                label: Some("particle_bind_group_layout"),
+            });
+
+        let simulation_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Simulation Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../particleSimulation.wgsl").into()),
+        });
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Particle Simulation Pipeline"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Particle Simulation Pipeline Layout"),
+                    bind_group_layouts: &[&compute_bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            module: &simulation_shader,
+            entry_point: "cs_main",
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../particle.wgsl").into()),
+        });
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("ParticleRootSignature"),
+                bind_group_layouts: &[camera_bind_group_layout, &render_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let create_pipeline = |label, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&render_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    conservative: false,
+                    unclipped_depth: false,
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            })
+        };
+        let additive_pipeline = create_pipeline(
+            "Additive ParticlePSO",
+            wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            },
+        );
+        let alpha_pipeline = create_pipeline("Alpha ParticlePSO", wgpu::BlendState::ALPHA_BLENDING);
+
+        Self {
+            emitters: Vec::new(),
+            frame: 0,
+            compute_bind_group_layout,
+            render_bind_group_layout,
+            compute_pipeline,
+            additive_pipeline,
+            alpha_pipeline,
+        }
+    }
+
+    pub fn add_emitter(
+        &mut self,
+        device: &wgpu::Device,
+        description: EmitterDescription,
+    ) -> EmitterId {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Emitter Buffer"),
+            size: std::mem::size_of::<EmitterUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // zeroed particles have no lifetime, so every slot starts out dead
+        let particle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Buffer"),
+            size: (description.max_particles.max(1) as usize * std::mem::size_of::<Particle>())
+                as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let spawn_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Spawn Buffer"),
+            size: std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.compute_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: spawn_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("particle_simulation_bind_group"),
+        });
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.render_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("particle_bind_group"),
+        });
+
+        self.emitters.push(Emitter {
+            description,
+            pending: 0.0,
+            uniform_buffer,
+            spawn_buffer,
+            compute_bind_group,
+            render_bind_group,
+        });
+        EmitterId(self.emitters.len() - 1)
+    }
+
+    pub fn set_position(&mut self, emitter: EmitterId, position: cgmath::Vector3<f32>) {
+        self.emitters[emitter.0].description.position = position;
+    }
+
+    // Works out this frame's spawns and turns the billboards towards the camera
+    pub fn update(&mut self, queue: &wgpu::Queue, delta: f32, camera: &Camera) {
+        self.frame = self.frame.wrapping_add(1);
+        let right = camera.get_right();
+        let up = right.cross(camera.get_forward());
+        for emitter in self.emitters.iter_mut() {
+            let description = &emitter.description;
+            let spawns = emitter.pending + description.rate.max(0.0) * delta;
+            let spawn_count = spawns.floor();
+            emitter.pending = spawns - spawn_count;
+            let uniform = EmitterUniform {
+                position: description.position.into(),
+                spawn_count: spawn_count as u32,
+                direction: description.direction.normalize().into(),
+                cone_cos: description.cone_angle.to_radians().cos(),
+                gravity: description.gravity.into(),
+                delta,
+                start_color: description.start_color,
+                end_color: description.end_color,
+                lifetime: [description.lifetime.0, description.lifetime.1],
+                speed: [description.speed.0, description.speed.1],
+                start_size: description.start_size,
+                end_size: description.end_size,
+                seed: self.frame,
+                max_particles: description.max_particles,
+                camera_right: right.extend(0.0).into(),
+                camera_up: up.extend(0.0).into(),
+            };
+            queue.write_buffer(&emitter.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+            queue.write_buffer(&emitter.spawn_buffer, 0, bytemuck::cast_slice(&[0u32]));
+        }
+    }
+
+    pub fn compute(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Particle Simulation Pass"),
+        });
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        for emitter in &self.emitters {
+            compute_pass.set_bind_group(0, &emitter.compute_bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                emitter
+                    .description
+                    .max_particles
+                    .div_ceil(Self::WORKGROUP_SIZE),
+                1,
+                1,
+            );
+        }
+    }
+
+    // Alpha blended emitters first, so the additive ones glow through the smoke
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        for (blend, pipeline) in [
+            (ParticleBlend::Alpha, &self.alpha_pipeline),
+            (ParticleBlend::Additive, &self.additive_pipeline),
+        ] {
+            render_pass.set_pipeline(pipeline);
+            for emitter in self
+                .emitters
+                .iter()
+                .filter(|emitter| emitter.description.blend == blend)
+            {
+                render_pass.set_bind_group(1, &emitter.render_bind_group, &[]);
+                render_pass.draw(0..6, 0..emitter.description.max_particles);
+            }
+        }
+    }
+}
+
+fn buffer_layout_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+    ty: wgpu::BufferBindingType,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
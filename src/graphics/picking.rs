@@ -0,0 +1,405 @@
+use cgmath::{InnerSpace, SquareMatrix};
+
+use super::culling::Aabb;
+use super::instance::{InstanceHandle, InstanceSet, RenderGroup};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: cgmath::Point3<f32>,
+    pub direction: cgmath::Vector3<f32>,
+}
+
+impl Ray {
+    // The direction is normalized, so distances along the ray are in world units
+    pub fn make(origin: cgmath::Point3<f32>, direction: cgmath::Vector3<f32>) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    // Into another space without normalizing, a distance along the transformed ray is still
+    // the same distance along this one
+    pub fn transform(&self, matrix: &cgmath::Matrix4<f32>) -> Self {
+        Self {
+            origin: cgmath::Point3::from_homogeneous(matrix * self.origin.to_homogeneous()),
+            direction: (matrix * self.direction.extend(0.0)).truncate(),
+        }
+    }
+
+    // Distance to where the ray enters the box, 0 if it starts inside
+    pub fn intersect_aabb(&self, bounds: &Aabb) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = f32::INFINITY;
+        for axis in 0..3 {
+            let origin = self.origin[axis];
+            let direction = self.direction[axis];
+            if direction.abs() < f32::EPSILON {
+                if origin < bounds.min[axis] || origin > bounds.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let first = (bounds.min[axis] - origin) / direction;
+            let second = (bounds.max[axis] - origin) / direction;
+            near = near.max(first.min(second));
+            far = far.min(first.max(second));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    // Möller-Trumbore, both sides of the triangle count
+    pub fn intersect_triangle(&self, [a, b, c]: [cgmath::Point3<f32>; 3]) -> Option<f32> {
+        let (edge1, edge2) = (b - a, c - a);
+        let p = self.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < f32::EPSILON * edge1.magnitude() * edge2.magnitude() {
+            return None;
+        }
+        let to_origin = self.origin - a;
+        let u = to_origin.dot(p) / determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = to_origin.cross(edge1);
+        let v = self.direction.dot(q) / determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = edge2.dot(q) / determinant;
+        (distance >= 0.0).then_some(distance)
+    }
+}
+
+// Model space triangles kept on the CPU for picking
+pub struct PickMesh {
+    positions: Vec<cgmath::Point3<f32>>,
+    indices: Vec<u32>,
+    bounds: Aabb,
+}
+
+impl PickMesh {
+    pub fn make<I: Copy + Into<u32>>(positions: &[[f32; 3]], indices: &[I]) -> Self {
+        let bounds = Aabb::from_points(positions.iter().map(|&position| position.into()))
+            .unwrap_or_else(|| {
+                Aabb::make(cgmath::vec3(0.0, 0.0, 0.0), cgmath::vec3(0.0, 0.0, 0.0))
+            });
+        let positions = positions.iter().map(|&position| position.into()).collect();
+        Self {
+            positions,
+            indices: indices.iter().map(|&index| index.into()).collect(),
+            bounds,
+        }
+    }
+
+    // Nearest hit, the ray in model space
+    fn intersect(&self, ray: &Ray) -> Option<f32> {
+        ray.intersect_aabb(&self.bounds)?;
+        self.indices
+            .chunks_exact(3)
+            .filter_map(|triangle| {
+                ray.intersect_triangle([0, 1, 2].map(|i| self.positions[triangle[i] as usize]))
+            })
+            .min_by(f32::total_cmp)
+    }
+}
+
+// What a ray is tested against for the instances of a group
+pub enum PickShape<'a> {
+    // the model space box, for meshes that deform away from their triangles
+    Bounds(Aabb),
+    Triangles(&'a PickMesh),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PickHit {
+    pub handle: InstanceHandle,
+    // along the ray from its origin
+    pub distance: f32,
+}
+
+// The nearest instance the ray hits. Works from the instances themselves, nothing has to be
+// uploaded first.
+pub fn pick<'a>(
+    ray: &Ray,
+    instance_set: &InstanceSet,
+    shape: impl Fn(RenderGroup) -> PickShape<'a>,
+) -> Option<PickHit> {
+    let mut nearest: Option<PickHit> = None;
+    for &handle in instance_set.handles() {
+        let (group, world) = match (
+            instance_set.get_group(handle),
+            instance_set.get_world_matrix(handle),
+        ) {
+            (Some(group), Some(world)) => (group, world),
+            _ => continue,
+        };
+        let inverse = match world.invert() {
+            Some(inverse) => inverse,
+            None => continue,
+        };
+        let local = ray.transform(&inverse);
+        let distance = match shape(group) {
+            PickShape::Bounds(bounds) => local.intersect_aabb(&bounds),
+            PickShape::Triangles(mesh) => mesh.intersect(&local),
+        };
+        if let Some(distance) = distance {
+            if nearest.is_none_or(|nearest| distance < nearest.distance) {
+                nearest = Some(PickHit { handle, distance });
+            }
+        }
+    }
+    nearest
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PickMode {
+    // pick on the CPU, skinned meshes by their bounds
+    Ray,
+    // IdBuffer, exact to the pixel for any mesh but answers a frame or two later
+    IdBuffer,
+}
+
+// The selected instances, drawn with the highlight tint
+pub struct Selection {
+    selected: Vec<InstanceHandle>,
+}
+
+impl Selection {
+    pub const HIGHLIGHT: [f32; 4] = [1.0, 0.55, 0.15, 1.0];
+
+    pub fn make() -> Self {
+        Self {
+            selected: Vec::new(),
+        }
+    }
+
+    // Selects the hit instance alone, or with extend adds it to the selection or takes it
+    // out again. Missing everything without extend clears the selection.
+    pub fn select(
+        &mut self,
+        instance_set: &mut InstanceSet,
+        hit: Option<InstanceHandle>,
+        extend: bool,
+    ) {
+        match (hit, extend) {
+            (Some(handle), true) => {
+                if let Some(position) = self.selected.iter().position(|&s| s == handle) {
+                    self.selected.swap_remove(position);
+                    instance_set.set_highlight(handle, None);
+                } else {
+                    self.selected.push(handle);
+                    instance_set.set_highlight(handle, Some(Self::HIGHLIGHT));
+                }
+            }
+            (Some(handle), false) => {
+                self.clear(instance_set);
+                self.selected.push(handle);
+                instance_set.set_highlight(handle, Some(Self::HIGHLIGHT));
+            }
+            (None, true) => {}
+            (None, false) => self.clear(instance_set),
+        }
+    }
+
+    pub fn clear(&mut self, instance_set: &mut InstanceSet) {
+        for handle in self.selected.drain(..) {
+            instance_set.set_highlight(handle, None);
+        }
+    }
+
+    // Can hold instances removed from the set since they were selected
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn get_selected(&self) -> &[InstanceHandle] {
+        &self.selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::instance::Instance;
+
+    fn ray(origin: [f32; 3], direction: [f32; 3]) -> Ray {
+        Ray::make(origin.into(), direction.into())
+    }
+
+    fn unit_box() -> Aabb {
+        Aabb::make(cgmath::vec3(-1.0, -1.0, -1.0), cgmath::vec3(1.0, 1.0, 1.0))
+    }
+
+    fn cube_at(x: f32) -> Box<dyn crate::graphics::instance::MatrixInstance> {
+        Instance::make(
+            cgmath::vec3(x, 0.0, 0.0),
+            cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            cgmath::vec3(1.0, 1.0, 1.0),
+        )
+    }
+
+    #[test]
+    fn ray_hits_the_near_face_of_a_box() {
+        let distance = ray([-5.0, 0.0, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&unit_box());
+        assert_eq!(distance, Some(4.0));
+    }
+
+    #[test]
+    fn ray_starting_inside_a_box_hits_at_zero() {
+        let distance = ray([0.5, 0.0, 0.0], [0.0, 1.0, 0.0]).intersect_aabb(&unit_box());
+        assert_eq!(distance, Some(0.0));
+    }
+
+    #[test]
+    fn ray_misses_a_box_beside_or_behind_it() {
+        assert_eq!(
+            ray([-5.0, 2.0, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&unit_box()),
+            None
+        );
+        assert_eq!(
+            ray([5.0, 0.0, 0.0], [1.0, 0.0, 0.0]).intersect_aabb(&unit_box()),
+            None
+        );
+        // parallel to a slab and outside it
+        assert_eq!(
+            ray([-5.0, 0.0, 1.5], [1.0, 0.0, 0.0]).intersect_aabb(&unit_box()),
+            None
+        );
+    }
+
+    const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+    fn triangle() -> [cgmath::Point3<f32>; 3] {
+        TRIANGLE.map(Into::into)
+    }
+
+    #[test]
+    fn ray_hits_a_triangle_from_either_side() {
+        let front = ray([0.25, 0.25, 2.0], [0.0, 0.0, -1.0]).intersect_triangle(triangle());
+        let back = ray([0.25, 0.25, -3.0], [0.0, 0.0, 1.0]).intersect_triangle(triangle());
+        assert_eq!(front, Some(2.0));
+        assert_eq!(back, Some(3.0));
+    }
+
+    #[test]
+    fn ray_misses_outside_behind_or_along_a_triangle() {
+        // past the hypotenuse
+        assert_eq!(
+            ray([0.75, 0.75, 2.0], [0.0, 0.0, -1.0]).intersect_triangle(triangle()),
+            None
+        );
+        assert_eq!(
+            ray([0.25, 0.25, 2.0], [0.0, 0.0, 1.0]).intersect_triangle(triangle()),
+            None
+        );
+        // in the triangle's plane
+        assert_eq!(
+            ray([-1.0, 0.25, 0.0], [1.0, 0.0, 0.0]).intersect_triangle(triangle()),
+            None
+        );
+    }
+
+    #[test]
+    fn pick_returns_the_nearest_instance() {
+        let instance_set = InstanceSet::make(vec![
+            (cube_at(6.0), RenderGroup::Textured),
+            (cube_at(0.0), RenderGroup::Textured),
+            (cube_at(3.0), RenderGroup::Textured),
+        ]);
+        let handles = instance_set.handles().to_vec();
+        let shape = |_| PickShape::Bounds(unit_box());
+
+        let hit = pick(
+            &ray([-5.0, 0.0, 0.0], [1.0, 0.0, 0.0]),
+            &instance_set,
+            shape,
+        )
+        .unwrap();
+        assert_eq!(hit.handle, handles[1]);
+        assert_eq!(hit.distance, 4.0);
+
+        let hit = pick(
+            &ray([12.0, 0.0, 0.0], [-1.0, 0.0, 0.0]),
+            &instance_set,
+            shape,
+        )
+        .unwrap();
+        assert_eq!(hit.handle, handles[0]);
+        assert_eq!(hit.distance, 5.0);
+
+        assert_eq!(
+            pick(
+                &ray([-5.0, 3.0, 0.0], [1.0, 0.0, 0.0]),
+                &instance_set,
+                shape
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn pick_tests_triangles_in_model_space() {
+        let mesh = PickMesh::make(&TRIANGLE, &[0u32, 1, 2]);
+        let instance_set = InstanceSet::make(vec![(cube_at(10.0), RenderGroup::Solid)]);
+        let shape = |_| PickShape::Triangles(&mesh);
+
+        let hit = pick(
+            &ray([10.25, 0.25, 2.0], [0.0, 0.0, -1.0]),
+            &instance_set,
+            shape,
+        );
+        assert_eq!(hit.map(|hit| hit.distance), Some(2.0));
+        // the triangle is at the instance, not the origin
+        assert_eq!(
+            pick(
+                &ray([0.25, 0.25, 2.0], [0.0, 0.0, -1.0]),
+                &instance_set,
+                shape
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn selection_replaces_toggles_and_clears() {
+        let mut instance_set = InstanceSet::make(vec![
+            (cube_at(0.0), RenderGroup::Textured),
+            (cube_at(3.0), RenderGroup::Textured),
+        ]);
+        let (a, b) = (instance_set.handles()[0], instance_set.handles()[1]);
+        let mut selection = Selection::make();
+        let untinted = [1.0, 1.0, 1.0, 1.0];
+        let assert_tints = |instance_set: &InstanceSet, tint_a, tint_b| {
+            assert_eq!(instance_set.get_tint(a), Some(tint_a));
+            assert_eq!(instance_set.get_tint(b), Some(tint_b));
+        };
+
+        selection.select(&mut instance_set, Some(a), false);
+        assert_eq!(selection.get_selected(), &[a]);
+        assert_tints(&instance_set, Selection::HIGHLIGHT, untinted);
+        selection.select(&mut instance_set, Some(b), false);
+        assert_eq!(selection.get_selected(), &[b]);
+        assert_tints(&instance_set, untinted, Selection::HIGHLIGHT);
+
+        selection.select(&mut instance_set, Some(a), true);
+        assert_eq!(selection.get_selected(), &[b, a]);
+        assert_tints(&instance_set, Selection::HIGHLIGHT, Selection::HIGHLIGHT);
+        selection.select(&mut instance_set, Some(b), true);
+        assert_eq!(selection.get_selected(), &[a]);
+        assert_tints(&instance_set, Selection::HIGHLIGHT, untinted);
+
+        // a miss with extend keeps the selection, without it clears
+        selection.select(&mut instance_set, None, true);
+        assert_eq!(selection.get_selected(), &[a]);
+        assert_tints(&instance_set, Selection::HIGHLIGHT, untinted);
+        selection.select(&mut instance_set, None, false);
+        assert!(selection.get_selected().is_empty());
+        assert_tints(&instance_set, untinted, untinted);
+
+        selection.select(&mut instance_set, Some(a), true);
+        selection.clear(&mut instance_set);
+        assert!(selection.get_selected().is_empty());
+        assert_tints(&instance_set, untinted, untinted);
+    }
+}
@@ -0,0 +1,238 @@
+use super::bloom::Bloom;
+use super::camera::Camera;
+use super::fxaa::Fxaa;
+use super::scene::{AntiAliasing, PostEffectKind, PostProcessDescription};
+use super::smaa::Smaa;
+use super::taa::Taa;
+use super::texture::Texture;
+use super::tonemapping::Tonemapper;
+
+// What an effect reads and writes in one step of the chain
+pub struct PostFrame<'a> {
+    pub input: &'a wgpu::TextureView,
+    // the effect must write every pixel of the output
+    pub output: &'a wgpu::TextureView,
+    // scene depth, only single sampled when the anti-aliasing isn't MSAA
+    pub depth: &'a wgpu::TextureView,
+}
+
+// A full screen pass over the HDR frame
+pub trait PostEffect {
+    fn kind(&self) -> PostEffectKind;
+    fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration);
+    // Called once a frame before the camera buffer is written, only while enabled
+    fn update(&mut self, _queue: &wgpu::Queue, _camera: &mut Camera) {}
+    // Called when the effect is enabled again, so it can drop state from older frames
+    fn reset(&mut self) {}
+    fn render(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, frame: &PostFrame);
+}
+
+struct Stage {
+    effect: Box<dyn PostEffect>,
+    enabled: bool,
+}
+
+// Chains the effects over two HDR targets, each one reading what the previous one wrote.
+// The scene is rendered into the first target.
+pub struct PostProcessStack {
+    targets: [Texture; 2],
+    stages: Vec<Stage>,
+    order: Vec<PostEffectKind>,
+}
+
+impl PostProcessStack {
+    pub fn make(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        description: &PostProcessDescription,
+        anti_aliasing: AntiAliasing,
+    ) -> Self {
+        // TAA resolves the raw scene, FXAA and SMAA look for edges in the finished image,
+        // unless the description already places them
+        let mut order = description.order.clone();
+        if !order.contains(&PostEffectKind::Taa) {
+            order.insert(0, PostEffectKind::Taa);
+        }
+        for kind in [PostEffectKind::Fxaa, PostEffectKind::Smaa] {
+            if !order.contains(&kind) {
+                order.push(kind);
+            }
+        }
+
+        let mut stack = Self {
+            targets: Self::create_targets(device, config),
+            stages: Vec::new(),
+            order,
+        };
+        stack.add(
+            Box::new(Bloom::make(device, config, &description.bloom)),
+            description.bloom.enabled,
+        );
+        stack.add(
+            Box::new(Fxaa::make(device)),
+            anti_aliasing == AntiAliasing::Fxaa,
+        );
+        stack.add(
+            Box::new(Smaa::make(device, config)),
+            anti_aliasing == AntiAliasing::Smaa,
+        );
+        stack.add(
+            Box::new(Taa::make(device, config)),
+            anti_aliasing == AntiAliasing::Taa,
+        );
+        stack
+    }
+
+    pub fn add(&mut self, effect: Box<dyn PostEffect>, enabled: bool) {
+        self.stages.push(Stage { effect, enabled });
+    }
+
+    // The scene pass renders (or resolves) into this view
+    pub fn get_view(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    pub fn is_enabled(&self, kind: PostEffectKind) -> bool {
+        self.stage(kind).is_some_and(|stage| stage.enabled)
+    }
+
+    pub fn set_enabled(&mut self, kind: PostEffectKind, enabled: bool) {
+        if let Some(stage) = self
+            .stages
+            .iter_mut()
+            .find(|stage| stage.effect.kind() == kind)
+        {
+            if enabled && !stage.enabled {
+                stage.effect.reset();
+            }
+            stage.enabled = enabled;
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.targets = Self::create_targets(device, config);
+        for stage in self.stages.iter_mut() {
+            stage.effect.resize(device, config);
+        }
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, camera: &mut Camera) {
+        for stage in self.stages.iter_mut().filter(|stage| stage.enabled) {
+            stage.effect.update(queue, camera);
+        }
+    }
+
+    // Returns the view holding the processed frame
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        depth: &wgpu::TextureView,
+    ) -> &wgpu::TextureView {
+        let mut current = 0;
+        for kind in self.order.iter() {
+            let Some(stage) = self.stage(*kind).filter(|stage| stage.enabled) else {
+                continue;
+            };
+            let frame = PostFrame {
+                input: &self.targets[current].view,
+                output: &self.targets[1 - current].view,
+                depth,
+            };
+            stage.effect.render(device, encoder, &frame);
+            current = 1 - current;
+        }
+        &self.targets[current].view
+    }
+
+    fn stage(&self, kind: PostEffectKind) -> Option<&Stage> {
+        self.stages.iter().find(|stage| stage.effect.kind() == kind)
+    }
+
+    fn create_targets(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> [Texture; 2] {
+        [
+            Texture::create_render_target(device, config, Tonemapper::HDR_FORMAT, "post_target_0"),
+            Texture::create_render_target(device, config, Tonemapper::HDR_FORMAT, "post_target_1"),
+        ]
+    }
+}
+
+// Pipeline drawing a full screen triangle from the shader's `vs_main`
+pub fn create_full_screen_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    targets: &[Option<wgpu::ColorTargetState>],
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(
+            &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            }),
+        ),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets,
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+// Runs one full screen draw into `targets`, clearing them first
+pub fn draw_full_screen(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+    targets: &[&wgpu::TextureView],
+) {
+    let color_attachments = targets
+        .iter()
+        .map(|view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &color_attachments,
+        depth_stencil_attachment: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
+
+// Bind group layout entry for a fragment shader texture
+pub fn texture_layout_entry(binding: u32, filterable: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable },
+        },
+        count: None,
+    }
+}
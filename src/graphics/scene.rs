@@ -0,0 +1,461 @@
+use anyhow::*;
+use std::path::{Path, PathBuf};
+
+// Everything about the scene that isn't geometry: where the lighting comes from and
+// what is drawn behind the objects.
+pub struct SceneDescription {
+    pub environment: Environment,
+    pub background: Background,
+    pub material: MaterialDescription,
+    pub tone_mapping: ToneMapping,
+    pub exposure: Exposure,
+    pub post_process: PostProcessDescription,
+    pub ambient_occlusion: AmbientOcclusionDescription,
+    pub anti_aliasing: AntiAliasing,
+    pub instance_grid: InstanceGridDescription,
+    // LOD chain from the lod_tool binary, drawn for the textured and solid instances in place
+    // of the cube
+    pub lod_mesh: Option<PathBuf>,
+}
+
+// Maps for the cube material, loaded through the TextureCache. Missing maps use the defaults.
+pub struct MaterialDescription {
+    pub albedo_map: Option<PathBuf>,
+    pub metallic_roughness_map: Option<PathBuf>,
+    pub occlusion_map: Option<PathBuf>,
+    pub emissive_map: Option<PathBuf>,
+    pub normal_map: Option<PathBuf>,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+// Source of the image based lighting
+pub enum Environment {
+    // Equirectangular .hdr file, falls back to the default gradient if it can't be loaded
+    HdrFile(PathBuf),
+    Gradient {
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    },
+}
+
+pub enum Background {
+    // Plain clear, no skybox pass
+    Color(wgpu::Color),
+    // The cube map the image based lighting was baked from
+    Environment,
+    // Six images in the order +X, -X, +Y, -Y, +Z, -Z
+    CubeFaces([PathBuf; 6]),
+    // Equirectangular image, LDR or HDR
+    Equirect(PathBuf),
+    Gradient {
+        zenith: [f32; 3],
+        horizon: [f32; 3],
+        ground: [f32; 3],
+    },
+    // Single scattering approximation lit by the directional light
+    Atmosphere {
+        rayleigh: [f32; 3],
+        mie: f32,
+        sun_intensity: f32,
+    },
+}
+
+// Curve that maps the exposed HDR colour into the display range
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ToneMapping {
+    Reinhard,
+    Aces,
+    AgX,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Exposure {
+    // Fixed multiplier applied before tone mapping
+    Manual(f32),
+    // Brings the average luminance of the frame to middle grey, measured with a histogram
+    // over [min, max] in log2 luminance. Adaptation is how quickly it follows a new average,
+    // per second: after 1 / adaptation seconds it's covered 63% of the way. Compensation is in
+    // stops.
+    Auto {
+        min_log_luminance: f32,
+        max_log_luminance: f32,
+        adaptation: f32,
+        compensation: f32,
+    },
+}
+
+// Full screen effects applied to the HDR frame before tone mapping
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PostEffectKind {
+    Bloom,
+    Fxaa,
+    Smaa,
+    Taa,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PostProcessDescription {
+    // the order effects run in, effects that aren't listed never run
+    pub order: Vec<PostEffectKind>,
+    pub bloom: BloomDescription,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BloomDescription {
+    pub enabled: bool,
+    // how much of the final colour comes from the blurred chain
+    pub strength: f32,
+    // radius of the upsampling tent filter, as a fraction of the screen height
+    pub filter_radius: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AntiAliasing {
+    // sample count, 1 turns anti-aliasing off. Lowered to what the adapter supports.
+    Msaa(u32),
+    // the post-processing modes render the scene with a single sample
+    Fxaa,
+    Smaa,
+    // jitters the projection every frame and blends with the reprojected history
+    Taa,
+}
+
+// Where the instance grid is animated
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InstanceAnimation {
+    // ArrayInstance::update on every instance, uploaded every frame
+    Cpu,
+    // a compute shader writes the instance buffer
+    Gpu,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InstanceGridDescription {
+    pub animation: InstanceAnimation,
+    // instances along x and z
+    pub width: u32,
+    pub depth: u32,
+}
+
+// Screen space occlusion that darkens the ambient (image based) lighting
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AmbientOcclusionDescription {
+    pub enabled: bool,
+    // world space radius searched around each pixel
+    pub radius: f32,
+    // exponent applied to the visibility, higher is darker
+    pub intensity: f32,
+}
+
+impl Environment {
+    pub const DEFAULT_GRADIENT: Environment = Environment::Gradient {
+        zenith: [0.2, 0.4, 0.8],
+        horizon: [0.7, 0.8, 0.9],
+        ground: [0.2, 0.18, 0.15],
+    };
+}
+
+impl Default for SceneDescription {
+    fn default() -> Self {
+        SceneDescription {
+            environment: Environment::HdrFile("environment.hdr".into()),
+            background: Background::Environment,
+            material: MaterialDescription {
+                albedo_map: None,
+                metallic_roughness_map: None,
+                occlusion_map: None,
+                emissive_map: None,
+                normal_map: None,
+                metallic: 0.0,
+                roughness: 0.6,
+            },
+            tone_mapping: ToneMapping::Aces,
+            exposure: Exposure::Auto {
+                min_log_luminance: -8.0,
+                max_log_luminance: 4.0,
+                adaptation: 3.0,
+                compensation: 0.0,
+            },
+            post_process: PostProcessDescription {
+                order: vec![PostEffectKind::Bloom],
+                bloom: BloomDescription {
+                    enabled: true,
+                    strength: 0.04,
+                    filter_radius: 0.005,
+                },
+            },
+            ambient_occlusion: AmbientOcclusionDescription {
+                enabled: true,
+                radius: 1.0,
+                intensity: 1.5,
+            },
+            anti_aliasing: AntiAliasing::Msaa(4),
+            instance_grid: InstanceGridDescription {
+                animation: InstanceAnimation::Gpu,
+                width: 10,
+                depth: 10,
+            },
+            lod_mesh: None,
+        }
+    }
+}
+
+impl SceneDescription {
+    // Line based `key = kind values...` format, `#` starts a comment:
+    //
+    //   environment = hdr environment.hdr
+    //   environment = gradient 0.2 0.4 0.8  0.7 0.8 0.9  0.2 0.18 0.15
+    //   background = color 0.1 0.2 0.3
+    //   background = environment
+    //   background = cube px.png nx.png py.png ny.png pz.png nz.png
+    //   background = equirect sky.hdr
+    //   background = gradient 0.2 0.4 0.8  0.7 0.8 0.9  0.2 0.18 0.15
+    //   background = atmosphere 5.8 13.5 33.1  2.0 20.0
+    //   albedo_map = textures/albedo.png    (also metallic_roughness_map, occlusion_map,
+    //                                        emissive_map and normal_map, .ktx2 and .dds
+    //                                        files are loaded with their own mip chain)
+    //   metallic = 0.0
+    //   roughness = 0.6
+    //   tone_mapping = aces                 (or reinhard, agx)
+    //   exposure = manual 1.0
+    //   exposure = auto -8 4 3.0 0.0        (min and max log2 luminance, adaptation, compensation)
+    //   post_process = bloom                (effects in the order they run, the anti-aliasing
+    //                                        effects fxaa, smaa and taa can be placed too)
+    //   bloom = on 0.04 0.005               (or off, strength, filter radius)
+    //   ambient_occlusion = on 1.0 1.5      (or off, radius, intensity)
+    //   anti_aliasing = msaa 4              (1, 2, 4 or 8 samples, or fxaa, smaa, taa)
+    //   instance_grid = gpu 10 10           (or cpu, instances along x and z)
+    //   lod_mesh = meshes/rock.lod          (written by the lod_tool binary)
+    //
+    // Keys that are missing keep their default.
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut scene = SceneDescription::default();
+        for (number, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("line {}: expected `key = value`", number + 1))?;
+            let path = || Some(PathBuf::from(value.trim()));
+            let mut words = value.split_whitespace();
+            let kind = words.next().unwrap_or_default();
+            let args = words.collect::<Vec<_>>();
+            let context = || format!("line {}: invalid {} `{}`", number + 1, key.trim(), kind);
+            match key.trim() {
+                "environment" => {
+                    scene.environment =
+                        Self::parse_environment(kind, &args).with_context(context)?
+                }
+                "background" => {
+                    scene.background = Self::parse_background(kind, &args).with_context(context)?
+                }
+                "albedo_map" => scene.material.albedo_map = path(),
+                "metallic_roughness_map" => scene.material.metallic_roughness_map = path(),
+                "occlusion_map" => scene.material.occlusion_map = path(),
+                "emissive_map" => scene.material.emissive_map = path(),
+                "normal_map" => scene.material.normal_map = path(),
+                "metallic" => scene.material.metallic = kind.parse().with_context(context)?,
+                "roughness" => scene.material.roughness = kind.parse().with_context(context)?,
+                "tone_mapping" => {
+                    scene.tone_mapping = Self::parse_tone_mapping(kind).with_context(context)?
+                }
+                "exposure" => {
+                    scene.exposure = Self::parse_exposure(kind, &args).with_context(context)?
+                }
+                "post_process" => {
+                    scene.post_process.order = value
+                        .split_whitespace()
+                        .map(Self::parse_post_effect)
+                        .collect::<Result<_>>()
+                        .with_context(context)?
+                }
+                "bloom" => {
+                    scene.post_process.bloom =
+                        Self::parse_bloom(kind, &args).with_context(context)?
+                }
+                "ambient_occlusion" => {
+                    scene.ambient_occlusion =
+                        Self::parse_ambient_occlusion(kind, &args).with_context(context)?
+                }
+                "anti_aliasing" => {
+                    scene.anti_aliasing =
+                        Self::parse_anti_aliasing(kind, &args).with_context(context)?
+                }
+                "instance_grid" => {
+                    scene.instance_grid =
+                        Self::parse_instance_grid(kind, &args).with_context(context)?
+                }
+                "lod_mesh" => scene.lod_mesh = path(),
+                other => bail!("line {}: unknown key `{}`", number + 1, other),
+            }
+        }
+        Ok(scene)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let source = std::fs::read_to_string(path.as_ref())
+            .with_context(|| format!("failed to read {}", path.as_ref().display()))?;
+        Self::parse(&source)
+    }
+
+    fn parse_environment(kind: &str, args: &[&str]) -> Result<Environment> {
+        Ok(match (kind, args) {
+            ("hdr", [path]) => Environment::HdrFile(path.into()),
+            ("gradient", _) => {
+                let [zenith, horizon, ground] = parse_colors(args)?;
+                Environment::Gradient {
+                    zenith,
+                    horizon,
+                    ground,
+                }
+            }
+            _ => bail!("unexpected arguments {:?}", args),
+        })
+    }
+
+    fn parse_background(kind: &str, args: &[&str]) -> Result<Background> {
+        Ok(match (kind, args) {
+            ("color", _) => {
+                let [[r, g, b]] = parse_colors(args)?;
+                Background::Color(wgpu::Color {
+                    r: r as f64,
+                    g: g as f64,
+                    b: b as f64,
+                    a: 1.0,
+                })
+            }
+            ("environment", []) => Background::Environment,
+            ("cube", [px, nx, py, ny, pz, nz]) => {
+                Background::CubeFaces([px, nx, py, ny, pz, nz].map(PathBuf::from))
+            }
+            ("equirect", [path]) => Background::Equirect(path.into()),
+            ("gradient", _) => {
+                let [zenith, horizon, ground] = parse_colors(args)?;
+                Background::Gradient {
+                    zenith,
+                    horizon,
+                    ground,
+                }
+            }
+            ("atmosphere", [r, g, b, mie, sun_intensity]) => Background::Atmosphere {
+                rayleigh: [r.parse()?, g.parse()?, b.parse()?],
+                mie: mie.parse()?,
+                sun_intensity: sun_intensity.parse()?,
+            },
+            _ => bail!("unexpected arguments {:?}", args),
+        })
+    }
+
+    fn parse_tone_mapping(kind: &str) -> Result<ToneMapping> {
+        Ok(match kind {
+            "reinhard" => ToneMapping::Reinhard,
+            "aces" => ToneMapping::Aces,
+            "agx" => ToneMapping::AgX,
+            _ => bail!("expected reinhard, aces or agx"),
+        })
+    }
+
+    fn parse_post_effect(name: &str) -> Result<PostEffectKind> {
+        Ok(match name {
+            "bloom" => PostEffectKind::Bloom,
+            "fxaa" => PostEffectKind::Fxaa,
+            "smaa" => PostEffectKind::Smaa,
+            "taa" => PostEffectKind::Taa,
+            _ => bail!("unknown effect `{}`", name),
+        })
+    }
+
+    fn parse_bloom(kind: &str, args: &[&str]) -> Result<BloomDescription> {
+        let mut bloom = SceneDescription::default().post_process.bloom;
+        bloom.enabled = parse_switch(kind)?;
+        match args {
+            [] => {}
+            [strength, filter_radius] => {
+                bloom.strength = strength.parse()?;
+                bloom.filter_radius = filter_radius.parse()?;
+            }
+            _ => bail!("unexpected arguments {:?}", args),
+        }
+        Ok(bloom)
+    }
+
+    fn parse_ambient_occlusion(kind: &str, args: &[&str]) -> Result<AmbientOcclusionDescription> {
+        let mut ambient_occlusion = SceneDescription::default().ambient_occlusion;
+        ambient_occlusion.enabled = parse_switch(kind)?;
+        match args {
+            [] => {}
+            [radius, intensity] => {
+                ambient_occlusion.radius = radius.parse()?;
+                ambient_occlusion.intensity = intensity.parse()?;
+            }
+            _ => bail!("unexpected arguments {:?}", args),
+        }
+        Ok(ambient_occlusion)
+    }
+
+    fn parse_anti_aliasing(kind: &str, args: &[&str]) -> Result<AntiAliasing> {
+        Ok(match (kind, args) {
+            ("msaa", [samples]) => match samples.parse()? {
+                samples @ (1 | 2 | 4 | 8) => AntiAliasing::Msaa(samples),
+                samples => bail!("unsupported sample count {}", samples),
+            },
+            ("fxaa", []) => AntiAliasing::Fxaa,
+            ("smaa", []) => AntiAliasing::Smaa,
+            ("taa", []) => AntiAliasing::Taa,
+            _ => bail!("unexpected arguments {:?}", args),
+        })
+    }
+
+    fn parse_instance_grid(kind: &str, args: &[&str]) -> Result<InstanceGridDescription> {
+        let animation = match kind {
+            "cpu" => InstanceAnimation::Cpu,
+            "gpu" => InstanceAnimation::Gpu,
+            _ => bail!("expected cpu or gpu"),
+        };
+        Ok(match args {
+            [width, depth] => InstanceGridDescription {
+                animation,
+                width: width.parse()?,
+                depth: depth.parse()?,
+            },
+            _ => bail!("unexpected arguments {:?}", args),
+        })
+    }
+
+    fn parse_exposure(kind: &str, args: &[&str]) -> Result<Exposure> {
+        Ok(match (kind, args) {
+            ("manual", [exposure]) => Exposure::Manual(exposure.parse()?),
+            ("auto", [min, max, adaptation, compensation]) => Exposure::Auto {
+                min_log_luminance: min.parse()?,
+                max_log_luminance: max.parse()?,
+                adaptation: adaptation.parse()?,
+                compensation: compensation.parse()?,
+            },
+            _ => bail!("unexpected arguments {:?}", args),
+        })
+    }
+}
+
+// Exactly N rgb triples
+fn parse_switch(kind: &str) -> Result<bool> {
+    Ok(match kind {
+        "on" => true,
+        "off" => false,
+        _ => bail!("expected on or off"),
+    })
+}
+
+fn parse_colors<const N: usize>(args: &[&str]) -> Result<[[f32; 3]; N]> {
+    if args.len() != 3 * N {
+        bail!("expected {} numbers, got {}", 3 * N, args.len());
+    }
+    let mut colors = [[0.0; 3]; N];
+    for (i, arg) in args.iter().enumerate() {
+        colors[i / 3][i % 3] = arg.parse()?;
+    }
+    Ok(colors)
+}
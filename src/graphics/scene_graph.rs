@@ -0,0 +1,214 @@
+use cgmath::SquareMatrix;
+
+use super::instance::{InstanceHandle, InstanceSet, Transform};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+struct Node {
+    local: Transform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    // parent world * local, valid after update unless dirty
+    world: cgmath::Matrix4<f32>,
+    dirty: bool,
+    instances: Vec<InstanceHandle>,
+}
+
+// Nodes with local transforms under a parent. Instances attached to a node are drawn relative
+// to its world matrix, so moving a node moves everything below it.
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+}
+
+impl SceneGraph {
+    pub fn make() -> Self {
+        Self { nodes: Vec::new() }
+    }
+
+    pub fn add_node(&mut self, parent: Option<NodeId>, local: Transform) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            local,
+            parent,
+            children: Vec::new(),
+            world: cgmath::Matrix4::identity(),
+            dirty: true,
+            instances: Vec::new(),
+        });
+        if let Some(parent) = parent {
+            self.nodes[parent.0].children.push(id);
+        }
+        id
+    }
+
+    // Moves the node with its subtree, refused if the new parent is inside that subtree
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn set_parent(&mut self, node: NodeId, parent: Option<NodeId>) -> bool {
+        let mut ancestor = parent;
+        while let Some(id) = ancestor {
+            if id == node {
+                return false;
+            }
+            ancestor = self.nodes[id.0].parent;
+        }
+        if let Some(old) = self.nodes[node.0].parent {
+            self.nodes[old.0].children.retain(|&child| child != node);
+        }
+        if let Some(parent) = parent {
+            self.nodes[parent.0].children.push(node);
+        }
+        let entry = &mut self.nodes[node.0];
+        entry.parent = parent;
+        entry.dirty = true;
+        true
+    }
+
+    pub fn get_local(&self, node: NodeId) -> &Transform {
+        &self.nodes[node.0].local
+    }
+
+    pub fn set_local(&mut self, node: NodeId, local: Transform) {
+        let entry = &mut self.nodes[node.0];
+        entry.local = local;
+        entry.dirty = true;
+    }
+
+    pub fn attach(&mut self, node: NodeId, instance: InstanceHandle, instances: &mut InstanceSet) {
+        let entry = &mut self.nodes[node.0];
+        entry.instances.push(instance);
+        instances.set_parent_matrix(instance, entry.world);
+    }
+
+    // The instance drops back to its own transform, taken as world space again
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn detach(&mut self, node: NodeId, instance: InstanceHandle, instances: &mut InstanceSet) {
+        self.nodes[node.0]
+            .instances
+            .retain(|&attached| attached != instance);
+        instances.set_parent_matrix(instance, cgmath::Matrix4::identity());
+    }
+
+    // Recomputes the world matrices of dirty nodes and everything below them, and hands them to
+    // the attached instances. Clean nodes under clean parents keep their cached matrix.
+    pub fn update(&mut self, instances: &mut InstanceSet) {
+        let mut stack: Vec<(usize, cgmath::Matrix4<f32>, bool)> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(index, _)| (index, cgmath::Matrix4::identity(), false))
+            .collect();
+        while let Some((index, parent_world, parent_changed)) = stack.pop() {
+            let node = &mut self.nodes[index];
+            let changed = parent_changed || node.dirty;
+            if changed {
+                node.world = parent_world * node.local.to_matrix();
+                node.dirty = false;
+                node.instances.retain(|&handle| instances.contains(handle));
+                for &handle in &node.instances {
+                    instances.set_parent_matrix(handle, node.world);
+                }
+            }
+            let world = node.world;
+            stack.extend(node.children.iter().map(|child| (child.0, world, changed)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::instance::{Instance, RenderGroup};
+
+    fn at(x: f32) -> Transform {
+        Transform {
+            position: (x, 0.0, 0.0).into(),
+            ..Default::default()
+        }
+    }
+
+    // an instance at the origin of whatever it's attached to
+    fn instance_set() -> InstanceSet {
+        InstanceSet::make(vec![(
+            Instance::make(
+                (0.0, 0.0, 0.0).into(),
+                cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+                (1.0, 1.0, 1.0).into(),
+            ),
+            RenderGroup::Solid,
+        )])
+    }
+
+    fn world_x(instances: &InstanceSet, handle: InstanceHandle) -> f32 {
+        instances.get_world_matrix(handle).unwrap().w.x
+    }
+
+    #[test]
+    fn set_parent_refuses_cycles() {
+        let mut graph = SceneGraph::make();
+        let a = graph.add_node(None, at(1.0));
+        let b = graph.add_node(Some(a), at(1.0));
+        let c = graph.add_node(Some(b), at(1.0));
+        assert!(!graph.set_parent(a, Some(c)));
+        assert!(!graph.set_parent(b, Some(b)));
+        assert_eq!(graph.nodes[a.0].parent, None);
+        assert_eq!(graph.nodes[b.0].children, vec![c]);
+        // moving up the tree is fine
+        assert!(graph.set_parent(c, Some(a)));
+        assert!(graph.set_parent(c, None));
+    }
+
+    #[test]
+    fn set_parent_moves_the_subtree() {
+        let mut instances = instance_set();
+        let handle = instances.handles()[0];
+        let mut graph = SceneGraph::make();
+        let a = graph.add_node(None, at(1.0));
+        let b = graph.add_node(None, at(10.0));
+        let child = graph.add_node(Some(a), at(0.5));
+        graph.attach(child, handle, &mut instances);
+        graph.update(&mut instances);
+        assert_eq!(world_x(&instances, handle), 1.5);
+
+        assert!(graph.set_parent(child, Some(b)));
+        assert!(graph.nodes[a.0].children.is_empty());
+        assert_eq!(graph.nodes[b.0].children, vec![child]);
+        graph.update(&mut instances);
+        assert_eq!(world_x(&instances, handle), 10.5);
+        // the old parent doesn't carry it anymore
+        graph.set_local(a, at(5.0));
+        graph.update(&mut instances);
+        assert_eq!(world_x(&instances, handle), 10.5);
+    }
+
+    #[test]
+    fn detach_returns_the_instance_to_its_own_transform() {
+        let mut instances = instance_set();
+        let handle = instances.handles()[0];
+        let mut graph = SceneGraph::make();
+        let node = graph.add_node(None, at(3.0));
+        graph.attach(node, handle, &mut instances);
+        graph.update(&mut instances);
+        assert_eq!(world_x(&instances, handle), 3.0);
+
+        graph.detach(node, handle, &mut instances);
+        assert!(graph.nodes[node.0].instances.is_empty());
+        assert_eq!(world_x(&instances, handle), 0.0);
+        graph.set_local(node, at(7.0));
+        graph.update(&mut instances);
+        assert_eq!(world_x(&instances, handle), 0.0);
+    }
+
+    #[test]
+    fn update_forgets_removed_instances() {
+        let mut instances = instance_set();
+        let handle = instances.handles()[0];
+        let mut graph = SceneGraph::make();
+        let node = graph.add_node(None, at(3.0));
+        graph.attach(node, handle, &mut instances);
+        instances.remove(handle);
+        graph.update(&mut instances);
+        assert!(graph.nodes[node.0].instances.is_empty());
+    }
+}
@@ -0,0 +1,460 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// Quadric error edge collapse. Only the indices change, every vertex of the result is one of
+// the input vertices, so a simplified mesh keeps the full mesh's attributes and skin weights.
+//
+// Vertices that share a position, split for normals or uvs, collapse together. Each of them
+// has to move to a vertex of the other end that it shares a triangle with, so seams between
+// faces survive instead of smearing one face's attributes onto the other.
+pub fn simplify(positions: &[[f32; 3]], indices: &[u32], target_index_count: usize) -> Vec<u32> {
+    let mut simplifier = Simplifier::make(positions, indices);
+    simplifier.run(target_index_count / 3);
+    simplifier.get_indices()
+}
+
+// Boundary edges are held in place by a plane through them, weighted so an open edge is
+// always the last thing to move
+const BOUNDARY_WEIGHT: f64 = 100.0;
+
+// The symmetric 4x4 matrix of the sum of squared distances to a set of planes
+#[derive(Copy, Clone, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: [f64; 3], distance: f64, weight: f64) -> Self {
+        let [a, b, c] = normal;
+        let d = distance;
+        #[rustfmt::skip]
+        let q = [a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d];
+        Self(q.map(|q| q * weight))
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (q, o) in self.0.iter_mut().zip(other.0) {
+            *q += o;
+        }
+    }
+
+    fn error(&self, [x, y, z]: [f64; 3]) -> f64 {
+        let q = &self.0;
+        let error = q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9];
+        error.max(0.0)
+    }
+}
+
+// Moving every vertex at from onto the vertices at to, with the versions of both when the
+// cost was worked out. Ordered so the heap pops the cheapest first.
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+struct Simplifier {
+    // the position each vertex is welded to
+    position_of: Vec<usize>,
+    points: Vec<[f64; 3]>,
+    quadrics: Vec<Quadric>,
+    // bumped whenever a position's quadric or neighbours change, older collapses are skipped
+    versions: Vec<u32>,
+    removed: Vec<bool>,
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    alive_count: usize,
+    // the triangles around each position, may hold dead ones
+    adjacency: Vec<Vec<usize>>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn make(positions: &[[f32; 3]], indices: &[u32]) -> Self {
+        let mut welded = HashMap::new();
+        let mut points = Vec::new();
+        let position_of: Vec<usize> = positions
+            .iter()
+            .map(|position| {
+                *welded.entry(position.map(f32::to_bits)).or_insert_with(|| {
+                    points.push(position.map(f64::from));
+                    points.len() - 1
+                })
+            })
+            .collect();
+
+        let triangles: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+        let mut simplifier = Self {
+            quadrics: vec![Quadric::default(); points.len()],
+            versions: vec![0; points.len()],
+            removed: vec![false; points.len()],
+            adjacency: vec![Vec::new(); points.len()],
+            alive: vec![true; triangles.len()],
+            alive_count: triangles.len(),
+            heap: BinaryHeap::new(),
+            position_of,
+            points,
+            triangles,
+        };
+
+        // how many triangles use each edge between positions, once means it's on a boundary
+        let mut edge_uses: HashMap<(usize, usize), u32> = HashMap::new();
+        for index in 0..simplifier.triangles.len() {
+            let corners = simplifier.corners(index);
+            if corners[0] == corners[1] || corners[1] == corners[2] || corners[0] == corners[2] {
+                simplifier.alive[index] = false;
+                simplifier.alive_count -= 1;
+                continue;
+            }
+            let normal = simplifier.normal(corners);
+            let quadric = Quadric::from_plane(
+                normalize(normal),
+                -dot(normalize(normal), simplifier.points[corners[0]]),
+                length(normal) * 0.5,
+            );
+            for (i, &corner) in corners.iter().enumerate() {
+                simplifier.quadrics[corner].add(&quadric);
+                simplifier.adjacency[corner].push(index);
+                let next = corners[(i + 1) % 3];
+                *edge_uses
+                    .entry((corner.min(next), corner.max(next)))
+                    .or_default() += 1;
+            }
+        }
+        for index in 0..simplifier.triangles.len() {
+            if !simplifier.alive[index] {
+                continue;
+            }
+            let corners = simplifier.corners(index);
+            let face_normal = normalize(simplifier.normal(corners));
+            for i in 0..3 {
+                let (a, b) = (corners[i], corners[(i + 1) % 3]);
+                if edge_uses[&(a.min(b), a.max(b))] != 1 {
+                    continue;
+                }
+                let edge = sub(simplifier.points[b], simplifier.points[a]);
+                let normal = normalize(cross(edge, face_normal));
+                let quadric = Quadric::from_plane(
+                    normal,
+                    -dot(normal, simplifier.points[a]),
+                    BOUNDARY_WEIGHT * dot(edge, edge),
+                );
+                simplifier.quadrics[a].add(&quadric);
+                simplifier.quadrics[b].add(&quadric);
+            }
+        }
+
+        for position in 0..simplifier.points.len() {
+            simplifier.push_collapses(position);
+        }
+        simplifier
+    }
+
+    fn corners(&self, triangle: usize) -> [usize; 3] {
+        self.triangles[triangle].map(|vertex| self.position_of[vertex as usize])
+    }
+
+    // Twice the area long
+    fn normal(&self, [a, b, c]: [usize; 3]) -> [f64; 3] {
+        let (a, b, c) = (self.points[a], self.points[b], self.points[c]);
+        cross(sub(b, a), sub(c, a))
+    }
+
+    fn neighbours(&self, position: usize) -> Vec<usize> {
+        let mut neighbours: Vec<usize> = self.adjacency[position]
+            .iter()
+            .filter(|&&triangle| self.alive[triangle])
+            .flat_map(|&triangle| self.corners(triangle))
+            .filter(|&corner| corner != position)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    // Both directions of every edge around the position
+    fn push_collapses(&mut self, position: usize) {
+        for neighbour in self.neighbours(position) {
+            for (from, to) in [(position, neighbour), (neighbour, position)] {
+                let mut quadric = self.quadrics[from];
+                quadric.add(&self.quadrics[to]);
+                self.heap.push(Collapse {
+                    cost: quadric.error(self.points[to]),
+                    from,
+                    to,
+                    versions: (self.versions[from], self.versions[to]),
+                });
+            }
+        }
+    }
+
+    fn run(&mut self, target_triangle_count: usize) {
+        while self.alive_count > target_triangle_count {
+            let collapse = match self.heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let Collapse { from, to, .. } = collapse;
+            if self.removed[from]
+                || self.removed[to]
+                || collapse.versions != (self.versions[from], self.versions[to])
+            {
+                continue;
+            }
+            if let Some(remap) = self.find_remap(from, to) {
+                self.apply(from, to, &remap);
+            }
+        }
+    }
+
+    // Where each vertex at from goes, None if the collapse would break a seam or flip a
+    // triangle
+    fn find_remap(&self, from: usize, to: usize) -> Option<HashMap<u32, u32>> {
+        let triangles: Vec<usize> = self.adjacency[from]
+            .iter()
+            .copied()
+            .filter(|&triangle| self.alive[triangle])
+            .collect();
+        let mut remap = HashMap::new();
+        for &triangle in &triangles {
+            let vertices = self.triangles[triangle];
+            let destination = vertices
+                .iter()
+                .find(|&&vertex| self.position_of[vertex as usize] == to);
+            if let Some(&destination) = destination {
+                for &vertex in &vertices {
+                    if self.position_of[vertex as usize] == from {
+                        remap.entry(vertex).or_insert(destination);
+                    }
+                }
+            }
+        }
+
+        for &triangle in &triangles {
+            let corners = self.corners(triangle);
+            if corners.contains(&to) {
+                continue;
+            }
+            for (&vertex, &corner) in self.triangles[triangle].iter().zip(&corners) {
+                if corner == from && !remap.contains_key(&vertex) {
+                    return None;
+                }
+            }
+            let before = self.normal(corners);
+            let after = self.normal(corners.map(|corner| if corner == from { to } else { corner }));
+            if dot(before, after) <= 0.0 {
+                return None;
+            }
+        }
+        Some(remap)
+    }
+
+    fn apply(&mut self, from: usize, to: usize, remap: &HashMap<u32, u32>) {
+        let triangles = std::mem::take(&mut self.adjacency[from]);
+        for triangle in triangles {
+            if !self.alive[triangle] {
+                continue;
+            }
+            if self.corners(triangle).contains(&to) {
+                self.alive[triangle] = false;
+                self.alive_count -= 1;
+                continue;
+            }
+            for vertex in self.triangles[triangle].iter_mut() {
+                if let Some(&destination) = remap.get(vertex) {
+                    *vertex = destination;
+                }
+            }
+            self.adjacency[to].push(triangle);
+        }
+        let quadric = self.quadrics[from];
+        self.quadrics[to].add(&quadric);
+        self.removed[from] = true;
+
+        let neighbours = self.neighbours(to);
+        for &position in neighbours.iter().chain([&to]) {
+            self.versions[position] += 1;
+        }
+        for position in neighbours.into_iter().chain([to]) {
+            self.push_collapses(position);
+        }
+    }
+
+    fn get_indices(&self) -> Vec<u32> {
+        self.triangles
+            .iter()
+            .zip(&self.alive)
+            .filter(|(_, &alive)| alive)
+            .flat_map(|(triangle, _)| *triangle)
+            .collect()
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn length(a: [f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn normalize(a: [f64; 3]) -> [f64; 3] {
+    let length = length(a);
+    if length > 0.0 {
+        a.map(|x| x / length)
+    } else {
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // n x n quads in the xy plane, facing +z
+    fn grid(n: u32) -> (Vec<[f32; 3]>, Vec<u32>) {
+        let mut positions = Vec::new();
+        for y in 0..=n {
+            for x in 0..=n {
+                positions.push([x as f32, y as f32, 0.0]);
+            }
+        }
+        let mut indices = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let corner = y * (n + 1) + x;
+                let (right, up) = (corner + 1, corner + n + 1);
+                indices.extend_from_slice(&[corner, right, up + 1, corner, up + 1, up]);
+            }
+        }
+        (positions, indices)
+    }
+
+    // A 2x2x2 cube of 4x4 quads a face, every face with its own vertices the way a mesh with
+    // face normals is split
+    fn cube() -> (Vec<[f32; 3]>, Vec<u32>) {
+        let (face_positions, face_indices) = grid(4);
+        let mut positions = Vec::new();
+        let mut indices = Vec::new();
+        for axis in 0..3 {
+            for side in [-1.0f32, 1.0] {
+                let first = positions.len() as u32;
+                for &[u, v, _] in &face_positions {
+                    let (u, v) = (u / 2.0 - 1.0, v / 2.0 - 1.0);
+                    let mut position = [0.0; 3];
+                    position[axis] = side;
+                    // swapped on the negative side so the face still points outwards
+                    position[(axis + 1) % 3] = if side > 0.0 { u } else { v };
+                    position[(axis + 2) % 3] = if side > 0.0 { v } else { u };
+                    positions.push(position);
+                }
+                indices.extend(face_indices.iter().map(|&index| first + index));
+            }
+        }
+        (positions, indices)
+    }
+
+    fn triangle_normal(positions: &[[f32; 3]], triangle: &[u32]) -> [f64; 3] {
+        let [a, b, c] = [0, 1, 2].map(|i| positions[triangle[i] as usize].map(f64::from));
+        cross(sub(b, a), sub(c, a))
+    }
+
+    #[test]
+    fn grid_reaches_the_target_without_flipping() {
+        let (positions, indices) = grid(8);
+        let target = indices.len() / 4;
+        let simplified = simplify(&positions, &indices, target);
+        assert!(!simplified.is_empty());
+        assert!(simplified.len() <= target);
+        assert_eq!(simplified.len() % 3, 0);
+        for triangle in simplified.chunks_exact(3) {
+            assert!(triangle.iter().all(|&index| indices.contains(&index)));
+            assert!(triangle_normal(&positions, triangle)[2] > 0.0);
+        }
+    }
+
+    #[test]
+    fn grid_keeps_its_outline() {
+        let (positions, indices) = grid(8);
+        let simplified = simplify(&positions, &indices, 6);
+        // the boundary quadrics hold the corners in place
+        for corner in [
+            [0.0, 0.0, 0.0],
+            [8.0, 0.0, 0.0],
+            [0.0, 8.0, 0.0],
+            [8.0, 8.0, 0.0],
+        ] {
+            assert!(simplified
+                .iter()
+                .any(|&index| positions[index as usize] == corner));
+        }
+    }
+
+    #[test]
+    fn cube_faces_stay_outward_and_on_their_vertices() {
+        let (positions, indices) = cube();
+        let target = indices.len() / 2;
+        let simplified = simplify(&positions, &indices, target);
+        assert!(!simplified.is_empty());
+        assert!(simplified.len() <= target);
+        for triangle in simplified.chunks_exact(3) {
+            assert!(triangle.iter().all(|&index| indices.contains(&index)));
+            let centroid = triangle
+                .iter()
+                .map(|&index| positions[index as usize].map(f64::from))
+                .fold([0.0; 3], |sum, p| {
+                    [sum[0] + p[0], sum[1] + p[1], sum[2] + p[2]]
+                });
+            assert!(dot(triangle_normal(&positions, triangle), centroid) > 0.0);
+        }
+    }
+
+    #[test]
+    fn target_above_the_input_changes_nothing() {
+        let (positions, indices) = grid(2);
+        assert_eq!(simplify(&positions, &indices, indices.len()), indices);
+    }
+}
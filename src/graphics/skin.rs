@@ -0,0 +1,395 @@
+use std::rc::Rc;
+
+use anyhow::{bail, Result};
+use cgmath::SquareMatrix;
+
+use super::animation::{self, Interpolate, PlayMode, Track};
+use super::instance::Transform;
+
+pub struct Joint {
+    pub parent: Option<usize>,
+    // local transform when nothing animates the joint
+    pub rest: Transform,
+    // from the mesh into the joint's space at bind time
+    pub inverse_bind: cgmath::Matrix4<f32>,
+}
+
+// Joints ordered parents first, the way glTF skins are usually exported
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    pub fn make(joints: Vec<Joint>) -> Result<Self> {
+        for (index, joint) in joints.iter().enumerate() {
+            if let Some(parent) = joint.parent {
+                if parent >= index {
+                    bail!("joint {} comes before its parent {}", index, parent);
+                }
+            }
+        }
+        Ok(Self { joints })
+    }
+
+    // Binds the mesh in the rest pose, for skins that come without inverse bind matrices
+    pub fn make_from_rest_pose(rest: Vec<(Option<usize>, Transform)>) -> Result<Self> {
+        let joints = rest
+            .into_iter()
+            .map(|(parent, rest)| Joint {
+                parent,
+                rest,
+                inverse_bind: cgmath::Matrix4::identity(),
+            })
+            .collect();
+        let mut skeleton = Self::make(joints)?;
+        let rest_pose = skeleton.get_rest_pose();
+        let globals = skeleton.compute_global_matrices(&rest_pose);
+        for (joint, global) in skeleton.joints.iter_mut().zip(globals) {
+            joint.inverse_bind = global.invert().unwrap_or_else(cgmath::Matrix4::identity);
+        }
+        Ok(skeleton)
+    }
+
+    pub fn get_joint_count(&self) -> usize {
+        self.joints.len()
+    }
+
+    fn get_rest_pose(&self) -> Vec<Transform> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+
+    fn compute_global_matrices(&self, pose: &[Transform]) -> Vec<cgmath::Matrix4<f32>> {
+        let mut globals: Vec<cgmath::Matrix4<f32>> = Vec::with_capacity(self.joints.len());
+        for (joint, local) in self.joints.iter().zip(pose) {
+            let parent = joint
+                .parent
+                .map_or_else(cgmath::Matrix4::identity, |parent| globals[parent]);
+            globals.push(parent * local.to_matrix());
+        }
+        globals
+    }
+}
+
+// The tracks of one joint
+pub struct JointChannel {
+    pub joint: usize,
+    pub translation: Option<Track<cgmath::Vector3<f32>>>,
+    pub rotation: Option<Track<cgmath::Quaternion<f32>>>,
+    pub scale: Option<Track<cgmath::Vector3<f32>>>,
+}
+
+// A glTF style animation, channels for some of the joints on one timeline. Joints without a
+// channel keep their rest pose.
+pub struct SkeletalClip {
+    pub channels: Vec<JointChannel>,
+    pub mode: PlayMode,
+}
+
+impl SkeletalClip {
+    pub fn get_duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .flat_map(|channel| {
+                [
+                    channel.translation.as_ref().map(Track::get_duration),
+                    channel.rotation.as_ref().map(Track::get_duration),
+                    channel.scale.as_ref().map(Track::get_duration),
+                ]
+            })
+            .flatten()
+            .fold(0.0, f32::max)
+    }
+
+    // Overwrites the animated parts of the pose
+    fn sample(&self, time: f32, pose: &mut [Transform]) {
+        let time = animation::wrap_time(self.mode, time, self.get_duration());
+        for channel in &self.channels {
+            let local = match pose.get_mut(channel.joint) {
+                Some(local) => local,
+                None => continue,
+            };
+            if let Some(position) = channel.translation.as_ref().and_then(|t| t.sample(time)) {
+                local.position = position;
+            }
+            if let Some(rotation) = channel.rotation.as_ref().and_then(|t| t.sample(time)) {
+                local.rotation = rotation;
+            }
+            if let Some(scale) = channel.scale.as_ref().and_then(|t| t.sample(time)) {
+                local.scale = scale;
+            }
+        }
+    }
+}
+
+struct ClipState {
+    clip: Rc<SkeletalClip>,
+    time: f32,
+}
+
+impl ClipState {
+    fn make(clip: Rc<SkeletalClip>) -> Self {
+        Self { clip, time: 0.0 }
+    }
+}
+
+struct Skin {
+    skeleton: Rc<Skeleton>,
+    joint_offset: u32,
+    primary: Option<ClipState>,
+    secondary: Option<ClipState>,
+    // 0 plays only the primary clip, 1 only the secondary
+    blend: f32,
+}
+
+impl Skin {
+    fn compute_pose(&self) -> Vec<Transform> {
+        let rest = self.skeleton.get_rest_pose();
+        let mut pose = rest.clone();
+        if let Some(primary) = &self.primary {
+            primary.clip.sample(primary.time, &mut pose);
+        }
+        if let Some(secondary) = &self.secondary {
+            if self.blend > 0.0 {
+                let mut other = rest;
+                secondary.clip.sample(secondary.time, &mut other);
+                // slerp doesn't land exactly on its end
+                if self.blend >= 1.0 {
+                    return other;
+                }
+                for (local, other) in pose.iter_mut().zip(other) {
+                    local.position =
+                        Interpolate::linear(local.position, other.position, self.blend);
+                    local.rotation =
+                        Interpolate::linear(local.rotation, other.rotation, self.blend);
+                    local.scale = Interpolate::linear(local.scale, other.scale, self.blend);
+                }
+            }
+        }
+        pose
+    }
+
+    // Joint matrices in the skeleton's order, from the bind pose into the current one
+    fn compute_palette(&self) -> Vec<cgmath::Matrix4<f32>> {
+        let globals = self.skeleton.compute_global_matrices(&self.compute_pose());
+        globals
+            .iter()
+            .zip(&self.skeleton.joints)
+            .map(|(global, joint)| global * joint.inverse_bind)
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SkinId(usize);
+
+// The joint matrices of every skin live in one storage buffer, the palette. Skinned instances
+// find theirs through the joint offset of their skin.
+pub struct SkinSet {
+    skins: Vec<Skin>,
+    joint_count: usize,
+    buffer: Option<wgpu::Buffer>,
+}
+
+impl SkinSet {
+    pub fn make() -> Self {
+        Self {
+            skins: Vec::new(),
+            joint_count: 0,
+            buffer: None,
+        }
+    }
+
+    // Skins have to be added before create_buffer, the palette doesn't grow
+    pub fn add(&mut self, skeleton: Rc<Skeleton>) -> SkinId {
+        let joint_offset = self.joint_count as u32;
+        self.joint_count += skeleton.get_joint_count();
+        self.skins.push(Skin {
+            skeleton,
+            joint_offset,
+            primary: None,
+            secondary: None,
+            blend: 0.0,
+        });
+        SkinId(self.skins.len() - 1)
+    }
+
+    pub fn get_joint_offset(&self, skin: SkinId) -> u32 {
+        self.skins[skin.0].joint_offset
+    }
+
+    pub fn play(&mut self, skin: SkinId, clip: Rc<SkeletalClip>) {
+        self.skins[skin.0].primary = Some(ClipState::make(clip));
+    }
+
+    // Plays a second clip, mixed in by the blend weight
+    pub fn play_blended(&mut self, skin: SkinId, clip: Rc<SkeletalClip>, blend: f32) {
+        let skin = &mut self.skins[skin.0];
+        skin.secondary = Some(ClipState::make(clip));
+        skin.blend = blend.clamp(0.0, 1.0);
+    }
+
+    pub fn set_blend(&mut self, skin: SkinId, blend: f32) {
+        self.skins[skin.0].blend = blend.clamp(0.0, 1.0);
+    }
+
+    pub fn create_buffer(&mut self, device: &wgpu::Device) {
+        // a binding can't be empty, keep one matrix around even without skins
+        let size = self.joint_count.max(1) * std::mem::size_of::<[[f32; 4]; 4]>();
+        self.buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Joint Palette Buffer"),
+            size: size as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+    }
+
+    pub fn get_buffer(&self) -> Option<&wgpu::Buffer> {
+        self.buffer.as_ref()
+    }
+
+    // Advances the clips by delta seconds and uploads the joint matrices
+    pub fn update(&mut self, queue: &wgpu::Queue, delta: f32) {
+        let mut palette: Vec<[[f32; 4]; 4]> = Vec::with_capacity(self.joint_count);
+        for skin in self.skins.iter_mut() {
+            for state in [&mut skin.primary, &mut skin.secondary]
+                .into_iter()
+                .flatten()
+            {
+                state.time += delta;
+            }
+            palette.extend(
+                skin.compute_palette()
+                    .into_iter()
+                    .map(|matrix| -> [[f32; 4]; 4] { matrix.into() }),
+            );
+        }
+        if let (Some(buffer), false) = (&self.buffer, palette.is_empty()) {
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&palette));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::animation::{Interpolation, Keyframe};
+    use cgmath::Rotation3;
+
+    fn yaw(degrees: f32) -> cgmath::Quaternion<f32> {
+        cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(degrees))
+    }
+
+    fn transform(position: [f32; 3], rotation: cgmath::Quaternion<f32>) -> Transform {
+        Transform {
+            position: position.into(),
+            rotation,
+            ..Default::default()
+        }
+    }
+
+    // a bent chain of three
+    fn skeleton() -> Rc<Skeleton> {
+        Rc::new(
+            Skeleton::make_from_rest_pose(vec![
+                (None, transform([1.0, 0.0, 0.0], yaw(30.0))),
+                (Some(0), transform([0.0, 2.0, 0.0], yaw(-45.0))),
+                (Some(1), transform([0.0, 2.0, 1.0], yaw(10.0))),
+            ])
+            .unwrap(),
+        )
+    }
+
+    // turns one joint about y from 0 to the given angle over a second
+    fn turn(joint: usize, degrees: f32) -> Rc<SkeletalClip> {
+        Rc::new(SkeletalClip {
+            channels: vec![JointChannel {
+                joint,
+                translation: None,
+                rotation: Some(Track::make(
+                    vec![
+                        Keyframe {
+                            time: 0.0,
+                            value: yaw(0.0),
+                        },
+                        Keyframe {
+                            time: 1.0,
+                            value: yaw(degrees),
+                        },
+                    ],
+                    Interpolation::Linear,
+                )),
+                scale: None,
+            }],
+            mode: PlayMode::Once,
+        })
+    }
+
+    fn skin(primary: Option<Rc<SkeletalClip>>, secondary: Option<Rc<SkeletalClip>>) -> Skin {
+        let state = |clip| ClipState { clip, time: 0.5 };
+        Skin {
+            skeleton: skeleton(),
+            joint_offset: 0,
+            primary: primary.map(state),
+            secondary: secondary.map(state),
+            blend: 0.0,
+        }
+    }
+
+    fn assert_same(a: &[Transform], b: &[Transform]) {
+        for (a, b) in a.iter().zip(b) {
+            assert_eq!(
+                (a.position, a.rotation, a.scale),
+                (b.position, b.rotation, b.scale)
+            );
+        }
+    }
+
+    #[test]
+    fn make_wants_parents_first() {
+        let joint = |parent| Joint {
+            parent,
+            rest: Transform::default(),
+            inverse_bind: cgmath::Matrix4::identity(),
+        };
+        assert!(Skeleton::make(vec![joint(None), joint(Some(0)), joint(Some(0))]).is_ok());
+        let error = Skeleton::make(vec![joint(Some(1)), joint(None)])
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "joint 0 comes before its parent 1");
+        assert!(Skeleton::make(vec![joint(None), joint(Some(1))]).is_err());
+    }
+
+    #[test]
+    fn rest_pose_palette_is_identity() {
+        for matrix in skin(None, None).compute_palette() {
+            let difference = matrix - cgmath::Matrix4::identity();
+            for column in [difference.x, difference.y, difference.z, difference.w] {
+                assert!(cgmath::InnerSpace::magnitude(column) < 1e-5, "{:?}", matrix);
+            }
+        }
+    }
+
+    #[test]
+    fn joints_without_a_channel_keep_their_rest_transform() {
+        let skin = skin(Some(turn(1, 90.0)), None);
+        let rest = skin.skeleton.get_rest_pose();
+        let pose = skin.compute_pose();
+        assert_same(&pose[..1], &rest[..1]);
+        assert_same(&pose[2..], &rest[2..]);
+        // the channel only turns the joint, it keeps its rest position
+        assert_eq!(pose[1].position, rest[1].position);
+        assert!(cgmath::InnerSpace::dot(pose[1].rotation, yaw(45.0)) > 0.9999);
+    }
+
+    #[test]
+    fn blend_ends_match_either_clip() {
+        let (primary, secondary) = (turn(1, 90.0), turn(2, -60.0));
+        let mut blended = skin(Some(primary.clone()), Some(secondary.clone()));
+        let only_primary = skin(Some(primary), None).compute_pose();
+        let only_secondary = skin(Some(secondary), None).compute_pose();
+
+        assert_same(&blended.compute_pose(), &only_primary);
+        blended.blend = 1.0;
+        assert_same(&blended.compute_pose(), &only_secondary);
+    }
+}
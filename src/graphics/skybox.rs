@@ -0,0 +1,257 @@
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use super::camera::Camera;
+use super::environment::EnvironmentMap;
+use super::scene::Background;
+use super::texture::Texture;
+
+// Draws the scene background at the far plane after the opaque geometry,
+// so only the pixels nothing else covered get shaded.
+pub struct Skybox {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    buffer: wgpu::Buffer,
+    uniform: SkyUniform,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    zenith: [f32; 4],
+    horizon: [f32; 4],
+    ground: [f32; 4],
+    scattering: [f32; 4],
+    sun: [f32; 4],
+    mode: u32,
+    _padding: [u32; 3],
+}
+
+impl Skybox {
+    const MODE_CUBE: u32 = 0;
+    const MODE_GRADIENT: u32 = 1;
+    const MODE_ATMOSPHERE: u32 = 2;
+    const EQUIRECT_CUBE_SIZE: u32 = 1024;
+
+    // Returns None for a plain clear colour
+    pub fn make(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        background: &Background,
+        environment: &EnvironmentMap,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Result<Option<Self>> {
+        let mut uniform = SkyUniform {
+            zenith: [0.0; 4],
+            horizon: [0.0; 4],
+            ground: [0.0; 4],
+            scattering: [0.0; 4],
+            sun: [0.0, 1.0, 0.0, 0.0],
+            mode: Self::MODE_CUBE,
+            _padding: [0; 3],
+        };
+
+        let owned_cube = match background {
+            Background::Color(_) => return Ok(None),
+            Background::Environment => None,
+            Background::CubeFaces(paths) => {
+                let mut faces = Vec::with_capacity(6);
+                for path in paths {
+                    faces.push(
+                        image::open(path)
+                            .with_context(|| format!("failed to load {}", path.display()))?,
+                    );
+                }
+                let faces: [image::DynamicImage; 6] = faces.try_into().ok().unwrap();
+                Some(Texture::from_cube_images(
+                    device,
+                    queue,
+                    &faces,
+                    "skybox_texture",
+                )?)
+            }
+            Background::Equirect(path) => {
+                let img = image::open(path)
+                    .with_context(|| format!("failed to load {}", path.display()))?;
+                Some(EnvironmentMap::cube_from_equirect(
+                    device,
+                    queue,
+                    &img.to_rgba32f(),
+                    Self::EQUIRECT_CUBE_SIZE,
+                ))
+            }
+            Background::Gradient {
+                zenith,
+                horizon,
+                ground,
+            } => {
+                uniform.mode = Self::MODE_GRADIENT;
+                uniform.zenith = [zenith[0], zenith[1], zenith[2], 1.0];
+                uniform.horizon = [horizon[0], horizon[1], horizon[2], 1.0];
+                uniform.ground = [ground[0], ground[1], ground[2], 1.0];
+                Some(Self::create_placeholder_cube(device))
+            }
+            Background::Atmosphere {
+                rayleigh,
+                mie,
+                sun_intensity,
+            } => {
+                uniform.mode = Self::MODE_ATMOSPHERE;
+                uniform.scattering = [rayleigh[0], rayleigh[1], rayleigh[2], *mie];
+                uniform.sun[3] = *sun_intensity;
+                Some(Self::create_placeholder_cube(device))
+            }
+        };
+        let cube = owned_cube
+            .as_ref()
+            .unwrap_or_else(|| environment.get_environment());
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sky Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("sky_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&cube.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(cube.sampler.as_ref().unwrap()),
+                },
+            ],
+            label: Some("sky_bind_group"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skybox Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../skybox.wgsl").into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("SkyboxPSO"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("SkyboxRootSignature"),
+                    bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+                unclipped_depth: false,
+            },
+            // The triangle sits exactly at depth 1.0, which only passes where the clear value survived
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Ok(Some(Self {
+            pipeline,
+            bind_group,
+            buffer,
+            uniform,
+        }))
+    }
+
+    // The atmosphere is lit by the same directional light that casts the shadows
+    pub fn update(&mut self, queue: &wgpu::Queue, light_camera: &Camera) {
+        let sun = -light_camera.get_forward();
+        self.uniform.sun = [sun.x, sun.y, sun.z, self.uniform.sun[3]];
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Procedural modes don't sample the cube but the layout still needs one bound
+    fn create_placeholder_cube(device: &wgpu::Device) -> Texture {
+        Texture::create_cube_texture(
+            device,
+            1,
+            1,
+            wgpu::TextureFormat::Rgba16Float,
+            "sky_placeholder_texture",
+        )
+    }
+}
@@ -0,0 +1,147 @@
+use super::post_process::{self, PostEffect, PostFrame};
+use super::scene::PostEffectKind;
+use super::texture::Texture;
+use super::tonemapping::Tonemapper;
+
+// Edge detection, blending weights and neighbourhood blending, see smaa.wgsl
+pub struct Smaa {
+    edges_texture: Texture,
+    weights_texture: Texture,
+    weights_bind_group: wgpu::BindGroup,
+
+    bind_group_layout: wgpu::BindGroupLayout,
+    edges_pipeline: wgpu::RenderPipeline,
+    weights_pipeline: wgpu::RenderPipeline,
+    blend_pipeline: wgpu::RenderPipeline,
+}
+
+impl Smaa {
+    const EDGES_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg8Unorm;
+    const WEIGHTS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    pub fn make(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                post_process::texture_layout_entry(0, false),
+                post_process::texture_layout_entry(1, false),
+            ],
+            label: Some("smaa_bind_group_layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("SMAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../smaa.wgsl").into()),
+        });
+        let create_pipeline = |entry_point, format: wgpu::TextureFormat| {
+            post_process::create_full_screen_pipeline(
+                device,
+                "SmaaPSO",
+                &[&bind_group_layout],
+                &shader,
+                entry_point,
+                &[Some(format.into())],
+            )
+        };
+        let edges_pipeline = create_pipeline("fs_edges", Self::EDGES_FORMAT);
+        let weights_pipeline = create_pipeline("fs_weights", Self::WEIGHTS_FORMAT);
+        let blend_pipeline = create_pipeline("fs_blend", Tonemapper::HDR_FORMAT);
+
+        let (edges_texture, weights_texture) = Self::create_textures(device, config);
+        let weights_bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &edges_texture.view,
+            &edges_texture.view,
+        );
+
+        Self {
+            edges_texture,
+            weights_texture,
+            weights_bind_group,
+
+            bind_group_layout,
+            edges_pipeline,
+            weights_pipeline,
+            blend_pipeline,
+        }
+    }
+
+    fn create_textures(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> (Texture, Texture) {
+        (
+            Texture::create_render_target(device, config, Self::EDGES_FORMAT, "smaa_edges"),
+            Texture::create_render_target(device, config, Self::WEIGHTS_FORMAT, "smaa_weights"),
+        )
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        source: &wgpu::TextureView,
+        aux: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(aux),
+                },
+            ],
+            label: Some("smaa_bind_group"),
+        })
+    }
+}
+
+impl PostEffect for Smaa {
+    fn kind(&self) -> PostEffectKind {
+        PostEffectKind::Smaa
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        (self.edges_texture, self.weights_texture) = Self::create_textures(device, config);
+        self.weights_bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.edges_texture.view,
+            &self.edges_texture.view,
+        );
+    }
+
+    fn render(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, frame: &PostFrame) {
+        let edges_bind_group =
+            Self::create_bind_group(device, &self.bind_group_layout, frame.input, frame.input);
+        post_process::draw_full_screen(
+            encoder,
+            "SMAA Edge Pass",
+            &self.edges_pipeline,
+            &edges_bind_group,
+            &[&self.edges_texture.view],
+        );
+        post_process::draw_full_screen(
+            encoder,
+            "SMAA Weight Pass",
+            &self.weights_pipeline,
+            &self.weights_bind_group,
+            &[&self.weights_texture.view],
+        );
+        let blend_bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            frame.input,
+            &self.weights_texture.view,
+        );
+        post_process::draw_full_screen(
+            encoder,
+            "SMAA Blend Pass",
+            &self.blend_pipeline,
+            &blend_bind_group,
+            &[frame.output],
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+
+use super::camera::Camera;
+use super::post_process::{self, PostEffect, PostFrame};
+use super::scene::PostEffectKind;
+use super::texture::{Filtering, SamplerOptions, Texture};
+use super::tonemapping::Tonemapper;
+
+// Jitters the camera and accumulates frames into a history, see taa.wgsl
+pub struct Taa {
+    // the resolve writes one while reading the other, they swap every frame
+    history_textures: [Texture; 2],
+    history_index: usize,
+    frame_index: u32,
+    size: (u32, u32),
+    previous_view_proj: cgmath::Matrix4<f32>,
+    reset: bool,
+
+    settings_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaSettings {
+    inv_view_proj: [[f32; 4]; 4],
+    previous_view_proj: [[f32; 4]; 4],
+    blend: f32,
+    reset: u32,
+    _padding: [u32; 2],
+}
+
+impl Taa {
+    // length of the Halton(2, 3) jitter sequence
+    const JITTER_SAMPLES: u32 = 8;
+    const BLEND: f32 = 0.1;
+
+    pub fn make(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TAA Settings Buffer"),
+            contents: bytemuck::cast_slice(&[<TaaSettings as bytemuck::Zeroable>::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = SamplerOptions {
+            filtering: Filtering::Bilinear,
+            ..Default::default()
+        }
+        .create_sampler(device);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                post_process::texture_layout_entry(1, false),
+                post_process::texture_layout_entry(2, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("taa_bind_group_layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("TAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../taa.wgsl").into()),
+        });
+        let pipeline = post_process::create_full_screen_pipeline(
+            device,
+            "TaaPSO",
+            &[&bind_group_layout],
+            &shader,
+            "fs_main",
+            &[
+                Some(Tonemapper::HDR_FORMAT.into()),
+                Some(Tonemapper::HDR_FORMAT.into()),
+            ],
+        );
+
+        Self {
+            history_textures: Self::create_history_textures(device, config),
+            history_index: 0,
+            frame_index: 0,
+            size: (config.width, config.height),
+            previous_view_proj: cgmath::Matrix4::identity(),
+            reset: true,
+
+            settings_buffer,
+            sampler,
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    fn create_history_textures(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> [Texture; 2] {
+        [
+            Texture::create_render_target(device, config, Tonemapper::HDR_FORMAT, "taa_history_0"),
+            Texture::create_render_target(device, config, Tonemapper::HDR_FORMAT, "taa_history_1"),
+        ]
+    }
+}
+
+// Low discrepancy sequence in [0, 1), covers the pixel evenly within a few frames
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+impl PostEffect for Taa {
+    fn kind(&self) -> PostEffectKind {
+        PostEffectKind::Taa
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.history_textures = Self::create_history_textures(device, config);
+        self.size = (config.width, config.height);
+        self.reset = true;
+    }
+
+    fn update(&mut self, queue: &wgpu::Queue, camera: &mut Camera) {
+        self.history_index = 1 - self.history_index;
+        self.frame_index = (self.frame_index + 1) % Self::JITTER_SAMPLES;
+
+        // half a pixel either way, in NDC
+        let (width, height) = self.size;
+        let sample = self.frame_index + 1;
+        camera.set_jitter(cgmath::Vector2::new(
+            (halton(sample, 2) - 0.5) * 2.0 / width as f32,
+            (halton(sample, 3) - 0.5) * 2.0 / height as f32,
+        ));
+
+        let unjittered = camera.build_unjittered_view_projection_matrix();
+        if self.reset {
+            self.previous_view_proj = unjittered;
+        }
+        let settings = TaaSettings {
+            inv_view_proj: camera
+                .build_view_projection_matrix()
+                .invert()
+                .unwrap_or_else(cgmath::Matrix4::identity)
+                .into(),
+            previous_view_proj: self.previous_view_proj.into(),
+            blend: Self::BLEND,
+            reset: self.reset as u32,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+        self.previous_view_proj = unjittered;
+        self.reset = false;
+    }
+
+    fn reset(&mut self) {
+        self.reset = true;
+    }
+
+    fn render(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, frame: &PostFrame) {
+        let history = &self.history_textures[1 - self.history_index];
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(frame.input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&history.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(frame.depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("taa_bind_group"),
+        });
+        post_process::draw_full_screen(
+            encoder,
+            "TAA Pass",
+            &self.pipeline,
+            &bind_group,
+            &[
+                frame.output,
+                &self.history_textures[self.history_index].view,
+            ],
+        );
+    }
+}
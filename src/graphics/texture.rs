@@ -1,28 +1,73 @@
 use anyhow::*;
 use image::GenericImageView;
 
+use super::compressed_texture::{self, CompressedImage};
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: Option<wgpu::Sampler>,
 }
 
-impl Texture {
-    pub fn from_bytes(
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        bytes: &[u8],
-        label: &str,
-    ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Filtering {
+    Nearest,
+    Bilinear,
+    // Bilinear within a mip level and linear between levels
+    Trilinear,
+}
+
+// How a texture is sampled. Each texture owns its sampler, so this is chosen per texture.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct SamplerOptions {
+    pub address_mode: wgpu::AddressMode,
+    pub filtering: Filtering,
+    // 1 disables anisotropic filtering, otherwise rounded up to a power of two up to 16.
+    // Silently ignored by adapters that don't support it.
+    pub anisotropy: u8,
+}
+
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        SamplerOptions {
+            address_mode: wgpu::AddressMode::ClampToEdge,
+            filtering: Filtering::Trilinear,
+            anisotropy: 1,
+        }
     }
+}
 
-    pub fn from_image(
+impl SamplerOptions {
+    pub fn create_sampler(&self, device: &wgpu::Device) -> wgpu::Sampler {
+        let (filter, mipmap_filter) = match self.filtering {
+            Filtering::Nearest => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest),
+            Filtering::Bilinear => (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest),
+            Filtering::Trilinear => (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear),
+        };
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter,
+            anisotropy_clamp: std::num::NonZeroU8::new(self.anisotropy.next_power_of_two().min(16))
+                .filter(|clamp| clamp.get() > 1),
+            ..Default::default()
+        })
+    }
+}
+
+impl Texture {
+    // Data textures (normal, metallic-roughness, occlusion maps) must use Rgba8Unorm.
+    // The full mip chain is generated on the GPU right after the upload.
+    pub fn from_image_with_format(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+        format: wgpu::TextureFormat,
+        sampler: &SamplerOptions,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -32,14 +77,17 @@ impl Texture {
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
+        let mip_level_count = size.max_mips(wgpu::TextureDimension::D2);
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
         });
 
         queue.write_texture(
@@ -57,18 +105,196 @@ impl Texture {
             },
             size,
         );
+        Self::generate_mipmaps(device, queue, &texture, format, mip_level_count);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+        let sampler = sampler.create_sampler(device);
+
+        Ok(Self {
+            texture,
+            view,
+            sampler: Some(sampler),
+        })
+    }
+
+    // Each level is rendered from the one above it with a linear filter. Going through a
+    // render pass means sRGB textures are averaged in linear space.
+    pub fn generate_mipmaps(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        mip_level_count: u32,
+    ) {
+        if mip_level_count <= 1 {
+            return;
+        }
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Blit Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../blit.wgsl").into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("MipmapPSO"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let bind_group_layout = pipeline.get_bind_group_layout(0);
+        // each source view holds a single level, so there is nothing to blend between mips
+        let sampler = SamplerOptions {
+            filtering: Filtering::Bilinear,
             ..Default::default()
+        }
+        .create_sampler(device);
+
+        let views = (0..mip_level_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    base_mip_level: mip,
+                    mip_level_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap CL"),
+        });
+        for mip in 1..mip_level_count as usize {
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&views[mip - 1]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                ],
+                label: Some("mipmap_bind_group"),
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mipmap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &views[mip],
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    // Uploads the pre-built mip chain as is when the device can sample the format. Without
+    // the matching compression feature BCn levels are decoded on the CPU, other formats fail.
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &CompressedImage,
+        label: Option<&str>,
+        sampler: &SamplerOptions,
+    ) -> Result<Self> {
+        let info = image.format.describe();
+        let (block_width, block_height) = (
+            info.block_dimensions.0 as u32,
+            info.block_dimensions.1 as u32,
+        );
+        // the base level of a compressed texture must be whole blocks
+        let supported = device.features().contains(info.required_features)
+            && image.width.is_multiple_of(block_width)
+            && image.height.is_multiple_of(block_height);
+        let (format, decoded_levels) = if supported {
+            (image.format, None)
+        } else {
+            let (format, levels) = image.decompress()?;
+            (format, Some(levels))
+        };
+        let levels = decoded_levels.as_ref().unwrap_or(&image.levels);
+        if levels.is_empty() {
+            bail!("{:?}: image has no mip levels", label);
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size: wgpu::Extent3d {
+                width: image.width,
+                height: image.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: levels.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         });
 
+        let info = format.describe();
+        let (block_width, block_height) = (
+            info.block_dimensions.0 as u32,
+            info.block_dimensions.1 as u32,
+        );
+        for (level, data) in levels.iter().enumerate() {
+            let (width, height) = image.level_size(level as u32);
+            let expected = compressed_texture::level_size(format, width, height);
+            if data.len() != expected {
+                bail!(
+                    "{:?}: mip level {} has {} bytes, expected {}",
+                    label,
+                    level,
+                    data.len(),
+                    expected
+                );
+            }
+            let blocks_wide = width.div_ceil(block_width);
+            let blocks_high = height.div_ceil(block_height);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(blocks_wide * info.block_size as u32),
+                    rows_per_image: std::num::NonZeroU32::new(blocks_high),
+                },
+                // small levels still cover a whole block
+                wgpu::Extent3d {
+                    width: blocks_wide * block_width,
+                    height: blocks_high * block_height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = sampler.create_sampler(device);
+
         Ok(Self {
             texture,
             view,
@@ -76,6 +302,29 @@ impl Texture {
         })
     }
 
+    // 1x1 texture used in place of a material map that wasn't provided.
+    // The value is stored as is (linear), so it works for colour and data maps alike.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+    ) -> Result<Self> {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        Self::from_image_with_format(
+            device,
+            queue,
+            &img,
+            Some(label),
+            wgpu::TextureFormat::Rgba8Unorm,
+            &SamplerOptions {
+                filtering: Filtering::Nearest,
+                ..Default::default()
+            },
+        )
+    }
+
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
 
     pub fn create_depth_texture(
@@ -123,10 +372,172 @@ impl Texture {
         }
     }
 
+    // Cube texture that compute passes write into (through a D2Array view) and shaders sample
+    pub fn create_cube_texture(
+        device: &wgpu::Device,
+        size: u32,
+        mip_level_count: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler: Some(sampler),
+        }
+    }
+
+    // Faces are expected in the order +X, -X, +Y, -Y, +Z, -Z
+    pub fn from_cube_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage; 6],
+        label: &str,
+    ) -> Result<Self> {
+        let (width, height) = faces[0].dimensions();
+        if width != height
+            || faces
+                .iter()
+                .any(|face| face.dimensions() != (width, height))
+        {
+            bail!("{}: cube faces must be square and all the same size", label);
+        }
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 6,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                &face.to_rgba8(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(4 * width),
+                    rows_per_image: std::num::NonZeroU32::new(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler: Some(sampler),
+        })
+    }
+
+    // View of a single mip of a cube texture, used as a storage target
+    pub fn create_cube_mip_view(&self, mip_level: u32) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            base_mip_level: mip_level,
+            mip_level_count: std::num::NonZeroU32::new(1),
+            ..Default::default()
+        })
+    }
+
+    // Single sampled colour target that later passes read from
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler: None,
+        }
+    }
+
     pub fn create_msaa_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
         label: &str,
+        sample_count: u32,
     ) -> Self {
         let size = wgpu::Extent3d {
             // 2.
@@ -138,9 +549,9 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 4,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
-            format: config.format,
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT, // 3.
         };
         let texture = device.create_texture(&desc);
@@ -0,0 +1,119 @@
+use anyhow::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use super::compressed_texture::CompressedImage;
+use super::texture::{SamplerOptions, Texture};
+
+// Colour maps (albedo, emissive) are authored in sRGB, data maps (normal,
+// metallic-roughness, occlusion) hold linear values and must not be decoded.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn format(&self) -> wgpu::TextureFormat {
+        match self {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct TextureKey {
+    path: PathBuf,
+    color_space: ColorSpace,
+    sampler: SamplerOptions,
+}
+
+// Loads every image once per path, colour space and sampler and hands out shared handles
+pub struct TextureCache {
+    textures: HashMap<TextureKey, Rc<Texture>>,
+}
+
+impl TextureCache {
+    pub fn make() -> TextureCache {
+        TextureCache {
+            textures: HashMap::new(),
+        }
+    }
+
+    pub fn load(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: impl AsRef<Path>,
+        color_space: ColorSpace,
+        sampler: &SamplerOptions,
+    ) -> Result<Rc<Texture>> {
+        let path = path.as_ref();
+        // so "a/../b.png" and "b.png" share an entry, falls back to the given path if it doesn't exist
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.get_or_insert(path.clone(), color_space, sampler, || {
+            // KTX2 and DDS keep their own mip chain instead of generating one
+            if CompressedImage::is_compressed_path(&path) {
+                let image =
+                    CompressedImage::load(&path)?.with_srgb(color_space == ColorSpace::Srgb);
+                return Texture::from_compressed(device, queue, &image, path.to_str(), sampler);
+            }
+            let img =
+                image::open(&path).with_context(|| format!("failed to load {}", path.display()))?;
+            Texture::from_image_with_format(
+                device,
+                queue,
+                &img,
+                path.to_str(),
+                color_space.format(),
+                sampler,
+            )
+        })
+    }
+
+    // For images embedded in the binary, `name` stands in for the path
+    pub fn load_bytes(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        bytes: &[u8],
+        color_space: ColorSpace,
+        sampler: &SamplerOptions,
+    ) -> Result<Rc<Texture>> {
+        self.get_or_insert(name.into(), color_space, sampler, || {
+            let img = image::load_from_memory(bytes)
+                .with_context(|| format!("failed to decode {}", name))?;
+            Texture::from_image_with_format(
+                device,
+                queue,
+                &img,
+                Some(name),
+                color_space.format(),
+                sampler,
+            )
+        })
+    }
+
+    fn get_or_insert(
+        &mut self,
+        path: PathBuf,
+        color_space: ColorSpace,
+        sampler: &SamplerOptions,
+        load: impl FnOnce() -> Result<Texture>,
+    ) -> Result<Rc<Texture>> {
+        let key = TextureKey {
+            path,
+            color_space,
+            sampler: *sampler,
+        };
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+        let texture = Rc::new(load()?);
+        self.textures.insert(key, texture.clone());
+        Ok(texture)
+    }
+}
@@ -0,0 +1,443 @@
+use wgpu::util::DeviceExt;
+
+use super::scene::{Exposure, ToneMapping};
+
+// The scene is rendered into Rgba16Float targets. This pass exposes it, maps it into the
+// display range and writes it to the surface, encoding to sRGB itself when the surface
+// format won't. Automatic exposure builds a luminance histogram of the frame on the GPU.
+pub struct Tonemapper {
+    size: (u32, u32),
+    tone_mapping: ToneMapping,
+    exposure: Exposure,
+    encode_srgb: bool,
+    // of the last frame, for the auto exposure adaptation
+    delta: f32,
+
+    settings_buffer: wgpu::Buffer,
+    params_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    exposure_buffer: wgpu::Buffer,
+
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    histogram_pipeline: wgpu::ComputePipeline,
+    histogram_bind_group_layout: wgpu::BindGroupLayout,
+    average_pipeline: wgpu::ComputePipeline,
+    average_bind_group: wgpu::BindGroup,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapSettings {
+    tone_mapping: u32,
+    encode_srgb: u32,
+    _padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct HistogramParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    pixel_count: f32,
+    adaptation_rate: f32,
+    key_value: f32,
+    compensation: f32,
+    // seconds since the last frame
+    delta: f32,
+    _padding: f32,
+}
+
+// Written by the average pass in auto mode, by the CPU in manual mode
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureState {
+    average_luminance: f32,
+    exposure: f32,
+}
+
+impl Tonemapper {
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const HISTOGRAM_BINS: usize = 256;
+    const WORKGROUP_SIZE: u32 = 16;
+    // middle grey
+    const KEY_VALUE: f32 = 0.18;
+
+    pub fn make(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        tone_mapping: ToneMapping,
+        exposure: Exposure,
+    ) -> Self {
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tonemap Settings Buffer"),
+            size: std::mem::size_of::<TonemapSettings>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Histogram Params Buffer"),
+            size: std::mem::size_of::<HistogramParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let histogram_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Histogram Buffer"),
+            contents: bytemuck::cast_slice(&[0u32; Self::HISTOGRAM_BINS]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let exposure_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Exposure Buffer"),
+            contents: bytemuck::cast_slice(&[ExposureState {
+                average_luminance: 0.0,
+                exposure: 1.0,
+            }]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // Tonemap Pass
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                uniform_layout_entry(0, wgpu::ShaderStages::FRAGMENT),
+                storage_layout_entry(1, wgpu::ShaderStages::FRAGMENT, true),
+                texture_layout_entry(2, wgpu::ShaderStages::FRAGMENT),
+            ],
+            label: Some("tonemap_bind_group_layout"),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tonemap Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../tonemap.wgsl").into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("TonemapPSO"),
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("TonemapRootSignature"),
+                    bind_group_layouts: &[&bind_group_layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        // Auto Exposure
+        let histogram_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    uniform_layout_entry(0, wgpu::ShaderStages::COMPUTE),
+                    texture_layout_entry(1, wgpu::ShaderStages::COMPUTE),
+                    storage_layout_entry(2, wgpu::ShaderStages::COMPUTE, false),
+                ],
+                label: Some("histogram_bind_group_layout"),
+            });
+        let histogram_pipeline = create_compute_pipeline(
+            device,
+            &histogram_bind_group_layout,
+            include_str!("../luminanceHistogram.wgsl"),
+            "Luminance Histogram",
+        );
+
+        let average_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    uniform_layout_entry(0, wgpu::ShaderStages::COMPUTE),
+                    storage_layout_entry(1, wgpu::ShaderStages::COMPUTE, false),
+                    storage_layout_entry(2, wgpu::ShaderStages::COMPUTE, false),
+                ],
+                label: Some("average_luminance_bind_group_layout"),
+            });
+        let average_pipeline = create_compute_pipeline(
+            device,
+            &average_bind_group_layout,
+            include_str!("../averageLuminance.wgsl"),
+            "Average Luminance",
+        );
+        let average_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &average_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("average_luminance_bind_group"),
+        });
+
+        let mut tonemapper = Self {
+            size: (config.width, config.height),
+            tone_mapping,
+            exposure,
+            encode_srgb: !config.format.describe().srgb,
+            delta: 0.0,
+
+            settings_buffer,
+            params_buffer,
+            histogram_buffer,
+            exposure_buffer,
+
+            pipeline,
+            bind_group_layout,
+            histogram_pipeline,
+            histogram_bind_group_layout,
+            average_pipeline,
+            average_bind_group,
+        };
+        tonemapper.set_tone_mapping(queue, tone_mapping);
+        tonemapper.set_exposure(queue, exposure);
+        tonemapper
+    }
+
+    pub fn get_tone_mapping(&self) -> ToneMapping {
+        self.tone_mapping
+    }
+
+    pub fn set_tone_mapping(&mut self, queue: &wgpu::Queue, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+        let settings = TonemapSettings {
+            tone_mapping: match tone_mapping {
+                ToneMapping::Reinhard => 0,
+                ToneMapping::Aces => 1,
+                ToneMapping::AgX => 2,
+            },
+            encode_srgb: self.encode_srgb as u32,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+    }
+
+    pub fn set_exposure(&mut self, queue: &wgpu::Queue, exposure: Exposure) {
+        self.exposure = exposure;
+        match exposure {
+            Exposure::Manual(exposure) => {
+                let state = ExposureState {
+                    average_luminance: 0.0,
+                    exposure,
+                };
+                queue.write_buffer(&self.exposure_buffer, 0, bytemuck::cast_slice(&[state]));
+            }
+            Exposure::Auto { .. } => self.write_params(queue),
+        }
+    }
+
+    pub fn resize(&mut self, queue: &wgpu::Queue, config: &wgpu::SurfaceConfiguration) {
+        self.size = (config.width, config.height);
+        self.write_params(queue);
+    }
+
+    // The time the next frame's adaptation covers, in seconds
+    pub fn update(&mut self, queue: &wgpu::Queue, delta: f32) {
+        self.delta = delta;
+        self.write_params(queue);
+    }
+
+    // Measures the frame (in auto mode) and draws it to the surface. `hdr` is whichever
+    // target the post-processing ended in, so the bind groups are made every frame.
+    pub fn render(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr: &wgpu::TextureView,
+        surface_view: &wgpu::TextureView,
+    ) {
+        let (bind_group, histogram_bind_group) = self.create_bind_groups(device, hdr);
+        if let Exposure::Auto { .. } = self.exposure {
+            let (width, height) = self.size;
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Auto Exposure Pass"),
+            });
+            compute_pass.set_pipeline(&self.histogram_pipeline);
+            compute_pass.set_bind_group(0, &histogram_bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                width.div_ceil(Self::WORKGROUP_SIZE),
+                height.div_ceil(Self::WORKGROUP_SIZE),
+                1,
+            );
+            compute_pass.set_pipeline(&self.average_pipeline);
+            compute_pass.set_bind_group(0, &self.average_bind_group, &[]);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: surface_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn write_params(&self, queue: &wgpu::Queue) {
+        let Exposure::Auto {
+            min_log_luminance,
+            max_log_luminance,
+            adaptation,
+            compensation,
+        } = self.exposure
+        else {
+            return;
+        };
+        let (width, height) = self.size;
+        let params = HistogramParams {
+            min_log_luminance,
+            log_luminance_range: (max_log_luminance - min_log_luminance).max(0.001),
+            pixel_count: (width * height) as f32,
+            adaptation_rate: adaptation.max(0.0),
+            key_value: Self::KEY_VALUE,
+            compensation,
+            delta: self.delta,
+            _padding: 0.0,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+    }
+
+    fn create_bind_groups(
+        &self,
+        device: &wgpu::Device,
+        hdr: &wgpu::TextureView,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.exposure_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(hdr),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        });
+        let histogram_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.histogram_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.histogram_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("histogram_bind_group"),
+        });
+        (bind_group, histogram_bind_group)
+    }
+}
+
+fn uniform_layout_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn storage_layout_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+    read_only: bool,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn texture_layout_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
+fn create_compute_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    source: &str,
+    label: &str,
+) -> wgpu::ComputePipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(label),
+        layout: Some(
+            &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            }),
+        ),
+        module: &shader,
+        entry_point: "cs_main",
+    })
+}
@@ -1,13 +1,58 @@
+use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
     position: [f32; 3],
     color: [f32; 2],
+    normal: [f32; 3],
+    // w is the handedness, bitangent = cross(normal, tangent) * w
+    tangent: [f32; 4],
+    // up to four joints of the skin, unused while all weights are zero
+    joints: [u16; 4],
+    weights: [f32; 4],
 }
 
 impl Vertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 2] =
-        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2];
+    const ATTRIBS: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![
+        0 => Float32x3, 1 => Float32x2, 2 => Float32x3, 3 => Float32x4, 4 => Uint16x4, 5 => Float32x4
+    ];
+
+    // An unskinned vertex, tangents come from generate_tangents
+    pub fn make(position: [f32; 3], uv: [f32; 2], normal: [f32; 3]) -> Self {
+        Self {
+            position,
+            color: uv,
+            normal,
+            tangent: [0.0; 4],
+            joints: [0; 4],
+            weights: [0.0; 4],
+        }
+    }
+
+    // Weights are normalized so they add up to one
+    pub fn make_skinned(
+        position: [f32; 3],
+        uv: [f32; 2],
+        normal: [f32; 3],
+        joints: [u16; 4],
+        weights: [f32; 4],
+    ) -> Self {
+        let total: f32 = weights.iter().sum();
+        Self {
+            joints,
+            weights: if total > 0.0 {
+                weights.map(|weight| weight / total)
+            } else {
+                weights
+            },
+            ..Self::make(position, uv, normal)
+        }
+    }
+
+    pub fn get_position(&self) -> [f32; 3] {
+        self.position
+    }
 
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
@@ -18,102 +63,199 @@ impl Vertex {
     }
 }
 
+// Tangents are left empty here, run generate_tangents on a copy before uploading
 pub const VERTICES: &[Vertex] = &[
     Vertex {
         position: [-1.0, -1.0, -1.0],
         color: [0.0, 1.0],
+        normal: [0.0, 0.0, -1.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     }, //0
     Vertex {
         position: [1.0, -1.0, -1.0],
         color: [1.0, 1.0],
+        normal: [0.0, 0.0, -1.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     }, //1
     Vertex {
         position: [-1.0, 1.0, -1.0],
         color: [0.0, 0.0],
+        normal: [0.0, 0.0, -1.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     }, //2
     Vertex {
         position: [1.0, 1.0, -1.0],
         color: [1.0, 0.0],
+        normal: [0.0, 0.0, -1.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     }, //3
     Vertex {
         position: [-1.0, -1.0, 1.0],
         color: [1.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     }, //4
     Vertex {
         position: [1.0, -1.0, 1.0],
         color: [0.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     }, //5
     Vertex {
         position: [-1.0, 1.0, 1.0],
         color: [1.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     }, //6
     Vertex {
         position: [1.0, 1.0, 1.0],
         color: [0.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     }, //7
     Vertex {
         position: [-1.0, -1.0, -1.0],
         color: [1.0, 1.0],
+        normal: [-1.0, 0.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     }, //8
     Vertex {
         position: [-1.0, 1.0, -1.0], // 9
         color: [1.0, 0.0],
+        normal: [-1.0, 0.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [-1.0, -1.0, 1.0], // 10
         color: [0.0, 1.0],
+        normal: [-1.0, 0.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [-1.0, 1.0, 1.0], // 11
         color: [0.0, 0.0],
+        normal: [-1.0, 0.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [1.0, -1.0, -1.0], // 12
         color: [1.0, 1.0],
+        normal: [1.0, 0.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [1.0, 1.0, -1.0], // 13
         color: [1.0, 0.0],
+        normal: [1.0, 0.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [1.0, -1.0, 1.0], // 14
         color: [0.0, 1.0],
+        normal: [1.0, 0.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [1.0, 1.0, 1.0], // 15
         color: [0.0, 0.0],
+        normal: [1.0, 0.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [-1.0, -1.0, -1.0], // 16
         color: [0.0, 0.0],
+        normal: [0.0, -1.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [1.0, -1.0, -1.0], // 17
         color: [1.0, 0.0],
+        normal: [0.0, -1.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [-1.0, -1.0, 1.0], // 18
         color: [0.0, 1.0],
+        normal: [0.0, -1.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [1.0, -1.0, 1.0], // 19
         color: [1.0, 1.0],
+        normal: [0.0, -1.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [-1.0, 1.0, -1.0], // 20
         color: [0.0, 1.0],
+        normal: [0.0, 1.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [1.0, 1.0, -1.0], // 21
         color: [1.0, 1.0],
+        normal: [0.0, 1.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [-1.0, 1.0, 1.0], // 22
         color: [0.0, 0.0],
+        normal: [0.0, 1.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
     Vertex {
         position: [1.0, 1.0, 1.0], // 23
         color: [1.0, 0.0],
+        normal: [0.0, 1.0, 0.0],
+        tangent: [0.0, 0.0, 0.0, 0.0],
+        joints: [0, 0, 0, 0],
+        weights: [0.0, 0.0, 0.0, 0.0],
     },
 ];
 
@@ -121,3 +263,211 @@ pub const INDICES: &[u16] = &[
     0, 2, 1, 2, 3, 1, 4, 5, 7, 4, 7, 6, 8, 10, 9, 10, 11, 9, 12, 13, 15, 12, 15, 14, 16, 17, 18,
     18, 17, 19, 20, 23, 21, 20, 22, 23,
 ];
+
+// Per-vertex tangent frames built the way MikkTSpace builds them, though not bit for bit the
+// same: every triangle's tangent is projected onto each corner's normal plane, normalized and
+// weighted by the corner angle, so a triangle's UV area doesn't change its say. The handedness
+// in w flips the bitangent when the UV mapping is mirrored.
+pub fn generate_tangents<I: Copy + Into<u32>>(vertices: &mut [Vertex], indices: &[I]) {
+    let mut tangents = vec![Vector3::zero(); vertices.len()];
+    let mut bitangents = vec![Vector3::zero(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let ids = [
+            triangle[0].into() as usize,
+            triangle[1].into() as usize,
+            triangle[2].into() as usize,
+        ];
+        let p = ids.map(|i| Vector3::from(vertices[i].position));
+        let uv = ids.map(|i| Vector2::from(vertices[i].color));
+
+        let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+        let (d1, d2) = (uv[1] - uv[0], uv[2] - uv[0]);
+        let det = d1.x * d2.y - d2.x * d1.y;
+        // relative to the UV edges, so only the triangle's UV shape counts and not its size
+        if det.abs() <= f32::EPSILON * d1.magnitude() * d2.magnitude() {
+            continue;
+        }
+        // only the directions are used, the sign of det keeps them the right way round
+        let tangent = (e1 * d2.y - e2 * d1.y) * det.signum();
+        let bitangent = (e2 * d1.x - e1 * d2.x) * det.signum();
+
+        for corner in 0..3 {
+            let a = p[(corner + 1) % 3] - p[corner];
+            let b = p[(corner + 2) % 3] - p[corner];
+            if a.magnitude2() == 0.0 || b.magnitude2() == 0.0 {
+                continue;
+            }
+            let angle = a.normalize().dot(b.normalize()).clamp(-1.0, 1.0).acos();
+            let normal = Vector3::from(vertices[ids[corner]].normal);
+            let project = |v: Vector3<f32>| {
+                let v = v - normal * normal.dot(v);
+                if v.magnitude2() > 0.0 {
+                    v.normalize()
+                } else {
+                    v
+                }
+            };
+            tangents[ids[corner]] += project(tangent) * angle;
+            bitangents[ids[corner]] += project(bitangent) * angle;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = Vector3::from(vertex.normal);
+        let mut tangent = tangents[i] - normal * normal.dot(tangents[i]);
+        if tangent.magnitude2() < f32::EPSILON {
+            // no usable UVs, any direction perpendicular to the normal will do
+            let axis = if normal.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            tangent = axis - normal * normal.dot(axis);
+        }
+        let tangent = tangent.normalize();
+        let sign = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        vertex.tangent = tangent.extend(sign).into();
+    }
+}
+
+// A square bar standing on the origin, bound to a chain of joints spaced evenly up its
+// height. Each ring of vertices is shared between the two closest joints.
+pub fn generate_skinned_bar(
+    width: f32,
+    height: f32,
+    rings: u16,
+    joint_count: u16,
+) -> (Vec<Vertex>, Vec<u16>) {
+    let half = width * 0.5;
+    let joint_spacing = height / joint_count as f32;
+    let skin = |y: f32| {
+        let position = (y / joint_spacing - 0.5).max(0.0);
+        let lower = (position.floor() as u16).min(joint_count - 1);
+        let upper = (lower + 1).min(joint_count - 1);
+        let t = (position - lower as f32).min(1.0);
+        ([lower, upper, 0, 0], [1.0 - t, t, 0.0, 0.0])
+    };
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    // outward normal and the horizontal direction along the face
+    let faces = [
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0]),
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+    ];
+    for (normal, across) in faces {
+        let first = vertices.len() as u16;
+        for ring in 0..=rings {
+            let v = ring as f32 / rings as f32;
+            let y = v * height;
+            let (joints, weights) = skin(y);
+            for side in [-1.0f32, 1.0] {
+                let position = [
+                    (normal[0] + across[0] * side) * half,
+                    y,
+                    (normal[2] + across[2] * side) * half,
+                ];
+                let uv = [(side + 1.0) * 0.5, 1.0 - v];
+                vertices.push(Vertex::make_skinned(position, uv, normal, joints, weights));
+            }
+        }
+        for ring in 0..rings {
+            let a = first + ring * 2;
+            indices.extend_from_slice(&[a, a + 1, a + 3, a, a + 3, a + 2]);
+        }
+    }
+
+    // top cap, rides on the last joint
+    let first = vertices.len() as u16;
+    let (joints, weights) = skin(height);
+    for (x, z, u, v) in [
+        (-half, half, 0.0, 1.0),
+        (half, half, 1.0, 1.0),
+        (half, -half, 1.0, 0.0),
+        (-half, -half, 0.0, 0.0),
+    ] {
+        vertices.push(Vertex::make_skinned(
+            [x, height, z],
+            [u, v],
+            [0.0, 1.0, 0.0],
+            joints,
+            weights,
+        ));
+    }
+    indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+
+    generate_tangents(&mut vertices, &indices);
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    // A unit quad facing +z, uv maps each corner's x and y through it
+    fn quad(uv: impl Fn(f32, f32) -> [f32; 2]) -> Vec<Vertex> {
+        let mut vertices: Vec<Vertex> = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]
+            .iter()
+            .map(|&(x, y)| Vertex::make([x, y, 0.0], uv(x, y), [0.0, 0.0, 1.0]))
+            .collect();
+        generate_tangents(&mut vertices, &[0u16, 1, 2, 0, 2, 3]);
+        vertices
+    }
+
+    #[test]
+    fn cube_tangents_are_unit_and_orthogonal() {
+        let mut vertices = VERTICES.to_vec();
+        generate_tangents(&mut vertices, INDICES);
+        for (i, vertex) in vertices.iter().enumerate() {
+            let tangent = Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+            assert_close(tangent.magnitude(), 1.0);
+            assert_close(tangent.dot(vertex.normal.into()), 0.0);
+            assert!(vertex.tangent[3].abs() == 1.0, "vertex {}", i);
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_flip_the_handedness() {
+        for vertex in quad(|x, y| [x, y]) {
+            assert_eq!(vertex.tangent, [1.0, 0.0, 0.0, 1.0]);
+        }
+        for vertex in quad(|x, y| [1.0 - x, y]) {
+            assert_eq!(vertex.tangent, [-1.0, 0.0, 0.0, -1.0]);
+        }
+    }
+
+    #[test]
+    fn tiny_uv_triangles_still_count() {
+        // a small tile of an atlas, its UV area is far below f32::EPSILON
+        for vertex in quad(|x, y| [0.5 + x * 1e-4, 0.25 + y * 1e-4]) {
+            assert_eq!(vertex.tangent, [1.0, 0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn uv_area_does_not_weight_the_tangent() {
+        // two triangles sharing the quad's corners 0 and 2, one with a much smaller UV area
+        let mut vertices = quad(|x, y| [x, y]);
+        vertices[3].color = [0.0, 0.01];
+        vertices[1].color = [1.0, 0.0];
+        generate_tangents(&mut vertices, &[0u16, 1, 2, 0, 2, 3]);
+        let tangent = vertices[0].tangent;
+        assert_close(
+            Vector3::new(tangent[0], tangent[1], tangent[2]).magnitude(),
+            1.0,
+        );
+        // the first triangle alone points along +x, the second doesn't drown it out
+        assert!(tangent[0] > 0.5, "{:?}", tangent);
+    }
+}
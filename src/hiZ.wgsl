@@ -0,0 +1,44 @@
+// Builds the Hi-Z pyramid, each texel the farthest depth under it. Level 0 is copied from the
+// depth buffer, every other level reduces the one above.
+@group(0) @binding(0)
+var depth: texture_depth_2d;
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(2)
+var destination: texture_storage_2d<r32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn cs_copy(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(destination);
+    if (id.x >= u32(size.x) || id.y >= u32(size.y)) {
+        return;
+    }
+    let value = textureLoad(depth, vec2<i32>(id.xy), 0);
+    textureStore(destination, vec2<i32>(id.xy), vec4<f32>(value, 0.0, 0.0, 0.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(destination);
+    if (id.x >= u32(size.x) || id.y >= u32(size.y)) {
+        return;
+    }
+    let source_size = textureDimensions(source, 0);
+    let start = vec2<i32>(id.xy) * 2;
+    // with an odd size the last texel also covers the row or column left over
+    var end = min(start + vec2<i32>(1), source_size - vec2<i32>(1));
+    if (i32(id.x) == size.x - 1) {
+        end.x = source_size.x - 1;
+    }
+    if (i32(id.y) == size.y - 1) {
+        end.y = source_size.y - 1;
+    }
+
+    var farthest = 0.0;
+    for (var y = start.y; y <= end.y; y = y + 1) {
+        for (var x = start.x; x <= end.x; x = x + 1) {
+            farthest = max(farthest, textureLoad(source, vec2<i32>(x, y), 0).r);
+        }
+    }
+    textureStore(destination, vec2<i32>(id.xy), vec4<f32>(farthest, 0.0, 0.0, 0.0));
+}
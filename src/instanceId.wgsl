@@ -0,0 +1,93 @@
+// Writes which instance covers each pixel, 0 where nothing does. Instances of the set are their
+// buffer index + 1, the GPU animated grid sets ARRAY_BIT on its own index. Skinned like
+// shadow.wgsl, so the ids line up with the animated meshes.
+struct VertexInput{
+    @location(0) vertex_position: vec3<f32>,
+    @location(1) tex_coord: vec2<f32>,
+    @location(4) joints: vec4<u32>,
+    @location(5) weights: vec4<f32>,
+}
+
+struct InstanceInput{
+    @location(6) model_matrix_0: vec4<f32>,
+    @location(7) model_matrix_1: vec4<f32>,
+    @location(8) model_matrix_2: vec4<f32>,
+    @location(9) model_matrix_3: vec4<f32>,
+    @location(10) normal_matrix_0: vec3<f32>,
+    @location(11) normal_matrix_1: vec3<f32>,
+    @location(12) normal_matrix_2: vec3<f32>,
+    @location(13) tint: vec4<f32>,
+    // x: material index, y: first joint of the skin in the palette
+    @location(14) indices: vec2<u32>,
+    @location(15) custom: vec4<f32>,
+}
+
+struct VertexOutput{
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> camera : mat4x4<f32>;
+@group(0) @binding(1)
+var<storage, read> joint_palette: array<mat4x4<f32>>;
+
+let ARRAY_BIT: u32 = 0x80000000u;
+
+// Weighted sum of the vertex's joint matrices, vertices without weights aren't skinned
+fn skin_matrix(joints: vec4<u32>, weights: vec4<f32>, joint_offset: u32) -> mat4x4<f32> {
+    if (dot(weights, vec4<f32>(1.0)) == 0.0) {
+        return mat4x4<f32>(
+            vec4<f32>(1.0, 0.0, 0.0, 0.0),
+            vec4<f32>(0.0, 1.0, 0.0, 0.0),
+            vec4<f32>(0.0, 0.0, 1.0, 0.0),
+            vec4<f32>(0.0, 0.0, 0.0, 1.0),
+        );
+    }
+    return joint_palette[joint_offset + joints.x] * weights.x
+        + joint_palette[joint_offset + joints.y] * weights.y
+        + joint_palette[joint_offset + joints.z] * weights.z
+        + joint_palette[joint_offset + joints.w] * weights.w;
+}
+
+fn project(model: VertexInput, instance: InstanceInput) -> vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let skin = skin_matrix(model.joints, model.weights, instance.indices.y);
+    return camera * model_matrix * skin * vec4<f32>(model.vertex_position, 1.0);
+}
+
+// instance_index counts from the draw's first instance, so it's the index in the buffer as long
+// as the whole instance buffer is bound
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = project(model, instance);
+    out.id = instance_index + 1u;
+    return out;
+}
+
+@vertex
+fn vs_array(
+    model: VertexInput,
+    instance: InstanceInput,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = project(model, instance);
+    out.id = instance_index | ARRAY_BIT;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) u32 {
+    return in.id;
+}
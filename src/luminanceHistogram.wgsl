@@ -0,0 +1,47 @@
+// Counts the pixels of the HDR scene colour per log2 luminance bin. Each workgroup
+// builds its own histogram in shared memory and adds it to the global one at the end.
+struct Params{
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    pixel_count: f32,
+    // fraction of the way to the new average covered each frame
+    adaptation_rate: f32,
+    // luminance the average is mapped to
+    key_value: f32,
+    // in stops
+    compensation: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var hdr: texture_2d<f32>;
+@group(0) @binding(2)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+
+// Bin 0 holds pixels too dark to matter, the rest span [min, min + range] in log2 space
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if (luminance < 0.0001) {
+        return 0u;
+    }
+    let t = clamp((log2(luminance) - params.min_log_luminance) / params.log_luminance_range, 0.0, 1.0);
+    return u32(t * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) index: u32) {
+    atomicStore(&local_bins[index], 0u);
+    workgroupBarrier();
+
+    let size = textureDimensions(hdr);
+    if (id.x < u32(size.x) && id.y < u32(size.y)) {
+        let color = textureLoad(hdr, vec2<i32>(id.xy), 0).rgb;
+        atomicAdd(&local_bins[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[index], atomicLoad(&local_bins[index]));
+}
@@ -0,0 +1,87 @@
+// Camera facing quads, one instance per particle slot. Dead slots collapse to nothing.
+struct Camera{
+    view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    inv_view_proj: mat4x4<f32>,
+}
+
+struct Particle{
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+}
+
+struct Emitter{
+    position: vec3<f32>,
+    spawn_count: u32,
+    direction: vec3<f32>,
+    cone_cos: f32,
+    gravity: vec3<f32>,
+    delta: f32,
+    start_color: vec4<f32>,
+    end_color: vec4<f32>,
+    lifetime: vec2<f32>,
+    speed: vec2<f32>,
+    start_size: f32,
+    end_size: f32,
+    seed: u32,
+    max_particles: u32,
+    camera_right: vec4<f32>,
+    camera_up: vec4<f32>,
+}
+
+struct VertexOutput{
+    @builtin(position) clip_position: vec4<f32>,
+    // -1 to 1 across the quad
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera : Camera;
+
+@group(1) @binding(0)
+var<uniform> emitter: Emitter;
+@group(1) @binding(1)
+var<storage, read> particles: array<Particle>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, @builtin(instance_index) instance_index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+    let particle = particles[instance_index];
+
+    var out: VertexOutput;
+    out.corner = corner;
+    if (particle.age >= particle.lifetime) {
+        out.clip_position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
+        out.color = vec4<f32>(0.0);
+        return out;
+    }
+    // colour and size over life
+    let t = particle.age / particle.lifetime;
+    let size = mix(emitter.start_size, emitter.end_size, t);
+    out.color = mix(emitter.start_color, emitter.end_color, t);
+
+    let offset = (emitter.camera_right.xyz * corner.x + emitter.camera_up.xyz * corner.y) * 0.5 * size;
+    out.clip_position = camera.view_proj * vec4<f32>(particle.position + offset, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // round, soft edged sprite
+    let falloff = 1.0 - smoothstep(0.5, 1.0, length(in.corner));
+    if (falloff <= 0.0) {
+        discard;
+    }
+    return vec4<f32>(in.color.rgb, in.color.a * falloff);
+}
@@ -0,0 +1,87 @@
+// Moves the live particles of one emitter and respawns dead ones, as many as the emitter wants
+// this frame. Dead slots race for the spawns through an atomic counter.
+struct Particle{
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    // a particle is dead once its age reaches its lifetime
+    lifetime: f32,
+}
+
+struct Emitter{
+    position: vec3<f32>,
+    spawn_count: u32,
+    direction: vec3<f32>,
+    // cosine of the cone's half angle
+    cone_cos: f32,
+    gravity: vec3<f32>,
+    delta: f32,
+    start_color: vec4<f32>,
+    end_color: vec4<f32>,
+    lifetime: vec2<f32>,
+    speed: vec2<f32>,
+    start_size: f32,
+    end_size: f32,
+    seed: u32,
+    max_particles: u32,
+    camera_right: vec4<f32>,
+    camera_up: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> emitter: Emitter;
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+@group(0) @binding(2)
+var<storage, read_write> spawned: atomic<u32>;
+
+// PCG hash
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// in [0, 1), advances the seed
+fn random(seed: ptr<function, u32>) -> f32 {
+    *seed = hash(*seed);
+    return f32(*seed >> 8u) / 16777216.0;
+}
+
+// Uniform over the cap of the cone around the emitter direction
+fn cone_direction(seed: ptr<function, u32>) -> vec3<f32> {
+    let cos_theta = mix(emitter.cone_cos, 1.0, random(seed));
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let phi = 6.2831853 * random(seed);
+
+    let w = normalize(emitter.direction);
+    var helper = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(w.y) > 0.99) {
+        helper = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let u = normalize(cross(helper, w));
+    let v = cross(w, u);
+    return (u * cos(phi) + v * sin(phi)) * sin_theta + w * cos_theta;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= emitter.max_particles) {
+        return;
+    }
+    var particle = particles[id.x];
+    if (particle.age < particle.lifetime) {
+        particle.velocity += emitter.gravity * emitter.delta;
+        particle.position += particle.velocity * emitter.delta;
+        particle.age += emitter.delta;
+    } else if (atomicAdd(&spawned, 1u) < emitter.spawn_count) {
+        var seed = hash(id.x ^ hash(emitter.seed));
+        particle.position = emitter.position;
+        particle.velocity = cone_direction(&seed) * mix(emitter.speed.x, emitter.speed.y, random(&seed));
+        particle.age = 0.0;
+        particle.lifetime = mix(emitter.lifetime.x, emitter.lifetime.y, random(&seed));
+    } else {
+        return;
+    }
+    particles[id.x] = particle;
+}
@@ -2,26 +2,74 @@
 struct VertexInput{
     @location(0) vertex_position: vec3<f32>,
     @location(1) tex_coord: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    // w is the handedness of the bitangent
+    @location(3) tangent: vec4<f32>,
+    @location(4) joints: vec4<u32>,
+    @location(5) weights: vec4<f32>,
 }
 
 struct InstanceInput{
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
+    @location(6) model_matrix_0: vec4<f32>,
+    @location(7) model_matrix_1: vec4<f32>,
+    @location(8) model_matrix_2: vec4<f32>,
+    @location(9) model_matrix_3: vec4<f32>,
+    @location(10) normal_matrix_0: vec3<f32>,
+    @location(11) normal_matrix_1: vec3<f32>,
+    @location(12) normal_matrix_2: vec3<f32>,
+    @location(13) tint: vec4<f32>,
+    // x: material index, y: first joint of the skin in the palette
+    @location(14) indices: vec2<u32>,
+    @location(15) custom: vec4<f32>,
 }
 
 struct VertexOutput{
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coord: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
+    @location(3) world_tangent: vec3<f32>,
+    @location(4) world_bitangent: vec3<f32>,
+    @location(5) tint: vec4<f32>,
 }
 
 struct PositionMatrix{
     view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+}
+
+struct Light{
+    direction: vec4<f32>,
+    color: vec4<f32>,
 }
 
 @group(1) @binding(0)
 var<uniform> camera : PositionMatrix;
+// joint matrices of every skin
+@group(1) @binding(1)
+var<storage, read> joint_palette: array<mat4x4<f32>>;
+
+@group(2) @binding(0)
+var<uniform> light : Light;
+// screen space ambient occlusion, one texel per pixel
+@group(2) @binding(1)
+var t_ambient_occlusion: texture_2d<f32>;
+
+// Weighted sum of the vertex's joint matrices, vertices without weights aren't skinned
+fn skin_matrix(joints: vec4<u32>, weights: vec4<f32>, joint_offset: u32) -> mat4x4<f32> {
+    if (dot(weights, vec4<f32>(1.0)) == 0.0) {
+        return mat4x4<f32>(
+            vec4<f32>(1.0, 0.0, 0.0, 0.0),
+            vec4<f32>(0.0, 1.0, 0.0, 0.0),
+            vec4<f32>(0.0, 0.0, 1.0, 0.0),
+            vec4<f32>(0.0, 0.0, 0.0, 1.0),
+        );
+    }
+    return joint_palette[joint_offset + joints.x] * weights.x
+        + joint_palette[joint_offset + joints.y] * weights.y
+        + joint_palette[joint_offset + joints.z] * weights.z
+        + joint_palette[joint_offset + joints.w] * weights.w;
+}
 
 @vertex
 fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput{
@@ -33,19 +81,148 @@ fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput{
         instance.model_matrix_2,
         instance.model_matrix_3,
     );
-    
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.vertex_position, 1.0);
+    let skin = skin_matrix(model.joints, model.weights, instance.indices.y);
+    let world_position = model_matrix * skin * vec4<f32>(model.vertex_position, 1.0);
+    // joints only rotate, so the skin's upper 3x3 is fine for directions
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    ) * mat3x3<f32>(skin[0].xyz, skin[1].xyz, skin[2].xyz);
+
+    out.clip_position = camera.view_proj * world_position;
     out.tex_coord = model.tex_coord;
+    out.world_position = world_position.xyz;
+    out.world_normal = normalize(normal_matrix * model.normal);
+    out.world_tangent = normalize(normal_matrix * model.tangent.xyz);
+    out.world_bitangent = cross(out.world_normal, out.world_tangent) * model.tangent.w;
+    out.tint = instance.tint;
 
     return out;
 }
 
+struct Material{
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec4<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+}
+
 @group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_albedo: texture_2d<f32>;
 @group(0) @binding(1)
-var s_diffuse: sampler;
+var s_albedo: sampler;
+@group(0) @binding(2)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(3)
+var s_metallic_roughness: sampler;
+@group(0) @binding(4)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(5)
+var s_occlusion: sampler;
+@group(0) @binding(6)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(7)
+var s_emissive: sampler;
+@group(0) @binding(8)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(9)
+var s_normal: sampler;
+@group(0) @binding(10)
+var<uniform> material: Material;
+
+@group(3) @binding(0)
+var t_irradiance: texture_cube<f32>;
+@group(3) @binding(1)
+var t_prefiltered: texture_cube<f32>;
+@group(3) @binding(2)
+var s_environment: sampler;
+@group(3) @binding(3)
+var t_brdf_lut: texture_2d<f32>;
+@group(3) @binding(4)
+var s_brdf_lut: sampler;
+
+let PI: f32 = 3.14159265359;
+// last mip of the prefiltered environment, see EnvironmentMap::PREFILTERED_MIP_COUNT
+let MAX_REFLECTION_LOD: f32 = 4.0;
+
+// Trowbridge-Reitz GGX normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith's method with the Schlick-GGX approximation, k remapped for direct lighting
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let ggx_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let ggx_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return ggx_v * ggx_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// rough surfaces reflect less at grazing angles, used for the ambient term
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>{
-    return textureSample(t_diffuse, s_diffuse, in.tex_coord);
-}
\ No newline at end of file
+    let albedo_sample = textureSample(t_albedo, s_albedo, in.tex_coord) * material.base_color_factor * in.tint;
+    let mr_sample = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coord);
+    let occlusion_sample = textureSample(t_occlusion, s_occlusion, in.tex_coord).r;
+    let emissive_sample = textureSample(t_emissive, s_emissive, in.tex_coord).rgb;
+
+    let albedo = albedo_sample.rgb;
+    let metallic = clamp(mr_sample.b * material.metallic_factor, 0.0, 1.0);
+    // keep a little roughness so the GGX lobe never collapses to a point
+    let roughness = clamp(mr_sample.g * material.roughness_factor, 0.04, 1.0);
+    let occlusion = mix(1.0, occlusion_sample, material.emissive_factor.w);
+    let emissive = emissive_sample * material.emissive_factor.rgb;
+
+    let normal_sample = textureSample(t_normal, s_normal, in.tex_coord).xyz * 2.0 - 1.0;
+    let tangent_normal = normal_sample * vec3<f32>(material.normal_scale, material.normal_scale, 1.0);
+    let tbn = mat3x3<f32>(normalize(in.world_tangent), normalize(in.world_bitangent), normalize(in.world_normal));
+    let n = normalize(tbn * tangent_normal);
+    let v = normalize(camera.view_position.xyz - in.world_position);
+    let l = normalize(-light.direction.xyz);
+    let h = normalize(v + l);
+
+    let n_dot_v = max(dot(n, v), 0.0001);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let n_dot_h = max(dot(n, h), 0.0);
+    let h_dot_v = max(dot(h, v), 0.0);
+
+    // dielectrics reflect ~4% at normal incidence, metals tint the reflection with albedo
+    let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let f = fresnel_schlick(h_dot_v, f0);
+    let d = distribution_ggx(n_dot_h, roughness);
+    let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+    let specular = d * g * f / (4.0 * n_dot_v * max(n_dot_l, 0.0001));
+
+    // whatever is not reflected is refracted, and metals absorb the refracted part
+    let k_d = (vec3<f32>(1.0) - f) * (1.0 - metallic);
+    let diffuse = k_d * albedo / PI;
+
+    let direct = (diffuse + specular) * light.color.rgb * n_dot_l;
+
+    // image based ambient lighting, split-sum approximation for the specular part
+    let f_ambient = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let k_d_ambient = (vec3<f32>(1.0) - f_ambient) * (1.0 - metallic);
+    let irradiance = textureSample(t_irradiance, s_environment, n).rgb;
+    let r = reflect(-v, n);
+    let prefiltered = textureSampleLevel(t_prefiltered, s_environment, r, roughness * MAX_REFLECTION_LOD).rgb;
+    let brdf = textureSample(t_brdf_lut, s_brdf_lut, vec2<f32>(n_dot_v, roughness)).rg;
+    let specular_ambient = prefiltered * (f_ambient * brdf.x + brdf.y);
+    let screen_occlusion = textureLoad(t_ambient_occlusion, vec2<i32>(in.clip_position.xy), 0).r;
+    let ambient = (k_d_ambient * irradiance * albedo + specular_ambient) * occlusion * screen_occlusion;
+
+    return vec4<f32>(ambient + direct + emissive, albedo_sample.a);
+}
@@ -2,17 +2,44 @@
 struct VertexInput{
     @location(0) vertex_position: vec3<f32>,
     @location(1) tex_coord: vec2<f32>,
+    @location(4) joints: vec4<u32>,
+    @location(5) weights: vec4<f32>,
 }
 
 struct InstanceInput{
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
+    @location(6) model_matrix_0: vec4<f32>,
+    @location(7) model_matrix_1: vec4<f32>,
+    @location(8) model_matrix_2: vec4<f32>,
+    @location(9) model_matrix_3: vec4<f32>,
+    @location(10) normal_matrix_0: vec3<f32>,
+    @location(11) normal_matrix_1: vec3<f32>,
+    @location(12) normal_matrix_2: vec3<f32>,
+    @location(13) tint: vec4<f32>,
+    // x: material index, y: first joint of the skin in the palette
+    @location(14) indices: vec2<u32>,
+    @location(15) custom: vec4<f32>,
 }
 
 @group(0) @binding(0)
 var<uniform> camera : mat4x4<f32>;
+@group(0) @binding(1)
+var<storage, read> joint_palette: array<mat4x4<f32>>;
+
+// Weighted sum of the vertex's joint matrices, vertices without weights aren't skinned
+fn skin_matrix(joints: vec4<u32>, weights: vec4<f32>, joint_offset: u32) -> mat4x4<f32> {
+    if (dot(weights, vec4<f32>(1.0)) == 0.0) {
+        return mat4x4<f32>(
+            vec4<f32>(1.0, 0.0, 0.0, 0.0),
+            vec4<f32>(0.0, 1.0, 0.0, 0.0),
+            vec4<f32>(0.0, 0.0, 1.0, 0.0),
+            vec4<f32>(0.0, 0.0, 0.0, 1.0),
+        );
+    }
+    return joint_palette[joint_offset + joints.x] * weights.x
+        + joint_palette[joint_offset + joints.y] * weights.y
+        + joint_palette[joint_offset + joints.z] * weights.z
+        + joint_palette[joint_offset + joints.w] * weights.w;
+}
 
 @vertex
 fn vs_main(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32>{
@@ -24,5 +51,6 @@ fn vs_main(model: VertexInput, instance: InstanceInput) -> @builtin(position) ve
         instance.model_matrix_3,
     );
     
-    return camera * model_matrix * vec4<f32>(model.vertex_position, 1.0);
+    let skin = skin_matrix(model.joints, model.weights, instance.indices.y);
+    return camera * model_matrix * skin * vec4<f32>(model.vertex_position, 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,98 @@
+// Full screen triangle drawn at the far plane, behind everything already in the depth buffer
+struct Camera{
+    view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    inv_view_proj: mat4x4<f32>,
+}
+
+struct Sky{
+    zenith: vec4<f32>,
+    horizon: vec4<f32>,
+    ground: vec4<f32>,
+    // rayleigh coefficients in 1e-6 / m, w is the mie coefficient in 1e-5 / m
+    scattering: vec4<f32>,
+    // direction towards the sun, w is the sun intensity
+    sun: vec4<f32>,
+    // 0: cube map, 1: gradient, 2: atmosphere
+    mode: u32,
+}
+
+struct VertexOutput{
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera : Camera;
+
+@group(1) @binding(0)
+var<uniform> sky : Sky;
+@group(1) @binding(1)
+var t_sky: texture_cube<f32>;
+@group(1) @binding(2)
+var s_sky: sampler;
+
+let PI: f32 = 3.14159265359;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput{
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.ndc = uv * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+fn gradient(dir: vec3<f32>) -> vec3<f32> {
+    if (dir.y >= 0.0) {
+        return mix(sky.horizon.rgb, sky.zenith.rgb, sqrt(dir.y));
+    }
+    return mix(sky.horizon.rgb, sky.ground.rgb, sqrt(-dir.y));
+}
+
+// relative air mass, Kasten-Young approximation
+fn air_mass(cos_zenith: f32) -> f32 {
+    let zenith_deg = degrees(acos(clamp(cos_zenith, 0.0, 1.0)));
+    return 1.0 / (max(cos_zenith, 0.0) + 0.50572 * pow(96.07995 - zenith_deg, -1.6364));
+}
+
+fn atmosphere(dir: vec3<f32>) -> vec3<f32> {
+    let beta_r = sky.scattering.rgb * 1e-6;
+    let beta_m = vec3<f32>(sky.scattering.w * 1e-5);
+    // optical thickness of the whole atmosphere towards the zenith, scale heights 8km / 1.2km
+    let tau_r = beta_r * 8000.0;
+    let tau_m = beta_m * 1200.0;
+
+    let sun = normalize(sky.sun.xyz);
+    let sun_transmittance = exp(-(tau_r + tau_m) * air_mass(sun.y));
+    let view_transmittance = exp(-(tau_r + tau_m) * air_mass(dir.y));
+
+    let mu = dot(dir, sun);
+    let phase_r = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+    let g = 0.76;
+    let phase_m = 3.0 / (8.0 * PI) * ((1.0 - g * g) * (1.0 + mu * mu)) / ((2.0 + g * g) * pow(1.0 + g * g - 2.0 * g * mu, 1.5));
+
+    let inscatter = sky.sun.w * sun_transmittance * (tau_r * phase_r + tau_m * phase_m) / (tau_r + tau_m) * (1.0 - view_transmittance);
+    let sun_disk = sky.sun.w * sun_transmittance * view_transmittance * smoothstep(0.9998, 0.99995, mu);
+
+    var color = inscatter + sun_disk;
+    if (dir.y < 0.0) {
+        // no real ground, fade the horizon colour into darkness
+        color = inscatter * mix(1.0, 0.1, sqrt(-dir.y));
+    }
+    return color;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>{
+    let world = camera.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let dir = normalize(world.xyz / world.w - camera.view_position.xyz);
+
+    var color: vec3<f32>;
+    switch sky.mode {
+        case 0u: { color = textureSample(t_sky, s_sky, dir).rgb; }
+        case 1u: { color = gradient(dir); }
+        default: { color = atmosphere(dir); }
+    }
+    return vec4<f32>(color, 1.0);
+}
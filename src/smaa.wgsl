@@ -0,0 +1,192 @@
+// Morphological anti-aliasing in the three passes of SMAA 1x: luma edge detection with local
+// contrast adaptation, blending weights from the edge patterns, and neighbourhood blending.
+// Only orthogonal patterns are handled, and the coverage of the revectorised edge is
+// integrated directly instead of being read from the precomputed area and search textures.
+struct VertexOutput{
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+// the frame for the edge and blending passes, the edges for the weight pass
+@group(0) @binding(0)
+var t_src: texture_2d<f32>;
+// the blending weights for the blending pass, unused otherwise
+@group(0) @binding(1)
+var t_aux: texture_2d<f32>;
+
+let THRESHOLD: f32 = 0.05;
+// edges much weaker than the strongest one around the pixel are dropped
+let LOCAL_CONTRAST_FACTOR: f32 = 2.0;
+let MAX_SEARCH: i32 = 16;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput{
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+fn load_src(coords: vec2<i32>) -> vec4<f32> {
+    let size = textureDimensions(t_src);
+    return textureLoad(t_src, clamp(coords, vec2<i32>(0), size - 1), 0);
+}
+
+// The frame is still HDR, so luma is compressed into [0, 1) before comparing
+fn luma_at(coords: vec2<i32>) -> f32 {
+    let l = dot(load_src(coords).rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+    return sqrt(l / (1.0 + l));
+}
+
+// r: edge on the left of the pixel, g: edge on its top
+@fragment
+fn fs_edges(in: VertexOutput) -> @location(0) vec4<f32>{
+    let c = vec2<i32>(in.clip_position.xy);
+    let l = luma_at(c);
+    let delta = abs(vec2<f32>(l - luma_at(c - vec2<i32>(1, 0)), l - luma_at(c - vec2<i32>(0, 1))));
+    var edges = step(vec2<f32>(THRESHOLD), delta);
+    if (edges.x + edges.y == 0.0) {
+        return vec4<f32>(0.0);
+    }
+
+    let delta_right = abs(l - luma_at(c + vec2<i32>(1, 0)));
+    let delta_bottom = abs(l - luma_at(c + vec2<i32>(0, 1)));
+    let delta_left_left = abs(luma_at(c - vec2<i32>(1, 0)) - luma_at(c - vec2<i32>(2, 0)));
+    let delta_top_top = abs(luma_at(c - vec2<i32>(0, 1)) - luma_at(c - vec2<i32>(0, 2)));
+    let max_delta = max(
+        max(max(delta.x, delta.y), max(delta_right, delta_bottom)),
+        max(delta_left_left, delta_top_top),
+    );
+    edges = edges * step(vec2<f32>(max_delta), LOCAL_CONTRAST_FACTOR * delta);
+    return vec4<f32>(edges, 0.0, 0.0);
+}
+
+fn edge_at(coords: vec2<i32>) -> vec2<f32> {
+    return load_src(coords).rg;
+}
+
+// Height of the revectorised line at one end of an edge run, half a pixel towards the side
+// the crossing edge is on. No crossing or crossings on both sides leave the end flat.
+fn crossing_height(positive_side: f32, negative_side: f32) -> f32 {
+    if (positive_side > 0.5 && negative_side < 0.5) {
+        return 0.5;
+    } else if (negative_side > 0.5 && positive_side < 0.5) {
+        return -0.5;
+    }
+    return 0.0;
+}
+
+// Integral of a line from (start, start_height) to (end, end_height) over [pixel, pixel + 1]
+fn segment_area(start: f32, end: f32, start_height: f32, end_height: f32, pixel: f32) -> f32 {
+    let low = max(start, pixel);
+    let high = min(end, pixel + 1.0);
+    if (high <= low) {
+        return 0.0;
+    }
+    let slope = (end_height - start_height) / (end - start);
+    let low_height = start_height + slope * (low - start);
+    let high_height = start_height + slope * (high - start);
+    return (low_height + high_height) * 0.5 * (high - low);
+}
+
+// The run is `distance_1 + distance_2 + 1` pixels long with this pixel `distance_1` from its
+// start. The line goes from each end's height to the middle of the run. Returns the area on
+// the positive side of the edge and on the negative side.
+fn coverage(distance_1: i32, distance_2: i32, height_1: f32, height_2: f32) -> vec2<f32> {
+    let length = f32(distance_1 + distance_2 + 1);
+    let pixel = f32(distance_1);
+    let area_1 = segment_area(0.0, length * 0.5, height_1, 0.0, pixel);
+    let area_2 = segment_area(length * 0.5, length, 0.0, height_2, pixel);
+    return vec2<f32>(max(area_1, 0.0) + max(area_2, 0.0), max(-area_1, 0.0) + max(-area_2, 0.0));
+}
+
+// r: how much this pixel takes from the one above, g: how much the one above takes from it,
+// b and a: the same for the pixel on the left
+@fragment
+fn fs_weights(in: VertexOutput) -> @location(0) vec4<f32>{
+    let c = vec2<i32>(in.clip_position.xy);
+    let edges = edge_at(c);
+    var weights = vec4<f32>(0.0);
+
+    if (edges.g > 0.5) {
+        var left = 0;
+        for (var i = 1; i <= MAX_SEARCH; i = i + 1) {
+            if (edge_at(c - vec2<i32>(i, 0)).g < 0.5) {
+                break;
+            }
+            left = i;
+        }
+        var right = 0;
+        for (var i = 1; i <= MAX_SEARCH; i = i + 1) {
+            if (edge_at(c + vec2<i32>(i, 0)).g < 0.5) {
+                break;
+            }
+            right = i;
+        }
+        // crossing edges are the left edges at both ends, in the row above or this one
+        let start = vec2<i32>(c.x - left, c.y);
+        let end = vec2<i32>(c.x + right + 1, c.y);
+        let height_1 = crossing_height(edge_at(start - vec2<i32>(0, 1)).r, edge_at(start).r);
+        let height_2 = crossing_height(edge_at(end - vec2<i32>(0, 1)).r, edge_at(end).r);
+        let area = coverage(left, right, height_1, height_2);
+        weights.r = area.y;
+        weights.g = area.x;
+    }
+
+    if (edges.r > 0.5) {
+        var top = 0;
+        for (var i = 1; i <= MAX_SEARCH; i = i + 1) {
+            if (edge_at(c - vec2<i32>(0, i)).r < 0.5) {
+                break;
+            }
+            top = i;
+        }
+        var bottom = 0;
+        for (var i = 1; i <= MAX_SEARCH; i = i + 1) {
+            if (edge_at(c + vec2<i32>(0, i)).r < 0.5) {
+                break;
+            }
+            bottom = i;
+        }
+        // crossing edges are the top edges at both ends, in the column on the left or this one
+        let start = vec2<i32>(c.x, c.y - top);
+        let end = vec2<i32>(c.x, c.y + bottom + 1);
+        let height_1 = crossing_height(edge_at(start - vec2<i32>(1, 0)).g, edge_at(start).g);
+        let height_2 = crossing_height(edge_at(end - vec2<i32>(1, 0)).g, edge_at(end).g);
+        let area = coverage(top, bottom, height_1, height_2);
+        weights.b = area.y;
+        weights.a = area.x;
+    }
+
+    return weights;
+}
+
+fn load_weights(coords: vec2<i32>) -> vec4<f32> {
+    let size = textureDimensions(t_aux);
+    if (any(coords >= size)) {
+        return vec4<f32>(0.0);
+    }
+    return textureLoad(t_aux, coords, 0);
+}
+
+@fragment
+fn fs_blend(in: VertexOutput) -> @location(0) vec4<f32>{
+    let c = vec2<i32>(in.clip_position.xy);
+    let center = load_src(c);
+    let weights = load_weights(c);
+    let top = weights.r;
+    let left = weights.b;
+    let bottom = load_weights(c + vec2<i32>(0, 1)).g;
+    let right = load_weights(c + vec2<i32>(1, 0)).a;
+    let sum = top + left + bottom + right;
+    if (sum < 0.00001) {
+        return center;
+    }
+
+    let total = max(sum, 1.0);
+    let color = center.rgb * (total - sum)
+        + load_src(c - vec2<i32>(0, 1)).rgb * top
+        + load_src(c - vec2<i32>(1, 0)).rgb * left
+        + load_src(c + vec2<i32>(0, 1)).rgb * bottom
+        + load_src(c + vec2<i32>(1, 0)).rgb * right;
+    return vec4<f32>(color / total, center.a);
+}
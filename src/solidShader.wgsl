@@ -2,18 +2,28 @@
 struct VertexInput{
     @location(0) vertex_position: vec3<f32>,
     @location(1) tex_coord: vec2<f32>,
+    @location(2) normal: vec3<f32>,
 }
 
 struct InstanceInput{
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
+    @location(6) model_matrix_0: vec4<f32>,
+    @location(7) model_matrix_1: vec4<f32>,
+    @location(8) model_matrix_2: vec4<f32>,
+    @location(9) model_matrix_3: vec4<f32>,
+    @location(10) normal_matrix_0: vec3<f32>,
+    @location(11) normal_matrix_1: vec3<f32>,
+    @location(12) normal_matrix_2: vec3<f32>,
+    @location(13) tint: vec4<f32>,
+    // x: material index, y: first joint of the skin in the palette
+    @location(14) indices: vec2<u32>,
+    @location(15) custom: vec4<f32>,
 }
 
 struct VertexOutput{
     @builtin(position) clip_position: vec4<f32>,
     @location(0) shadowPos: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) tint: vec4<f32>,
 }
 
 @group(0) @binding(0)
@@ -35,6 +45,13 @@ fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput{
 
     output.clip_position = camera * model_matrix * pos;
     output.shadowPos = vec3<f32>(pos_from_light.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5), pos_from_light.z);
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    output.world_normal = normalize(normal_matrix * model.normal);
+    output.tint = instance.tint;
     return output;
 }
 
@@ -42,6 +59,13 @@ fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput{
 var t_depth: texture_depth_2d;
 @group(1) @binding(1)
 var s_depth: sampler_comparison;
+@group(1) @binding(2)
+var t_ambient_occlusion: texture_2d<f32>;
+
+@group(2) @binding(0)
+var t_irradiance: texture_cube<f32>;
+@group(2) @binding(2)
+var s_environment: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>{
@@ -51,7 +75,9 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>{
         in.shadowPos.xy,
         in.shadowPos.z - 0.005
     );
-    let light_factor = min(0.3 + shadow * 1.0, 1.0);
-    let color = light_factor * vec3<f32>(0.8,0.8,0.8);
-    return vec4<f32>(color, 1.0);
+    let screen_occlusion = textureLoad(t_ambient_occlusion, vec2<i32>(in.clip_position.xy), 0).r;
+    let ambient = textureSample(t_irradiance, s_environment, normalize(in.world_normal)).rgb * screen_occlusion;
+    let light_factor = min(ambient + shadow * 1.0, vec3<f32>(1.0));
+    let color = light_factor * vec3<f32>(0.8,0.8,0.8) * in.tint.rgb;
+    return vec4<f32>(color, in.tint.a);
 }
\ No newline at end of file
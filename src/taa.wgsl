@@ -0,0 +1,95 @@
+// Temporal anti-aliasing. The projection is jittered by a different sub-pixel offset every
+// frame, each pixel is reprojected into the previous frame through the depth buffer and the
+// new sample is blended into the accumulated history. The history is clamped to the colours
+// around the pixel so disoccluded and moving surfaces don't leave trails.
+struct Settings{
+    // jittered, this frame
+    inv_view_proj: mat4x4<f32>,
+    // without jitter, last frame
+    previous_view_proj: mat4x4<f32>,
+    // weight of the new frame
+    blend: f32,
+    // set when the history holds nothing usable
+    reset: u32,
+}
+
+struct VertexOutput{
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+struct FragmentOutput{
+    @location(0) color: vec4<f32>,
+    @location(1) history: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> settings: Settings;
+@group(0) @binding(1)
+var t_src: texture_2d<f32>;
+@group(0) @binding(2)
+var t_history: texture_2d<f32>;
+@group(0) @binding(3)
+var t_depth: texture_depth_2d;
+@group(0) @binding(4)
+var s_history: sampler;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput{
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn load_src(coords: vec2<i32>) -> vec3<f32> {
+    let size = textureDimensions(t_src);
+    return textureLoad(t_src, clamp(coords, vec2<i32>(0), size - 1), 0).rgb;
+}
+
+fn resolve(color: vec4<f32>) -> FragmentOutput {
+    var out: FragmentOutput;
+    out.color = color;
+    out.history = color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput{
+    let c = vec2<i32>(in.clip_position.xy);
+    let current = textureLoad(t_src, c, 0);
+    let size = vec2<f32>(textureDimensions(t_src));
+    let depth = textureLoad(t_depth, c, 0);
+    let uv = (vec2<f32>(c) + 0.5) / size;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = settings.inv_view_proj * ndc;
+    let previous_clip = settings.previous_view_proj * vec4<f32>(world.xyz / world.w, 1.0);
+    let previous_ndc = previous_clip.xy / previous_clip.w;
+    let previous_uv = vec2<f32>(previous_ndc.x * 0.5 + 0.5, 0.5 - previous_ndc.y * 0.5);
+    var history = textureSampleLevel(t_history, s_history, previous_uv, 0.0).rgb;
+
+    if (settings.reset == 1u || any(previous_uv < vec2<f32>(0.0)) || any(previous_uv > vec2<f32>(1.0))) {
+        return resolve(current);
+    }
+
+    var low = current.rgb;
+    var high = current.rgb;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let neighbour = load_src(c + vec2<i32>(x, y));
+            low = min(low, neighbour);
+            high = max(high, neighbour);
+        }
+    }
+    history = clamp(history, low, high);
+
+    // weighting by inverse luminance keeps single bright samples from flickering
+    let current_weight = settings.blend / (1.0 + luminance(current.rgb));
+    let history_weight = (1.0 - settings.blend) / (1.0 + luminance(history));
+    let color = (current.rgb * current_weight + history * history_weight)
+        / (current_weight + history_weight);
+    return resolve(vec4<f32>(color, current.a));
+}